@@ -0,0 +1,58 @@
+/// A Bluetooth adapter as reported by the system.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdapterInfo {
+    pub name: String,
+    pub address: String,
+    pub powered: bool,
+}
+
+/// Lists the adapters BlueZ knows about. The first one is the adapter the
+/// peripheral backend uses.
+#[cfg(target_os = "linux")]
+pub async fn list_adapters() -> Result<Vec<AdapterInfo>, String> {
+    let session = bluer::Session::new().await.map_err(|err| err.to_string())?;
+    let mut adapters = Vec::new();
+    for name in session
+        .adapter_names()
+        .await
+        .map_err(|err| err.to_string())?
+    {
+        let adapter = session.adapter(&name).map_err(|err| err.to_string())?;
+        adapters.push(AdapterInfo {
+            address: adapter
+                .address()
+                .await
+                .map(|address| address.to_string())
+                .map_err(|err| err.to_string())?,
+            powered: adapter.is_powered().await.map_err(|err| err.to_string())?,
+            name,
+        });
+    }
+    Ok(adapters)
+}
+
+/// CoreBluetooth and WinRT only expose the system's default adapter.
+#[cfg(not(target_os = "linux"))]
+pub async fn list_adapters() -> Result<Vec<AdapterInfo>, String> {
+    Err("adapter enumeration is only available with BlueZ".to_string())
+}
+
+/// Checks that `name` is the adapter the backend will use, since
+/// ble-peripheral-rust always opens the default one.
+pub async fn check_selected(name: &str) -> Result<(), String> {
+    let adapters = list_adapters().await?;
+    match adapters.first() {
+        Some(default) if default.name == name => Ok(()),
+        Some(default) => {
+            if adapters.iter().any(|adapter| adapter.name == name) {
+                Err(format!(
+                    "adapter {} exists but the backend can only use the default adapter {}",
+                    name, default.name
+                ))
+            } else {
+                Err(format!("no adapter named {}", name))
+            }
+        }
+        None => Err("no Bluetooth adapters found".to_string()),
+    }
+}
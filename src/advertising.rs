@@ -0,0 +1,516 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::time::Duration;
+
+use uuid::Uuid;
+
+use ble_peripheral_rust::uuid::ShortUuid;
+
+use crate::config::BLUETOOTH_BASE_UUID;
+use crate::error::Error;
+
+/// Maximum payload of a legacy advertising or scan response packet.
+pub const MAX_ADVERTISEMENT_BYTES: usize = 31;
+
+/// Longest local name that fits in one packet next to its AD header. The
+/// backend moves a name that does not fit the advertisement into the scan
+/// response, so this is the practical limit.
+pub const MAX_LOCAL_NAME_BYTES: usize = MAX_ADVERTISEMENT_BYTES - 2;
+
+/// Shortest advertising interval the Core spec allows.
+pub const MIN_ADVERTISING_INTERVAL: Duration = Duration::from_millis(20);
+
+/// Longest advertising interval the Core spec allows.
+pub const MAX_ADVERTISING_INTERVAL: Duration = Duration::from_millis(10_240);
+
+/// Apple's company identifier, under which iBeacon frames are sent.
+pub const APPLE_COMPANY_ID: u16 = 0x004C;
+
+/// iBeacon frame type and the length of the payload that follows it.
+const IBEACON_TYPE: u8 = 0x02;
+const IBEACON_LENGTH: u8 = 0x15;
+
+/// 16-bit service UUID Eddystone frames are sent under.
+pub const EDDYSTONE_SERVICE_UUID: u16 = 0xFEAA;
+
+/// Calibrated TX power at 0 m put in Eddystone-URL frames, in dBm.
+pub const EDDYSTONE_TX_POWER: i8 = -20;
+
+const EDDYSTONE_URL_FRAME: u8 = 0x10;
+
+/// URL schemes in the order of their Eddystone prefix codes.
+const EDDYSTONE_SCHEMES: [&str; 4] = ["http://www.", "https://www.", "http://", "https://"];
+
+/// Text compressed to one byte each, in the order of their codes.
+const EDDYSTONE_EXPANSIONS: [&str; 14] = [
+    ".com/", ".org/", ".edu/", ".net/", ".info/", ".biz/", ".gov/", ".com", ".org", ".edu", ".net",
+    ".info", ".biz", ".gov",
+];
+
+/// Length of the Flags AD structure that every connectable advertisement carries.
+const FLAGS_AD_BYTES: usize = 3;
+
+/// Length of the Appearance AD structure.
+const APPEARANCE_AD_BYTES: usize = 4;
+
+/// AD Flags bits the Core spec reserves for future use.
+const RESERVED_FLAGS: u8 = 0xE0;
+
+/// Checks AD Flags only use the five bits the Core spec defines.
+pub fn validate_flags(flags: u8) -> Result<(), String> {
+    if flags & RESERVED_FLAGS != 0 {
+        return Err(format!(
+            "flags 0x{:02X} set reserved bits (only 0x1F are defined)",
+            flags
+        ));
+    }
+    Ok(())
+}
+
+/// Checks a local name is not empty and fits in `MAX_LOCAL_NAME_BYTES`.
+pub fn validate_local_name(name: &str) -> Result<(), String> {
+    if name.trim().is_empty() {
+        return Err("the name must not be empty".to_string());
+    }
+    if name.len() > MAX_LOCAL_NAME_BYTES {
+        return Err(format!(
+            "'{}' is {} bytes, more than the {} that fit in a packet",
+            name,
+            name.len(),
+            MAX_LOCAL_NAME_BYTES
+        ));
+    }
+    Ok(())
+}
+
+/// The two legacy packets a peripheral can fill.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Packet {
+    Advertisement,
+    ScanResponse,
+}
+
+impl fmt::Display for Packet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Packet::Advertisement => write!(f, "advertisement"),
+            Packet::ScanResponse => write!(f, "scan response"),
+        }
+    }
+}
+
+/// Identifies an advertisement started with `Device::add_advertisement`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct AdvertisementHandle(pub(crate) u32);
+
+impl fmt::Display for AdvertisementHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "#{}", self.0)
+    }
+}
+
+/// Everything that goes into the primary advertisement.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AdvertisementData {
+    pub local_name: String,
+    pub service_uuids: Vec<Uuid>,
+    /// Company identifier and payload of the manufacturer-specific AD field.
+    pub manufacturer_data: Option<(u16, Vec<u8>)>,
+    pub service_data: HashMap<Uuid, Vec<u8>>,
+    /// GAP Appearance, e.g. `0x0340` for a generic thermometer, so scanners
+    /// can show a fitting icon.
+    pub appearance: Option<u16>,
+    /// AD Flags to send instead of the ones the platform picks.
+    pub flags: Option<u8>,
+    /// Fields sent only when a central actively scans the peripheral.
+    pub scan_response: ScanResponseData,
+    pub parameters: AdvertisingParameters,
+}
+
+/// How often and how loudly to advertise. `None` leaves the platform default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AdvertisingParameters {
+    pub interval_min: Option<Duration>,
+    pub interval_max: Option<Duration>,
+    /// Desired transmit power in dBm.
+    pub tx_power_level: Option<i8>,
+    /// `Some(false)` advertises as a non-connectable broadcaster, e.g. for
+    /// beacons.
+    pub connectable: Option<bool>,
+}
+
+/// One of the advertising interval bounds, as blamed by
+/// `AdvertisingParameters::validate`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interval {
+    Min,
+    Max,
+}
+
+impl AdvertisingParameters {
+    pub fn is_default(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn is_broadcaster(&self) -> bool {
+        self.connectable == Some(false)
+    }
+
+    /// Checks the intervals against the spec's 20 ms to 10.24 s range and
+    /// that the minimum does not exceed the maximum, naming the interval at
+    /// fault. A minimum above the maximum is blamed on the minimum.
+    pub fn validate(&self) -> Result<(), (Interval, String)> {
+        let intervals = [
+            (Interval::Min, self.interval_min),
+            (Interval::Max, self.interval_max),
+        ];
+        for (field, interval) in intervals {
+            let Some(interval) = interval else {
+                continue;
+            };
+            if !(MIN_ADVERTISING_INTERVAL..=MAX_ADVERTISING_INTERVAL).contains(&interval) {
+                return Err((
+                    field,
+                    format!(
+                        "advertising interval {} ms is outside 20..=10240 ms",
+                        interval.as_millis()
+                    ),
+                ));
+            }
+        }
+        if let (Some(min), Some(max)) = (self.interval_min, self.interval_max) {
+            if min > max {
+                return Err((
+                    Interval::Min,
+                    format!(
+                        "interval_min ({} ms) is greater than interval_max ({} ms)",
+                        min.as_millis(),
+                        max.as_millis()
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// The fields of an iBeacon frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IBeacon {
+    pub uuid: Uuid,
+    pub major: u16,
+    pub minor: u16,
+    /// RSSI in dBm measured 1 m from the beacon, for ranging.
+    pub measured_power: i8,
+}
+
+impl IBeacon {
+    /// The manufacturer data after Apple's company ID: type, length, then
+    /// the UUID, major and minor big-endian and the measured power.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut data = vec![IBEACON_TYPE, IBEACON_LENGTH];
+        data.extend_from_slice(self.uuid.as_bytes());
+        data.extend_from_slice(&self.major.to_be_bytes());
+        data.extend_from_slice(&self.minor.to_be_bytes());
+        data.push(self.measured_power as u8);
+        data
+    }
+
+    /// Parses manufacturer data produced by `encode`, or `None` if it is not
+    /// an iBeacon frame.
+    pub fn decode(company_id: u16, data: &[u8]) -> Option<Self> {
+        let [IBEACON_TYPE, IBEACON_LENGTH, rest @ ..] = data else {
+            return None;
+        };
+        if company_id != APPLE_COMPANY_ID || rest.len() != IBEACON_LENGTH as usize {
+            return None;
+        }
+        Some(Self {
+            uuid: Uuid::from_slice(&rest[..16]).ok()?,
+            major: u16::from_be_bytes([rest[16], rest[17]]),
+            minor: u16::from_be_bytes([rest[18], rest[19]]),
+            measured_power: rest[20] as i8,
+        })
+    }
+}
+
+/// Compresses a URL into an Eddystone-URL scheme code and body.
+fn encode_eddystone_url(url: &str) -> Result<Vec<u8>, Error> {
+    let (scheme, rest) = EDDYSTONE_SCHEMES
+        .iter()
+        .enumerate()
+        .find_map(|(code, scheme)| Some((code as u8, url.strip_prefix(scheme)?)))
+        .ok_or_else(|| {
+            Error::InvalidAdvertisement(format!(
+                "'{}' does not start with http:// or https://",
+                url
+            ))
+        })?;
+    let mut encoded = vec![scheme];
+    let mut rest = rest;
+    while !rest.is_empty() {
+        // Longest first, so `.com/` wins over `.com`.
+        if let Some((code, tail)) = EDDYSTONE_EXPANSIONS
+            .iter()
+            .enumerate()
+            .find_map(|(code, expansion)| Some((code as u8, rest.strip_prefix(expansion)?)))
+        {
+            encoded.push(code);
+            rest = tail;
+            continue;
+        }
+        let byte = rest.as_bytes()[0];
+        if !byte.is_ascii_graphic() {
+            return Err(Error::InvalidAdvertisement(format!(
+                "'{}' contains a character Eddystone-URL cannot encode",
+                url
+            )));
+        }
+        encoded.push(byte);
+        rest = &rest[1..];
+    }
+    Ok(encoded)
+}
+
+/// Fields moved out of the advertisement into the scan response packet.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScanResponseData {
+    pub local_name: Option<String>,
+    pub service_uuids: Vec<Uuid>,
+    pub manufacturer_data: Option<(u16, Vec<u8>)>,
+    pub service_data: HashMap<Uuid, Vec<u8>>,
+}
+
+/// Number of bytes a UUID takes on air: 2 or 4 when it is derived from the
+/// Bluetooth Base UUID, 16 otherwise.
+pub fn uuid_wire_len(uuid: &Uuid) -> usize {
+    let value = uuid.as_u128();
+    if value & 0xFFFF_FFFF_FFFF_FFFF_FFFF_FFFF == BLUETOOTH_BASE_UUID {
+        if value >> 96 <= 0xFFFF {
+            2
+        } else {
+            4
+        }
+    } else {
+        16
+    }
+}
+
+/// Size of a set of AD structures, excluding the Flags field.
+fn fields_len(
+    local_name: &str,
+    service_uuids: &[Uuid],
+    manufacturer_data: &Option<(u16, Vec<u8>)>,
+    service_data: &HashMap<Uuid, Vec<u8>>,
+) -> usize {
+    let mut size = 0;
+    if !local_name.is_empty() {
+        size += 2 + local_name.len();
+    }
+    for width in [2, 4, 16] {
+        let count = service_uuids
+            .iter()
+            .filter(|uuid| uuid_wire_len(uuid) == width)
+            .count();
+        if count > 0 {
+            size += 2 + width * count;
+        }
+    }
+    if let Some((_, data)) = manufacturer_data {
+        size += 2 + 2 + data.len();
+    }
+    for (uuid, data) in service_data {
+        size += 2 + uuid_wire_len(uuid) + data.len();
+    }
+    size
+}
+
+fn check_len(packet: Packet, size: usize) -> Result<(), Error> {
+    if size > MAX_ADVERTISEMENT_BYTES {
+        return Err(Error::AdvertisementTooLarge {
+            packet,
+            size,
+            limit: MAX_ADVERTISEMENT_BYTES,
+        });
+    }
+    Ok(())
+}
+
+impl ScanResponseData {
+    pub fn is_empty(&self) -> bool {
+        self.encoded_len() == 0
+    }
+
+    pub fn encoded_len(&self) -> usize {
+        fields_len(
+            self.local_name.as_deref().unwrap_or_default(),
+            &self.service_uuids,
+            &self.manufacturer_data,
+            &self.service_data,
+        )
+    }
+}
+
+impl AdvertisementData {
+    pub fn new(local_name: &str, service_uuids: &[Uuid]) -> Self {
+        Self {
+            local_name: local_name.to_string(),
+            service_uuids: service_uuids.to_vec(),
+            ..Default::default()
+        }
+    }
+
+    /// A non-connectable iBeacon advertisement, with nothing but the
+    /// manufacturer data so it fits the 31 byte packet.
+    pub fn ibeacon(uuid: Uuid, major: u16, minor: u16, measured_power: i8) -> Self {
+        let beacon = IBeacon {
+            uuid,
+            major,
+            minor,
+            measured_power,
+        };
+        Self {
+            manufacturer_data: Some((APPLE_COMPANY_ID, beacon.encode())),
+            parameters: AdvertisingParameters {
+                connectable: Some(false),
+                ..AdvertisingParameters::default()
+            },
+            ..Self::default()
+        }
+    }
+
+    /// A non-connectable Eddystone-URL advertisement for `url`, with the
+    /// scheme and common domain endings compressed to one byte each.
+    ///
+    /// URLs that still do not fit the frame are rejected with
+    /// `Error::AdvertisementTooLarge`.
+    pub fn eddystone_url(url: &str) -> Result<Self, Error> {
+        let mut frame = vec![EDDYSTONE_URL_FRAME, EDDYSTONE_TX_POWER as u8];
+        frame.extend(encode_eddystone_url(url)?);
+        let service = Uuid::from_short(EDDYSTONE_SERVICE_UUID);
+        let data = Self {
+            service_uuids: vec![service],
+            service_data: HashMap::from([(service, frame)]),
+            parameters: AdvertisingParameters {
+                connectable: Some(false),
+                ..AdvertisingParameters::default()
+            },
+            ..Self::default()
+        };
+        data.validate()?;
+        Ok(data)
+    }
+
+    /// The iBeacon frame in the manufacturer data, if there is one.
+    pub fn as_ibeacon(&self) -> Option<IBeacon> {
+        let (company_id, data) = self.manufacturer_data.as_ref()?;
+        IBeacon::decode(*company_id, data)
+    }
+
+    /// Size of the advertising payload once encoded as AD structures.
+    pub fn encoded_len(&self) -> usize {
+        let appearance = self.appearance.map_or(0, |_| APPEARANCE_AD_BYTES);
+        FLAGS_AD_BYTES
+            + appearance
+            + fields_len(
+                &self.local_name,
+                &self.service_uuids,
+                &self.manufacturer_data,
+                &self.service_data,
+            )
+    }
+
+    /// Checks the flags and both packets against the legacy 31 byte limit.
+    pub fn validate(&self) -> Result<(), Error> {
+        if let Some(flags) = self.flags {
+            validate_flags(flags).map_err(Error::InvalidAdvertisement)?;
+        }
+        check_len(Packet::Advertisement, self.encoded_len())?;
+        check_len(Packet::ScanResponse, self.scan_response.encoded_len())
+    }
+
+    pub fn has_manufacturer_or_service_data(&self) -> bool {
+        self.manufacturer_data.is_some()
+            || !self.service_data.is_empty()
+            || self.scan_response.manufacturer_data.is_some()
+            || !self.scan_response.service_data.is_empty()
+    }
+
+    pub fn has_appearance_or_flags(&self) -> bool {
+        self.appearance.is_some() || self.flags.is_some()
+    }
+
+    /// Name handed to the backend, wherever it was placed.
+    pub fn advertised_name(&self) -> &str {
+        match &self.scan_response.local_name {
+            Some(name) if self.local_name.is_empty() => name,
+            _ => &self.local_name,
+        }
+    }
+
+    /// Service UUIDs from both packets, in order and without duplicates.
+    pub fn all_service_uuids(&self) -> Vec<Uuid> {
+        let mut uuids = self.service_uuids.clone();
+        for uuid in &self.scan_response.service_uuids {
+            if !uuids.contains(uuid) {
+                uuids.push(*uuid);
+            }
+        }
+        uuids
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn beacon() -> IBeacon {
+        IBeacon {
+            uuid: Uuid::from_u128(0xE2C5_6DB5_DFFB_48D2_B060_D0F5_A710_96E0),
+            major: 0x0102,
+            minor: 0xFFFE,
+            measured_power: -59,
+        }
+    }
+
+    #[test]
+    fn ibeacon_frame_has_the_apple_layout() {
+        let data = AdvertisementData::ibeacon(
+            beacon().uuid,
+            beacon().major,
+            beacon().minor,
+            beacon().measured_power,
+        );
+        let (company_id, frame) = data.manufacturer_data.clone().unwrap();
+        assert_eq!(company_id, 0x004C);
+        assert_eq!(frame.len(), 23);
+        assert_eq!(frame[..2], [0x02, 0x15]);
+        assert_eq!(frame[2..18], *beacon().uuid.as_bytes());
+        assert_eq!(frame[18..], [0x01, 0x02, 0xFF, 0xFE, 0xC5]);
+        assert!(data.parameters.is_broadcaster());
+        assert!(data.validate().is_ok());
+    }
+
+    #[test]
+    fn ibeacon_round_trips() {
+        let (company_id, frame) = AdvertisementData::ibeacon(
+            beacon().uuid,
+            beacon().major,
+            beacon().minor,
+            beacon().measured_power,
+        )
+        .manufacturer_data
+        .unwrap();
+        assert_eq!(IBeacon::decode(company_id, &frame), Some(beacon()));
+    }
+
+    #[test]
+    fn decode_rejects_other_frames() {
+        let frame = beacon().encode();
+        assert_eq!(IBeacon::decode(0x0059, &frame), None);
+        assert_eq!(IBeacon::decode(APPLE_COMPANY_ID, &frame[..22]), None);
+        let mut other_type = frame.clone();
+        other_type[0] = 0x03;
+        assert_eq!(IBeacon::decode(APPLE_COMPANY_ID, &other_type), None);
+    }
+}
@@ -0,0 +1,100 @@
+use std::collections::HashMap;
+
+use crate::profile::switch::SwitchState;
+
+/// Console commands an alias may not shadow.
+pub const RESERVED_COMMANDS: &[&str] = &[
+    "on",
+    "off",
+    "reload",
+    "save-config",
+    "set",
+    "brightness",
+    "timeout",
+    "history",
+    "write",
+    "stats",
+    "snapshot",
+    "pair",
+    "disconnect",
+    "help",
+    "status",
+    "quit",
+    "send",
+    "adv",
+    "subs",
+    "hex",
+    "char",
+    "name",
+    "gatt",
+];
+
+/// User-defined words that stand for a switch state, e.g. `1` or `an` for on.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Aliases {
+    states: HashMap<String, SwitchState>,
+}
+
+fn normalize(word: &str) -> String {
+    word.trim().to_lowercase()
+}
+
+impl Aliases {
+    /// Builds the table from `alias -> state` pairs, rejecting reserved words,
+    /// unknown states and aliases that collide once case is ignored.
+    pub fn from_pairs<'a>(
+        pairs: impl IntoIterator<Item = (&'a str, &'a str)>,
+    ) -> Result<Self, String> {
+        let mut states = HashMap::new();
+        for (alias, target) in pairs {
+            let key = normalize(alias);
+            if key.is_empty() {
+                return Err("alias must not be empty".to_string());
+            }
+            if RESERVED_COMMANDS.contains(&key.as_str()) {
+                return Err(format!("'{}' is a built-in command", alias));
+            }
+            let state = SwitchState::parse(target).ok_or_else(|| {
+                format!(
+                    "alias '{}' must map to 'on' or 'off', not '{}'",
+                    alias, target
+                )
+            })?;
+            if let Some(existing) = states.insert(key, state) {
+                if existing != state {
+                    return Err(format!("alias '{}' is defined more than once", alias));
+                }
+            }
+        }
+        Ok(Self { states })
+    }
+
+    /// Adds `other`'s aliases, failing if one maps to a different state here.
+    pub fn merge(&mut self, other: Aliases) -> Result<(), String> {
+        for (alias, state) in other.states {
+            match self.states.get(&alias) {
+                Some(existing) if *existing != state => {
+                    return Err(format!("alias '{}' is defined more than once", alias));
+                }
+                _ => {
+                    self.states.insert(alias, state);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub fn resolve(&self, word: &str) -> Option<SwitchState> {
+        self.states.get(&normalize(word)).copied()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.states.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, SwitchState)> {
+        self.states
+            .iter()
+            .map(|(alias, state)| (alias.as_str(), *state))
+    }
+}
@@ -0,0 +1,77 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::{watch, Mutex};
+
+use crate::device::Device;
+use crate::profile::switch::{self, SwitchState};
+use crate::state::Source;
+use crate::switches::SwitchBank;
+
+/// Turns switches back off a fixed time after they were turned on, like a
+/// staircase light. A timeout of zero disables it.
+#[derive(Debug, Clone)]
+pub struct AutoOff {
+    timeout: Arc<watch::Sender<u64>>,
+}
+
+impl AutoOff {
+    pub fn new(timeout_secs: u64) -> Self {
+        Self {
+            timeout: Arc::new(watch::channel(timeout_secs).0),
+        }
+    }
+
+    pub fn timeout_secs(&self) -> u64 {
+        *self.timeout.borrow()
+    }
+
+    /// Changes the timeout. Switches that are on restart their countdown
+    /// with the new value.
+    pub fn set_timeout_secs(&self, secs: u64) {
+        self.timeout.send_replace(secs);
+    }
+
+    /// Starts one timer task per switch.
+    ///
+    /// Each task re-reads the switch after every change, so an off/on
+    /// sequence arms a fresh countdown instead of leaving the old one to
+    /// fire early, and turning the switch off cancels it.
+    pub fn spawn(&self, switches: Arc<SwitchBank>, device: Arc<Mutex<Device>>) {
+        for switch in switches.iter() {
+            let characteristic = switch.characteristic;
+            let mut changes = switch.state.read().unwrap().subscribe();
+            let mut timeout = self.timeout.subscribe();
+            let switches = switches.clone();
+            let device = device.clone();
+            tokio::spawn(async move {
+                loop {
+                    let is_on = changes.borrow_and_update().is_on();
+                    let secs = *timeout.borrow_and_update();
+                    if is_on && secs > 0 {
+                        tokio::select! {
+                            _ = tokio::time::sleep(Duration::from_secs(secs)) => {
+                                let Some(switch) = switches.by_characteristic(characteristic) else {
+                                    break;
+                                };
+                                log::info!(
+                                    "Auto-off: turning '{}' off after {}s",
+                                    switch.name,
+                                    secs
+                                );
+                                switch::set(switch, SwitchState::Off, Source::Timer, &device).await;
+                            }
+                            changed = changes.changed() => if changed.is_err() { break },
+                            changed = timeout.changed() => if changed.is_err() { break },
+                        }
+                    } else {
+                        tokio::select! {
+                            changed = changes.changed() => if changed.is_err() { break },
+                            changed = timeout.changed() => if changed.is_err() { break },
+                        }
+                    }
+                }
+            });
+        }
+    }
+}
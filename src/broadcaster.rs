@@ -0,0 +1,79 @@
+use crate::advertising::AdvertisementData;
+use crate::error::Error;
+
+/// AD type of the Flags field.
+#[cfg(target_os = "linux")]
+const FLAGS_AD_TYPE: u8 = 0x01;
+
+/// An advertisement registered with BlueZ; dropping it stops advertising.
+#[cfg_attr(not(target_os = "linux"), allow(dead_code))]
+pub(crate) struct Broadcast {
+    #[cfg(target_os = "linux")]
+    _handle: Option<bluer::adv::AdvertisementHandle>,
+}
+
+impl Broadcast {
+    /// A registration with nothing behind it, for the mock backend, which
+    /// has no BlueZ to register sets with.
+    #[cfg(any(test, feature = "mock"))]
+    pub(crate) fn detached() -> Self {
+        Self {
+            #[cfg(target_os = "linux")]
+            _handle: None,
+        }
+    }
+}
+
+/// Registers `data` with BlueZ as an advertising set of its own, next to the
+/// backend's GATT application: a `broadcast` advertisement for broadcaster
+/// data, a connectable `peripheral` one otherwise.
+#[cfg(target_os = "linux")]
+pub(crate) async fn start(data: &AdvertisementData) -> Result<Broadcast, Error> {
+    let backend = |err: bluer::Error| Error::Backend(err.to_string());
+    let session = bluer::Session::new().await.map_err(backend)?;
+    let adapter = session.default_adapter().await.map_err(backend)?;
+    let name = data.advertised_name();
+    let advertisement = bluer::adv::Advertisement {
+        advertisement_type: if data.parameters.is_broadcaster() {
+            bluer::adv::Type::Broadcast
+        } else {
+            bluer::adv::Type::Peripheral
+        },
+        service_uuids: data.all_service_uuids().into_iter().collect(),
+        local_name: (!name.is_empty()).then(|| name.to_string()),
+        manufacturer_data: [
+            &data.manufacturer_data,
+            &data.scan_response.manufacturer_data,
+        ]
+        .into_iter()
+        .flatten()
+        .cloned()
+        .collect(),
+        service_data: data
+            .service_data
+            .iter()
+            .chain(&data.scan_response.service_data)
+            .map(|(uuid, value)| (*uuid, value.clone()))
+            .collect(),
+        appearance: data.appearance,
+        // BlueZ sets the Flags field itself unless it is given as raw data,
+        // which needs `bluetoothd --experimental`.
+        advertisting_data: data
+            .flags
+            .map(|flags| (FLAGS_AD_TYPE, vec![flags]))
+            .into_iter()
+            .collect(),
+        ..Default::default()
+    };
+    let handle = adapter.advertise(advertisement).await.map_err(backend)?;
+    Ok(Broadcast {
+        _handle: Some(handle),
+    })
+}
+
+/// CoreBluetooth only advertises connectable peripherals, and the backend
+/// does not expose WinRT's publisher, so neither can add an advertising set.
+#[cfg(not(target_os = "linux"))]
+pub(crate) async fn start(_data: &AdvertisementData) -> Result<Broadcast, Error> {
+    Err(Error::UnsupportedOperation("non-connectable advertising"))
+}
@@ -0,0 +1,246 @@
+use std::fmt;
+use uuid::Uuid;
+
+use ble_peripheral_rust::{
+    gatt::{
+        characteristic::Characteristic,
+        descriptor::Descriptor,
+        properties::{AttributePermission, CharacteristicProperty},
+        service::Service,
+    },
+    uuid::ShortUuid,
+};
+
+/// Short UUID of the Client Characteristic Configuration Descriptor.
+pub const CCCD_UUID: u16 = 0x2902;
+
+/// A CCCD with notifications and indications off, as centrals expect to
+/// find it before subscribing.
+pub fn cccd() -> Descriptor {
+    Descriptor {
+        uuid: Uuid::from_short(CCCD_UUID),
+        permissions: vec![
+            AttributePermission::Readable,
+            AttributePermission::Writeable,
+        ],
+        value: Some(vec![0, 0]),
+        ..Default::default()
+    }
+}
+
+/// Whether a characteristic with these properties needs a CCCD.
+pub fn needs_cccd(properties: &[CharacteristicProperty]) -> bool {
+    properties.iter().any(|property| {
+        matches!(
+            property,
+            CharacteristicProperty::Notify
+                | CharacteristicProperty::Indicate
+                | CharacteristicProperty::NotifyEncryptionRequired
+                | CharacteristicProperty::IndicateEncryptionRequired
+        )
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuildError {
+    MissingUuid,
+    MissingCccd(Uuid),
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BuildError::MissingUuid => write!(f, "service has no UUID"),
+            BuildError::MissingCccd(uuid) => write!(
+                f,
+                "characteristic {} supports notify/indicate but has no CCCD (0x2902) descriptor",
+                uuid
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+/// Fluent alternative to filling in a `Service` literal by hand.
+#[derive(Debug, Clone)]
+pub struct ServiceBuilder {
+    uuid: Option<Uuid>,
+    primary: bool,
+    characteristics: Vec<Characteristic>,
+}
+
+impl Default for ServiceBuilder {
+    fn default() -> Self {
+        Self {
+            uuid: None,
+            primary: true,
+            characteristics: Vec::new(),
+        }
+    }
+}
+
+impl ServiceBuilder {
+    pub fn new(uuid: Uuid) -> Self {
+        Self::default().uuid(uuid)
+    }
+
+    pub fn uuid(mut self, uuid: Uuid) -> Self {
+        self.uuid = Some(uuid);
+        self
+    }
+
+    pub fn primary(mut self, primary: bool) -> Self {
+        self.primary = primary;
+        self
+    }
+
+    pub fn characteristic(mut self, characteristic: Characteristic) -> Self {
+        self.characteristics.push(characteristic);
+        self
+    }
+
+    pub fn build(self) -> Result<Service, BuildError> {
+        let uuid = self.uuid.ok_or(BuildError::MissingUuid)?;
+        Ok(Service {
+            uuid,
+            primary: self.primary,
+            characteristics: self.characteristics,
+        })
+    }
+}
+
+/// Fluent alternative to a `Characteristic` literal.
+///
+/// Permissions are inferred from the declared properties unless set
+/// explicitly, and `notify()`/`indicate()` add a CCCD descriptor.
+#[derive(Debug, Clone)]
+pub struct CharacteristicBuilder {
+    uuid: Uuid,
+    properties: Vec<CharacteristicProperty>,
+    permissions: Option<Vec<AttributePermission>>,
+    value: Option<Vec<u8>>,
+    descriptors: Vec<Descriptor>,
+    implicit_cccd: bool,
+    encrypted: bool,
+}
+
+impl CharacteristicBuilder {
+    pub fn new(uuid: Uuid) -> Self {
+        Self {
+            uuid,
+            properties: Vec::new(),
+            permissions: None,
+            value: None,
+            descriptors: Vec::new(),
+            implicit_cccd: true,
+            encrypted: false,
+        }
+    }
+
+    pub fn property(mut self, property: CharacteristicProperty) -> Self {
+        if !self.properties.contains(&property) {
+            self.properties.push(property);
+        }
+        self
+    }
+
+    pub fn readable(self) -> Self {
+        self.property(CharacteristicProperty::Read)
+    }
+
+    pub fn writable(self) -> Self {
+        self.property(CharacteristicProperty::Write)
+    }
+
+    /// Accepts Write Commands, which the central sends without waiting for
+    /// a response.
+    pub fn write_without_response(self) -> Self {
+        self.property(CharacteristicProperty::WriteWithoutResponse)
+    }
+
+    pub fn notify(self) -> Self {
+        self.property(CharacteristicProperty::Notify)
+    }
+
+    pub fn indicate(self) -> Self {
+        self.property(CharacteristicProperty::Indicate)
+    }
+
+    /// Overrides the permissions that would otherwise be inferred.
+    pub fn permissions(mut self, permissions: Vec<AttributePermission>) -> Self {
+        self.permissions = Some(permissions);
+        self
+    }
+
+    pub fn value(mut self, value: Vec<u8>) -> Self {
+        self.value = Some(value);
+        self
+    }
+
+    pub fn with_descriptor(mut self, descriptor: Descriptor) -> Self {
+        self.descriptors.push(descriptor);
+        self
+    }
+
+    /// Disables the CCCD that `notify()`/`indicate()` would otherwise add.
+    pub fn implicit_cccd(mut self, enabled: bool) -> Self {
+        self.implicit_cccd = enabled;
+        self
+    }
+
+    /// Infers the encryption-required permissions instead of the plain ones,
+    /// so the stack rejects reads and writes over an unencrypted link.
+    pub fn encrypted(mut self) -> Self {
+        self.encrypted = true;
+        self
+    }
+
+    fn inferred_permissions(&self) -> Vec<AttributePermission> {
+        let mut permissions = Vec::new();
+        if self.properties.contains(&CharacteristicProperty::Read) {
+            permissions.push(if self.encrypted {
+                AttributePermission::ReadEncryptionRequired
+            } else {
+                AttributePermission::Readable
+            });
+        }
+        if self.properties.contains(&CharacteristicProperty::Write)
+            || self
+                .properties
+                .contains(&CharacteristicProperty::WriteWithoutResponse)
+        {
+            permissions.push(if self.encrypted {
+                AttributePermission::WriteEncryptionRequired
+            } else {
+                AttributePermission::Writeable
+            });
+        }
+        permissions
+    }
+
+    pub fn build(mut self) -> Result<Characteristic, BuildError> {
+        let cccd_uuid = Uuid::from_short(CCCD_UUID);
+        let has_cccd = self.descriptors.iter().any(|d| d.uuid == cccd_uuid);
+
+        if needs_cccd(&self.properties) && !has_cccd {
+            if !self.implicit_cccd {
+                return Err(BuildError::MissingCccd(self.uuid));
+            }
+            self.descriptors.push(cccd());
+        }
+
+        let permissions = match self.permissions.take() {
+            Some(permissions) => permissions,
+            None => self.inferred_permissions(),
+        };
+
+        Ok(Characteristic {
+            uuid: self.uuid,
+            properties: self.properties,
+            permissions,
+            value: self.value,
+            descriptors: self.descriptors,
+        })
+    }
+}
@@ -0,0 +1,51 @@
+use std::fmt;
+
+use ble_peripheral_rust::gatt::peripheral_event::PeripheralRequest;
+
+use crate::error::Error;
+
+/// Identifies a connected central by the platform address or handle the
+/// backend reports as the request's client.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct CentralId(pub String);
+
+impl CentralId {
+    pub fn from_request(request: &PeripheralRequest) -> Self {
+        CentralId(request.client.clone())
+    }
+}
+
+impl fmt::Display for CentralId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Asks BlueZ to drop the connection to `central`, whose ID is its address.
+///
+/// The backend does not expose connections, so this goes to BlueZ directly.
+#[cfg(target_os = "linux")]
+pub(crate) async fn disconnect(central: &CentralId) -> Result<(), Error> {
+    let backend = |err: bluer::Error| Error::Backend(err.to_string());
+    let address: bluer::Address = central
+        .0
+        .parse()
+        .map_err(|_| Error::Backend(format!("{} is not a Bluetooth address", central)))?;
+    let session = bluer::Session::new().await.map_err(backend)?;
+    let adapter = session.default_adapter().await.map_err(backend)?;
+    adapter
+        .device(address)
+        .map_err(backend)?
+        .disconnect()
+        .await
+        .map_err(backend)
+}
+
+/// CoreBluetooth does not let a peripheral drop a central, and the backend
+/// does not hand out the WinRT `GattSession` that could.
+#[cfg(not(target_os = "linux"))]
+pub(crate) async fn disconnect(_central: &CentralId) -> Result<(), Error> {
+    Err(Error::UnsupportedOperation(
+        "peripheral-initiated disconnect",
+    ))
+}
@@ -0,0 +1,237 @@
+use std::path::PathBuf;
+use std::time::Duration;
+
+use clap::{Args, Parser, Subcommand};
+use uuid::Uuid;
+
+use crate::config::{parse_uuid, ConfigSource};
+use crate::event_queue::{OverflowPolicy, DEFAULT_EVENT_CHANNEL_CAPACITY};
+use crate::history::DEFAULT_HISTORY_SIZE;
+use crate::output::OutputFormat;
+use crate::profile::{switch::SwitchState, Profile};
+use crate::switches::DEFAULT_SWITCH_NAME;
+use crate::validate::DEFAULT_MAX_ATTRIBUTE_LENGTH;
+
+/// Simulated BLE peripheral.
+///
+/// Without a subcommand the `run` options are accepted directly.
+#[derive(Debug, Parser)]
+#[command(version, about, args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    #[command(flatten)]
+    pub run: RunArgs,
+}
+
+impl Cli {
+    pub fn into_command(self) -> Command {
+        self.command.unwrap_or(Command::Run(self.run))
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Register the services and advertise (the default).
+    Run(RunArgs),
+    /// Check a service file for problems without touching Bluetooth.
+    #[command(alias = "validate")]
+    ValidateConfig {
+        /// Service file to check.
+        path: PathBuf,
+
+        /// Longest characteristic or descriptor value accepted, in bytes.
+        #[arg(long, default_value_t = DEFAULT_MAX_ATTRIBUTE_LENGTH)]
+        max_attribute_length: usize,
+    },
+    /// Print the available Bluetooth adapters.
+    ListAdapters,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct RunArgs {
+    /// Name used when advertising [default: the config file's name, or RustBLE].
+    #[arg(long, env = "BLE_DEVICE_NAME")]
+    pub name: Option<String>,
+
+    /// Service UUID, either 16-bit (`180F`) or full 128-bit form.
+    #[arg(long = "service", default_value = "1234", value_parser = parse_uuid)]
+    pub service_uuid: Uuid,
+
+    /// Characteristic UUID, either 16-bit (`2A19`) or full 128-bit form.
+    #[arg(
+        long = "char",
+        env = "BLE_CHAR_UUID",
+        default_value = "2A3D",
+        value_parser = parse_uuid
+    )]
+    pub char_uuid: Uuid,
+
+    /// Number of peripheral events buffered before the backend has to wait.
+    #[arg(
+        long,
+        env = "BLE_EVENT_CHANNEL_CAPACITY",
+        default_value_t = DEFAULT_EVENT_CHANNEL_CAPACITY,
+        value_parser = parse_capacity
+    )]
+    pub event_channel_capacity: usize,
+
+    /// What happens to informational events when the event channel is full.
+    /// Read and write requests are never dropped.
+    #[arg(
+        long,
+        env = "BLE_EVENT_OVERFLOW",
+        value_enum,
+        default_value_t = OverflowPolicy::Block
+    )]
+    pub event_overflow: OverflowPolicy,
+
+    /// Longest value, in bytes, a central may write in several fragments
+    /// (a long write). Longer ones are rejected.
+    #[arg(long, default_value_t = DEFAULT_MAX_ATTRIBUTE_LENGTH)]
+    pub max_write_length: usize,
+
+    /// Built-in GATT layout and request handling to use.
+    #[arg(long, value_enum, default_value_t = Profile::Switch)]
+    pub profile: Profile,
+
+    /// Named switch on its own characteristic, as `name=UUID`. Repeat for
+    /// several channels; the first one is what plain `on`/`off` controls.
+    #[arg(long = "switch", value_name = "NAME=UUID", value_parser = parse_switch)]
+    pub switches: Vec<(String, Uuid)>,
+
+    /// State the switch boots in [default: the config file's state, or off].
+    #[arg(long, value_enum)]
+    pub initial_state: Option<SwitchState>,
+
+    /// Turn a switch back off this many seconds after it was turned on; 0
+    /// disables it [default: the config file's `auto_off_secs`, or 0].
+    #[arg(long)]
+    pub auto_off_secs: Option<u64>,
+
+    /// TOML, JSON or YAML file describing the services to register instead of the built-in layout.
+    #[arg(long, conflicts_with = "config_dir")]
+    pub config: Option<PathBuf>,
+
+    /// Directory whose `*.toml` files are all loaded, in filename order.
+    #[arg(long)]
+    pub config_dir: Option<PathBuf>,
+
+    /// Print the GATT table that would be registered and exit without touching Bluetooth.
+    #[arg(long)]
+    pub dry_run: bool,
+
+    /// Adapter to advertise on, as listed by `list-adapters`.
+    #[arg(long)]
+    pub adapter: Option<String>,
+
+    /// Answer writes that are not `on`, `off` or an alias with an error
+    /// instead of echoing them to subscribers.
+    #[arg(long)]
+    pub strict_writes: bool,
+
+    /// Register a BlueZ agent so pairing requests are confirmed on the
+    /// console with `pair accept` or `pair reject`.
+    #[arg(long)]
+    pub pairing_agent: bool,
+
+    /// Prefix notifications and reads with a per-characteristic u16 sequence
+    /// number. Changes the wire format.
+    #[arg(long)]
+    pub seq_numbers: bool,
+
+    /// Coalesce notifications per characteristic so only the last value
+    /// within this many milliseconds is sent. Reads and write responses are
+    /// not delayed. 0 notifies on every change.
+    #[arg(long, default_value_t = 0)]
+    pub notify_debounce_ms: u64,
+
+    /// Re-register the services and resume advertising when the adapter
+    /// powers back on after being switched off.
+    #[arg(long)]
+    pub auto_restore: bool,
+
+    /// Number of recent state changes the `history` command remembers.
+    #[arg(long, default_value_t = DEFAULT_HISTORY_SIZE)]
+    pub history_size: usize,
+
+    /// File the switch state is saved to on every change and restored from
+    /// at startup.
+    #[arg(long)]
+    pub state_file: Option<PathBuf>,
+
+    /// Also write log output to this file, rotated once it reaches 10 MiB.
+    #[arg(long)]
+    pub log_file: Option<PathBuf>,
+
+    /// Log filter applied on top of `RUST_LOG`, e.g. `info,ble::events=debug`.
+    #[arg(long)]
+    pub log_level: Option<String>,
+
+    /// How console commands print their results. `json` prints one object
+    /// per line with a `type` field and keeps log lines on stderr.
+    #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+    pub output: OutputFormat,
+
+    /// Run without the console, e.g. under systemd: control is only through
+    /// BLE writes, and the process runs until SIGTERM or Ctrl-C.
+    #[arg(long)]
+    pub headless: bool,
+
+    /// Console commands to run, one per line, once advertising has started.
+    /// `sleep <seconds>` lines pause between them.
+    #[arg(long)]
+    pub script: Option<PathBuf>,
+
+    /// Exit once the script has run instead of opening the prompt, or with
+    /// `--headless` instead of waiting for a signal.
+    #[arg(long, requires = "script")]
+    pub script_exit: bool,
+
+    /// Stop and exit at the first script command that fails, instead of
+    /// logging it and carrying on.
+    #[arg(long, requires = "script")]
+    pub script_fatal: bool,
+}
+
+impl RunArgs {
+    /// The `--switch` channels, or a single default switch on `char_uuid`.
+    pub fn switch_channels(&self, char_uuid: Uuid) -> Vec<(String, Uuid)> {
+        if self.switches.is_empty() {
+            vec![(DEFAULT_SWITCH_NAME.to_string(), char_uuid)]
+        } else {
+            self.switches.clone()
+        }
+    }
+
+    pub fn notify_debounce(&self) -> Option<Duration> {
+        (self.notify_debounce_ms > 0).then(|| Duration::from_millis(self.notify_debounce_ms))
+    }
+
+    pub fn config_source(&self) -> Option<ConfigSource> {
+        match (&self.config, &self.config_dir) {
+            (Some(path), _) => Some(ConfigSource::File(path.clone())),
+            (None, Some(dir)) => Some(ConfigSource::Dir(dir.clone())),
+            (None, None) => None,
+        }
+    }
+}
+
+fn parse_switch(input: &str) -> Result<(String, Uuid), String> {
+    let (name, uuid) = input
+        .split_once('=')
+        .ok_or_else(|| format!("expected NAME=UUID, got '{}'", input))?;
+    if name.is_empty() {
+        return Err("switch name must not be empty".to_string());
+    }
+    Ok((name.to_string(), parse_uuid(uuid)?))
+}
+
+fn parse_capacity(input: &str) -> Result<usize, String> {
+    match input.trim().parse::<usize>() {
+        Ok(0) => Err("capacity must be greater than 0".to_string()),
+        Ok(capacity) => Ok(capacity),
+        Err(_) => Err(format!("'{}' is not a valid capacity", input)),
+    }
+}
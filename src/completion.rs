@@ -0,0 +1,154 @@
+use std::sync::{Arc, RwLock};
+
+use rustyline::completion::{Completer, Pair};
+use rustyline::highlight::Highlighter;
+use rustyline::hint::Hinter;
+use rustyline::validate::Validator;
+use rustyline::{Context, Helper};
+use uuid::Uuid;
+
+use crate::console::COMMANDS;
+use crate::short_uuid::ShortUuidExt;
+
+/// Words that follow a command, for the commands that take a fixed one.
+const SUBCOMMANDS: &[(&str, &[&str])] = &[
+    ("adv", &["start", "stop"]),
+    ("char", &["add", "remove"]),
+    ("pair", &["accept", "reject"]),
+    ("stats", &["reset"]),
+];
+
+/// The characteristic UUIDs currently in the GATT table, kept up to date by
+/// `Device` and readable from the prompt's thread without locking the
+/// device.
+#[derive(Debug, Clone, Default)]
+pub struct KnownUuids(Arc<RwLock<Vec<Uuid>>>);
+
+impl KnownUuids {
+    pub fn set(&self, uuids: Vec<Uuid>) {
+        *self.0.write().unwrap() = uuids;
+    }
+
+    pub fn get(&self) -> Vec<Uuid> {
+        self.0.read().unwrap().clone()
+    }
+}
+
+/// Completes what is being typed at `pos` in `line`: command names, their
+/// fixed subcommands, and registered characteristic UUIDs where a command
+/// takes one. Returns where the completed word starts and the candidates.
+///
+/// UUIDs complete in whichever form was started, the 16-bit short form
+/// (`2A3D`, also after `0x`) or the full hyphenated one; an empty word
+/// offers the short form where there is one.
+pub fn complete_line(line: &str, pos: usize, uuids: &[Uuid]) -> (usize, Vec<String>) {
+    let before = &line[..pos];
+    let start = before
+        .rfind(char::is_whitespace)
+        .map_or(0, |index| index + 1);
+    let word = &before[start..];
+    let previous: Vec<&str> = before[..start].split_whitespace().collect();
+    let candidates = match previous.as_slice() {
+        [] => command_names()
+            .filter(|name| starts_with_ignore_case(name, word))
+            .map(ToString::to_string)
+            .collect(),
+        [command] => {
+            let command = command.to_lowercase();
+            match SUBCOMMANDS.iter().find(|(name, _)| *name == command) {
+                Some((_, subcommands)) => subcommands
+                    .iter()
+                    .filter(|subcommand| starts_with_ignore_case(subcommand, word))
+                    .map(ToString::to_string)
+                    .collect(),
+                None if command == "write" || command == "hex" => complete_uuid(word, uuids),
+                None => Vec::new(),
+            }
+        }
+        [command, subcommand]
+            if command.eq_ignore_ascii_case("char")
+                && subcommand.eq_ignore_ascii_case("remove") =>
+        {
+            complete_uuid(word, uuids)
+        }
+        _ => Vec::new(),
+    };
+    (start, candidates)
+}
+
+fn command_names() -> impl Iterator<Item = &'static str> {
+    COMMANDS
+        .iter()
+        .map(|info| info.name)
+        .chain(std::iter::once("off"))
+}
+
+fn complete_uuid(word: &str, uuids: &[Uuid]) -> Vec<String> {
+    let (prefix, typed) = match word.get(..2) {
+        Some(hex) if hex.eq_ignore_ascii_case("0x") => (&word[..2], &word[2..]),
+        _ => ("", word),
+    };
+    uuids
+        .iter()
+        .filter_map(|uuid| {
+            let short = uuid.to_short().map(|short| format!("{:04X}", short));
+            let long = uuid.hyphenated().to_string();
+            match short {
+                Some(short) if starts_with_ignore_case(&short, typed) => {
+                    Some(format!("{}{}", prefix, short))
+                }
+                _ if prefix.is_empty() && starts_with_ignore_case(&long, typed) => Some(long),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+fn starts_with_ignore_case(candidate: &str, prefix: &str) -> bool {
+    candidate
+        .get(..prefix.len())
+        .is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+}
+
+/// Rustyline helper that completes with `complete_line` against the live GATT
+/// table.
+pub struct ConsoleHelper {
+    uuids: KnownUuids,
+}
+
+impl ConsoleHelper {
+    pub fn new(uuids: KnownUuids) -> Self {
+        Self { uuids }
+    }
+}
+
+impl Completer for ConsoleHelper {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _ctx: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let (start, candidates) = complete_line(line, pos, &self.uuids.get());
+        let pairs = candidates
+            .into_iter()
+            .map(|candidate| Pair {
+                display: candidate.clone(),
+                replacement: format!("{} ", candidate),
+            })
+            .collect();
+        Ok((start, pairs))
+    }
+}
+
+impl Hinter for ConsoleHelper {
+    type Hint = String;
+}
+
+impl Highlighter for ConsoleHelper {}
+
+impl Validator for ConsoleHelper {}
+
+impl Helper for ConsoleHelper {}
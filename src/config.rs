@@ -0,0 +1,1107 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::advertising::{validate_flags, AdvertisingParameters, Interval};
+use crate::aliases::Aliases;
+use crate::device::Device;
+use crate::persist;
+use crate::presentation;
+use crate::profile::switch::SwitchState;
+use crate::short_uuid::ShortUuidExt;
+use crate::state_machine::TransitionTable;
+use crate::switches::{Encoding, Mirror, SwitchBank, DEFAULT_SWITCH_NAME};
+
+use ble_peripheral_rust::gatt::{
+    characteristic::Characteristic,
+    descriptor::Descriptor,
+    properties::{AttributePermission, CharacteristicProperty},
+    service::Service,
+};
+
+/// Bluetooth Base UUID (`00000000-0000-1000-8000-00805F9B34FB`) used to expand
+/// 32-bit short UUIDs.
+pub const BLUETOOTH_BASE_UUID: u128 = 0x0000_0000_0000_1000_8000_0080_5F9B_34FB;
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Io {
+        file: PathBuf,
+        source: std::io::Error,
+    },
+    Parse {
+        file: PathBuf,
+        message: String,
+    },
+    Serialize {
+        file: PathBuf,
+        message: String,
+    },
+    Invalid {
+        file: PathBuf,
+        table: String,
+        field: &'static str,
+        message: String,
+    },
+    /// Two files in a config directory define the same UUID.
+    Collision {
+        uuid: Uuid,
+        first: PathBuf,
+        second: PathBuf,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io { file, source } => {
+                write!(f, "{}: {}", file.display(), source)
+            }
+            ConfigError::Parse { file, message } | ConfigError::Serialize { file, message } => {
+                write!(f, "{}: {}", file.display(), message)
+            }
+            ConfigError::Invalid {
+                file,
+                table,
+                field,
+                message,
+            } => write!(f, "{}: {}.{}: {}", file.display(), table, field, message),
+            ConfigError::Collision {
+                uuid,
+                first,
+                second,
+            } => write!(
+                f,
+                "UUID {} is defined in both {} and {}",
+                uuid,
+                first.display(),
+                second.display()
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ConfigError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct RawConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    initial_state: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    auto_off_secs: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    advertising: Option<RawAdvertising>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    aliases: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    state_machine: Option<RawStateMachine>,
+    #[serde(default)]
+    services: Vec<RawService>,
+}
+
+/// Lifecycle for the `machine` profile, e.g. `states = ["Idle", "Running"]`,
+/// `transitions = { Idle = ["Running"], Running = ["Idle"] }`.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct RawStateMachine {
+    states: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    initial: Option<String>,
+    #[serde(default)]
+    transitions: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct RawAdvertising {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    service_uuids: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    interval_min_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    interval_max_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tx_power_level: Option<i8>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    connectable: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    appearance: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    flags: Option<u8>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct RawService {
+    uuid: String,
+    #[serde(default = "default_primary")]
+    primary: bool,
+    #[serde(default)]
+    characteristics: Vec<RawCharacteristic>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct RawCharacteristic {
+    uuid: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    properties: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    permissions: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    value: Option<RawValue>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    descriptors: Vec<RawDescriptor>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mirror: Option<RawMirror>,
+    #[serde(default, skip_serializing_if = "is_false")]
+    strict_format: bool,
+}
+
+fn is_false(value: &bool) -> bool {
+    !value
+}
+
+/// Marks a characteristic as carrying a switch's state, e.g.
+/// `mirror = { switch = "lamp", encoding = "byte" }`.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct RawMirror {
+    #[serde(default = "default_mirror_switch")]
+    switch: String,
+    encoding: String,
+}
+
+fn default_mirror_switch() -> String {
+    DEFAULT_SWITCH_NAME.to_string()
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(deny_unknown_fields)]
+struct RawDescriptor {
+    uuid: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    properties: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    permissions: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    value: Option<RawValue>,
+}
+
+/// Attribute values may be written either as text (`value = "on"`) or as a
+/// byte array (`value = [0, 1]`). Saved configs always use the byte form.
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(untagged)]
+enum RawValue {
+    Text(String),
+    Bytes(Vec<u8>),
+}
+
+impl RawValue {
+    fn into_bytes(self) -> Vec<u8> {
+        match self {
+            RawValue::Text(text) => text.into_bytes(),
+            RawValue::Bytes(bytes) => bytes,
+        }
+    }
+}
+
+fn default_primary() -> bool {
+    true
+}
+
+/// Parses a 16-bit (`2A3D`), 32-bit (`0000180F`) or full 128-bit UUID.
+pub fn parse_uuid(input: &str) -> Result<Uuid, String> {
+    Uuid::try_from_string(input).map_err(|err| err.to_string())
+}
+
+/// Formats a UUID in the shortest form `parse_uuid` reads back.
+pub fn format_uuid(uuid: &Uuid) -> String {
+    let value = uuid.as_u128();
+    if value & 0xFFFF_FFFF_FFFF_FFFF_FFFF_FFFF == BLUETOOTH_BASE_UUID {
+        let short = (value >> 96) as u32;
+        if short <= 0xFFFF {
+            return format!("{:04X}", short);
+        }
+        return format!("{:08X}", short);
+    }
+    uuid.hyphenated().to_string()
+}
+
+pub fn property_name(property: &CharacteristicProperty) -> &'static str {
+    match property {
+        CharacteristicProperty::Broadcast => "broadcast",
+        CharacteristicProperty::Read => "read",
+        CharacteristicProperty::WriteWithoutResponse => "write_without_response",
+        CharacteristicProperty::Write => "write",
+        CharacteristicProperty::Notify => "notify",
+        CharacteristicProperty::Indicate => "indicate",
+        CharacteristicProperty::AuthenticatedSignedWrites => "authenticated_signed_writes",
+        CharacteristicProperty::ExtendedProperties => "extended_properties",
+        CharacteristicProperty::NotifyEncryptionRequired => "notify_encryption_required",
+        CharacteristicProperty::IndicateEncryptionRequired => "indicate_encryption_required",
+    }
+}
+
+pub fn permission_name(permission: &AttributePermission) -> &'static str {
+    match permission {
+        AttributePermission::Readable => "readable",
+        AttributePermission::Writeable => "writeable",
+        AttributePermission::ReadEncryptionRequired => "read_encryption_required",
+        AttributePermission::WriteEncryptionRequired => "write_encryption_required",
+    }
+}
+
+pub(crate) fn parse_property(name: &str) -> Option<CharacteristicProperty> {
+    let property = match name {
+        "broadcast" => CharacteristicProperty::Broadcast,
+        "read" => CharacteristicProperty::Read,
+        "write_without_response" => CharacteristicProperty::WriteWithoutResponse,
+        "write" => CharacteristicProperty::Write,
+        "notify" => CharacteristicProperty::Notify,
+        "indicate" => CharacteristicProperty::Indicate,
+        "authenticated_signed_writes" => CharacteristicProperty::AuthenticatedSignedWrites,
+        "extended_properties" => CharacteristicProperty::ExtendedProperties,
+        "notify_encryption_required" => CharacteristicProperty::NotifyEncryptionRequired,
+        "indicate_encryption_required" => CharacteristicProperty::IndicateEncryptionRequired,
+        _ => return None,
+    };
+    Some(property)
+}
+
+fn parse_permission(name: &str) -> Option<AttributePermission> {
+    let permission = match name {
+        "readable" => AttributePermission::Readable,
+        "writeable" => AttributePermission::Writeable,
+        "read_encryption_required" => AttributePermission::ReadEncryptionRequired,
+        "write_encryption_required" => AttributePermission::WriteEncryptionRequired,
+        _ => return None,
+    };
+    Some(permission)
+}
+
+/// Tracks where in the document a value came from so errors can point at it.
+struct Context<'a> {
+    file: &'a Path,
+}
+
+impl Context<'_> {
+    fn invalid(&self, table: &str, field: &'static str, message: String) -> ConfigError {
+        ConfigError::Invalid {
+            file: self.file.to_path_buf(),
+            table: table.to_string(),
+            field,
+            message,
+        }
+    }
+
+    fn uuid(&self, table: &str, value: &str) -> Result<Uuid, ConfigError> {
+        parse_uuid(value).map_err(|message| self.invalid(table, "uuid", message))
+    }
+
+    fn properties(
+        &self,
+        table: &str,
+        names: &[String],
+    ) -> Result<Vec<CharacteristicProperty>, ConfigError> {
+        names
+            .iter()
+            .map(|name| {
+                parse_property(name).ok_or_else(|| {
+                    self.invalid(table, "properties", format!("unknown property '{}'", name))
+                })
+            })
+            .collect()
+    }
+
+    fn permissions(
+        &self,
+        table: &str,
+        names: &[String],
+    ) -> Result<Vec<AttributePermission>, ConfigError> {
+        names
+            .iter()
+            .map(|name| {
+                parse_permission(name).ok_or_else(|| {
+                    self.invalid(
+                        table,
+                        "permissions",
+                        format!("unknown permission '{}'", name),
+                    )
+                })
+            })
+            .collect()
+    }
+
+    fn service(&self, table: &str, raw: RawService) -> Result<Service, ConfigError> {
+        let characteristics = raw
+            .characteristics
+            .into_iter()
+            .enumerate()
+            .map(|(index, raw)| {
+                self.characteristic(&format!("{}.characteristics[{}]", table, index), raw)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Service {
+            uuid: self.uuid(table, &raw.uuid)?,
+            primary: raw.primary,
+            characteristics,
+        })
+    }
+
+    fn characteristic(
+        &self,
+        table: &str,
+        raw: RawCharacteristic,
+    ) -> Result<Characteristic, ConfigError> {
+        let descriptors = raw
+            .descriptors
+            .into_iter()
+            .enumerate()
+            .map(|(index, raw)| self.descriptor(&format!("{}.descriptors[{}]", table, index), raw))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Characteristic {
+            uuid: self.uuid(table, &raw.uuid)?,
+            properties: self.properties(table, &raw.properties)?,
+            permissions: self.permissions(table, &raw.permissions)?,
+            value: raw.value.map(RawValue::into_bytes),
+            descriptors,
+        })
+    }
+
+    fn mirror(&self, table: &str, uuid: &str, raw: &RawMirror) -> Result<Mirror, ConfigError> {
+        let encoding = Encoding::parse(&raw.encoding).ok_or_else(|| {
+            self.invalid(
+                table,
+                "mirror",
+                format!(
+                    "unknown encoding '{}', expected 'text' or 'byte'",
+                    raw.encoding
+                ),
+            )
+        })?;
+        Ok(Mirror {
+            switch: raw.switch.clone(),
+            characteristic: self.uuid(table, uuid)?,
+            encoding,
+        })
+    }
+
+    fn descriptor(&self, table: &str, raw: RawDescriptor) -> Result<Descriptor, ConfigError> {
+        Ok(Descriptor {
+            uuid: self.uuid(table, &raw.uuid)?,
+            properties: self.properties(table, &raw.properties)?,
+            permissions: self.permissions(table, &raw.permissions)?,
+            value: raw.value.map(RawValue::into_bytes),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Toml,
+    Json,
+    Yaml,
+}
+
+impl Format {
+    /// Picks the format from the file extension, defaulting to TOML.
+    fn of(file: &Path) -> Self {
+        let extension = file
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("json") => Format::Json,
+            Some("yaml") | Some("yml") => Format::Yaml,
+            _ => Format::Toml,
+        }
+    }
+
+    fn parse(self, contents: &str) -> Result<RawConfig, String> {
+        match self {
+            Format::Toml => toml::from_str(contents).map_err(|err| err.to_string()),
+            Format::Json => serde_json::from_str(contents).map_err(|err| err.to_string()),
+            // serde_yaml errors already carry "at line X column Y".
+            Format::Yaml => serde_yaml::from_str(contents).map_err(|err| err.to_string()),
+        }
+    }
+}
+
+/// Advertising parameters stored alongside the services.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AdvertisingConfig {
+    pub name: Option<String>,
+    pub service_uuids: Vec<Uuid>,
+    /// GAP Appearance value, e.g. `0x0340` for a generic thermometer.
+    pub appearance: Option<u16>,
+    pub flags: Option<u8>,
+    pub parameters: AdvertisingParameters,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    pub initial_state: Option<SwitchState>,
+    /// Seconds after which a switch that was turned on turns itself off.
+    pub auto_off_secs: Option<u64>,
+    pub advertising: Option<AdvertisingConfig>,
+    pub aliases: Aliases,
+    pub services: Vec<Service>,
+    /// Characteristics that mirror a switch's state in another encoding.
+    pub mirrors: Vec<Mirror>,
+    /// Characteristics whose updates must match their CPF format's size.
+    pub strict_formats: Vec<Uuid>,
+    /// Transition table for the `machine` profile.
+    pub state_machine: Option<TransitionTable>,
+    /// Files the configuration was read from, in load order.
+    pub files: Vec<PathBuf>,
+}
+
+impl Config {
+    /// The running setup as a config that recreates it, for `save-config`.
+    ///
+    /// Advertising is taken from the last advertisement even while it is
+    /// stopped, and services registered with `char add` or CCCDs the device
+    /// added on its own are left out, so loading the result registers the
+    /// same GATT table again.
+    pub fn capture(
+        device: &Device,
+        switches: &SwitchBank,
+        table: &TransitionTable,
+        auto_off_secs: u64,
+    ) -> Self {
+        let services = device.declared_services();
+        let strict_formats = services
+            .iter()
+            .flat_map(|service| &service.characteristics)
+            .map(|characteristic| characteristic.uuid)
+            .filter(|&uuid| device.is_strict_format(uuid))
+            .collect();
+        Config {
+            initial_state: Some(switches.primary().state.read().unwrap().switch_state()),
+            auto_off_secs: Some(auto_off_secs).filter(|&secs| secs > 0),
+            advertising: device.last_advertisement().map(|data| AdvertisingConfig {
+                name: device.local_name().map(str::to_string),
+                service_uuids: data.all_service_uuids(),
+                appearance: data.appearance,
+                flags: data.flags,
+                parameters: data.parameters,
+            }),
+            aliases: switches.aliases(),
+            services,
+            mirrors: switches.mirrors().cloned().collect(),
+            strict_formats,
+            // The default table is what loading a config without one gives.
+            state_machine: Some(table.clone()).filter(|table| *table != TransitionTable::default()),
+            files: Vec::new(),
+        }
+    }
+}
+
+/// Where the configuration comes from: one file or a directory of them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigSource {
+    File(PathBuf),
+    Dir(PathBuf),
+}
+
+impl ConfigSource {
+    pub fn path(&self) -> &Path {
+        match self {
+            ConfigSource::File(path) | ConfigSource::Dir(path) => path,
+        }
+    }
+
+    pub fn load(&self) -> Result<Config, ConfigError> {
+        match self {
+            ConfigSource::File(path) => load_config(path),
+            ConfigSource::Dir(path) => load_config_dir(path),
+        }
+    }
+}
+
+/// Reads a service description and converts it into GATT services.
+///
+/// Files ending in `.json` are parsed as JSON, `.yaml`/`.yml` as YAML and
+/// everything else as TOML; all three go through the same structures.
+pub fn load_config(file: &Path) -> Result<Config, ConfigError> {
+    let contents = std::fs::read_to_string(file).map_err(|source| ConfigError::Io {
+        file: file.to_path_buf(),
+        source,
+    })?;
+    let raw = Format::of(file)
+        .parse(&contents)
+        .map_err(|message| ConfigError::Parse {
+            file: file.to_path_buf(),
+            message,
+        })?;
+
+    let context = Context { file };
+    let initial_state = match raw.initial_state {
+        Some(value) => Some(SwitchState::parse(&value).ok_or_else(|| {
+            context.invalid(
+                "(root)",
+                "initial_state",
+                format!("expected 'on' or 'off', got '{}'", value),
+            )
+        })?),
+        None => None,
+    };
+    let advertising = match raw.advertising {
+        Some(raw) => {
+            let parameters = AdvertisingParameters {
+                interval_min: raw.interval_min_ms.map(Duration::from_millis),
+                interval_max: raw.interval_max_ms.map(Duration::from_millis),
+                tx_power_level: raw.tx_power_level,
+                connectable: raw.connectable,
+            };
+            parameters.validate().map_err(|(interval, message)| {
+                let field = match interval {
+                    Interval::Min => "interval_min_ms",
+                    Interval::Max => "interval_max_ms",
+                };
+                context.invalid("advertising", field, message)
+            })?;
+            if let Some(flags) = raw.flags {
+                validate_flags(flags)
+                    .map_err(|message| context.invalid("advertising", "flags", message))?;
+            }
+            Some(AdvertisingConfig {
+                name: raw.name,
+                service_uuids: raw
+                    .service_uuids
+                    .iter()
+                    .map(|uuid| context.uuid("advertising.service_uuids", uuid))
+                    .collect::<Result<_, _>>()?,
+                appearance: raw.appearance,
+                flags: raw.flags,
+                parameters,
+            })
+        }
+        None => None,
+    };
+    let aliases = Aliases::from_pairs(
+        raw.aliases
+            .iter()
+            .map(|(alias, target)| (alias.as_str(), target.as_str())),
+    )
+    .map_err(|message| context.invalid("aliases", "(key)", message))?;
+    let state_machine = match raw.state_machine {
+        Some(machine) => Some(
+            TransitionTable::new(
+                machine.states,
+                machine.initial.as_deref(),
+                &machine.transitions,
+            )
+            .map_err(|message| context.invalid("state_machine", "transitions", message))?,
+        ),
+        None => None,
+    };
+    let mut mirrors = Vec::new();
+    let mut strict = Vec::new();
+    for (service_index, service) in raw.services.iter().enumerate() {
+        for (index, characteristic) in service.characteristics.iter().enumerate() {
+            let table = format!("services[{}].characteristics[{}]", service_index, index);
+            if let Some(mirror) = &characteristic.mirror {
+                mirrors.push(context.mirror(&table, &characteristic.uuid, mirror)?);
+            }
+            if characteristic.strict_format {
+                strict.push((service_index, index, table));
+            }
+        }
+    }
+    let services: Vec<Service> = raw
+        .services
+        .into_iter()
+        .enumerate()
+        .map(|(index, raw)| context.service(&format!("services[{}]", index), raw))
+        .collect::<Result<_, _>>()?;
+    let strict_formats = strict
+        .into_iter()
+        .map(|(service_index, index, table)| {
+            let characteristic = &services[service_index].characteristics[index];
+            match presentation::declared_len(characteristic) {
+                Some(_) => Ok(characteristic.uuid),
+                None => Err(context.invalid(
+                    &table,
+                    "strict_format",
+                    "needs a 0x2904 descriptor with a fixed-size format".to_string(),
+                )),
+            }
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok(Config {
+        initial_state,
+        auto_off_secs: raw.auto_off_secs,
+        advertising,
+        aliases,
+        services,
+        mirrors,
+        strict_formats,
+        state_machine,
+        files: vec![file.to_path_buf()],
+    })
+}
+
+/// Loads every `*.toml` file in `dir` in sorted filename order.
+///
+/// A service or characteristic UUID defined by two different files is an
+/// error naming both, as is `initial_state`, `auto_off_secs`, `[advertising]`
+/// or `[state_machine]` set twice.
+pub fn load_config_dir(dir: &Path) -> Result<Config, ConfigError> {
+    let io_error = |source| ConfigError::Io {
+        file: dir.to_path_buf(),
+        source,
+    };
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir).map_err(io_error)? {
+        let path = entry.map_err(io_error)?.path();
+        let is_toml = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("toml"));
+        if is_toml && path.is_file() {
+            files.push(path);
+        }
+    }
+    files.sort();
+
+    let mut merged = Config::default();
+    let mut owners: HashMap<Uuid, PathBuf> = HashMap::new();
+    let mut claim = |uuid: Uuid, file: &Path| match owners.get(&uuid) {
+        Some(first) if first != file => Err(ConfigError::Collision {
+            uuid,
+            first: first.clone(),
+            second: file.to_path_buf(),
+        }),
+        _ => {
+            owners.insert(uuid, file.to_path_buf());
+            Ok(())
+        }
+    };
+    let duplicate = |file: &Path, field: &'static str| ConfigError::Invalid {
+        file: file.to_path_buf(),
+        table: "(root)".to_string(),
+        field,
+        message: "already set by an earlier file in the directory".to_string(),
+    };
+
+    for file in files {
+        let config = load_config(&file)?;
+        for service in &config.services {
+            claim(service.uuid, &file)?;
+            for characteristic in &service.characteristics {
+                claim(characteristic.uuid, &file)?;
+            }
+        }
+        if config.initial_state.is_some() {
+            if merged.initial_state.is_some() {
+                return Err(duplicate(&file, "initial_state"));
+            }
+            merged.initial_state = config.initial_state;
+        }
+        if config.auto_off_secs.is_some() {
+            if merged.auto_off_secs.is_some() {
+                return Err(duplicate(&file, "auto_off_secs"));
+            }
+            merged.auto_off_secs = config.auto_off_secs;
+        }
+        if config.state_machine.is_some() {
+            if merged.state_machine.is_some() {
+                return Err(duplicate(&file, "state_machine"));
+            }
+            merged.state_machine = config.state_machine;
+        }
+        if config.advertising.is_some() {
+            if merged.advertising.is_some() {
+                return Err(duplicate(&file, "advertising"));
+            }
+            merged.advertising = config.advertising;
+        }
+        merged
+            .aliases
+            .merge(config.aliases)
+            .map_err(|message| ConfigError::Invalid {
+                file: file.clone(),
+                table: "aliases".to_string(),
+                field: "(key)",
+                message,
+            })?;
+        merged.services.extend(config.services);
+        merged.mirrors.extend(config.mirrors);
+        merged.strict_formats.extend(config.strict_formats);
+        merged.files.push(file);
+    }
+    Ok(merged)
+}
+
+pub fn load_services(file: &Path) -> Result<Vec<Service>, ConfigError> {
+    Ok(load_config(file)?.services)
+}
+
+/// Services in the layout of a config file's `services` array, with UUIDs
+/// spelled by `format`.
+pub(crate) fn services_to_raw(
+    services: &[Service],
+    mirrors: &[Mirror],
+    strict_formats: &[Uuid],
+    format: fn(&Uuid) -> String,
+) -> Vec<RawService> {
+    services
+        .iter()
+        .map(|service| raw_service(service, mirrors, strict_formats, format))
+        .collect()
+}
+
+/// Parses services written in the config file layout, naming the offending
+/// table and field on error.
+pub(crate) fn services_from_raw(raw: Vec<RawService>) -> Result<Vec<Service>, String> {
+    let context = Context {
+        file: Path::new(""),
+    };
+    raw.into_iter()
+        .enumerate()
+        .map(|(index, raw)| context.service(&format!("services[{}]", index), raw))
+        .collect::<Result<_, _>>()
+        .map_err(|err| match err {
+            ConfigError::Invalid {
+                table,
+                field,
+                message,
+                ..
+            } => format!("{}.{}: {}", table, field, message),
+            other => other.to_string(),
+        })
+}
+
+fn raw_service(
+    service: &Service,
+    mirrors: &[Mirror],
+    strict_formats: &[Uuid],
+    format: fn(&Uuid) -> String,
+) -> RawService {
+    RawService {
+        uuid: format(&service.uuid),
+        primary: service.primary,
+        characteristics: service
+            .characteristics
+            .iter()
+            .map(|characteristic| RawCharacteristic {
+                uuid: format(&characteristic.uuid),
+                properties: characteristic
+                    .properties
+                    .iter()
+                    .map(|property| property_name(property).to_string())
+                    .collect(),
+                permissions: characteristic
+                    .permissions
+                    .iter()
+                    .map(|permission| permission_name(permission).to_string())
+                    .collect(),
+                value: characteristic.value.clone().map(RawValue::Bytes),
+                descriptors: characteristic
+                    .descriptors
+                    .iter()
+                    .map(|descriptor| RawDescriptor {
+                        uuid: format(&descriptor.uuid),
+                        properties: descriptor
+                            .properties
+                            .iter()
+                            .map(|property| property_name(property).to_string())
+                            .collect(),
+                        permissions: descriptor
+                            .permissions
+                            .iter()
+                            .map(|permission| permission_name(permission).to_string())
+                            .collect(),
+                        value: descriptor.value.clone().map(RawValue::Bytes),
+                    })
+                    .collect(),
+                mirror: mirrors
+                    .iter()
+                    .find(|mirror| mirror.characteristic == characteristic.uuid)
+                    .map(|mirror| RawMirror {
+                        switch: mirror.switch.clone(),
+                        encoding: mirror.encoding.name().to_string(),
+                    }),
+                strict_format: strict_formats.contains(&characteristic.uuid),
+            })
+            .collect(),
+    }
+}
+
+/// Writes `config` as TOML in a form `load_config` reads back to the same layout.
+///
+/// The file is written with `persist::write_atomically`, so an existing file
+/// is only replaced when `force` is set.
+pub fn save_config(file: &Path, config: &Config, force: bool) -> Result<(), ConfigError> {
+    let raw = RawConfig {
+        initial_state: config.initial_state.map(|state| state.as_str().to_string()),
+        auto_off_secs: config.auto_off_secs,
+        advertising: config
+            .advertising
+            .as_ref()
+            .map(|advertising| RawAdvertising {
+                name: advertising.name.clone(),
+                service_uuids: advertising.service_uuids.iter().map(format_uuid).collect(),
+                interval_min_ms: advertising
+                    .parameters
+                    .interval_min
+                    .map(|interval| interval.as_millis() as u64),
+                interval_max_ms: advertising
+                    .parameters
+                    .interval_max
+                    .map(|interval| interval.as_millis() as u64),
+                tx_power_level: advertising.parameters.tx_power_level,
+                connectable: advertising.parameters.connectable,
+                appearance: advertising.appearance,
+                flags: advertising.flags,
+            }),
+        aliases: config
+            .aliases
+            .iter()
+            .map(|(alias, state)| (alias.to_string(), state.as_str().to_string()))
+            .collect(),
+        state_machine: config.state_machine.as_ref().map(|table| RawStateMachine {
+            states: table.states().to_vec(),
+            initial: Some(table.initial().to_string()),
+            transitions: table
+                .states()
+                .iter()
+                .map(|from| {
+                    let targets = table.targets(from).into_iter().map(String::from);
+                    (from.clone(), targets.collect())
+                })
+                .filter(|(_, targets): &(String, Vec<String>)| !targets.is_empty())
+                .collect(),
+        }),
+        services: services_to_raw(
+            &config.services,
+            &config.mirrors,
+            &config.strict_formats,
+            format_uuid,
+        ),
+    };
+    let contents = toml::to_string_pretty(&raw).map_err(|err| ConfigError::Serialize {
+        file: file.to_path_buf(),
+        message: err.to_string(),
+    })?;
+
+    persist::write_atomically(file, contents.as_bytes(), force).map_err(|source| {
+        let source = if source.kind() == std::io::ErrorKind::AlreadyExists {
+            std::io::Error::new(
+                std::io::ErrorKind::AlreadyExists,
+                "file exists, pass --force to overwrite",
+            )
+        } else {
+            source
+        };
+        ConfigError::Io {
+            file: file.to_path_buf(),
+            source,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes `contents` to a file called `name` in a directory of its own
+    /// under the system temp dir, so tests running in parallel never share
+    /// one.
+    fn temp_file(test: &str, name: &str, contents: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("bluetooth-config-{}-{}", std::process::id(), test));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(name);
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    fn invalid_field(err: ConfigError) -> (String, &'static str) {
+        match err {
+            ConfigError::Invalid { table, field, .. } => (table, field),
+            other => panic!("expected Invalid, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn advertising_errors_name_the_interval_at_fault() {
+        let cases = [
+            ("interval_min_ms = 5", "interval_min_ms"),
+            ("interval_max_ms = 20000", "interval_max_ms"),
+            (
+                "interval_min_ms = 100\ninterval_max_ms = 50",
+                "interval_min_ms",
+            ),
+        ];
+        for (index, (advertising, field)) in cases.into_iter().enumerate() {
+            let path = temp_file(
+                &format!("intervals-{}", index),
+                "config.toml",
+                &format!("[advertising]\n{}\n", advertising),
+            );
+            let err = load_config(&path).unwrap_err();
+            assert_eq!(
+                invalid_field(err),
+                ("advertising".to_string(), field),
+                "{}",
+                advertising
+            );
+        }
+    }
+
+    #[test]
+    fn save_config_only_overwrites_with_force() {
+        let path = temp_file("save", "saved.toml", "# keep me\n");
+        let config = load_config(&temp_file(
+            "save",
+            "source.toml",
+            "[[services]]\nuuid = \"180F\"\n",
+        ))
+        .unwrap();
+
+        match save_config(&path, &config, false) {
+            Err(ConfigError::Io { source, .. }) => {
+                assert_eq!(source.kind(), std::io::ErrorKind::AlreadyExists)
+            }
+            other => panic!("expected AlreadyExists, got {:?}", other),
+        }
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "# keep me\n");
+
+        save_config(&path, &config, true).unwrap();
+        let saved = load_config(&path).unwrap();
+        assert_eq!(saved.services.len(), 1);
+        assert_eq!(saved.services[0].uuid, parse_uuid("180F").unwrap());
+
+        let fresh = path.with_file_name("fresh.toml");
+        let _ = std::fs::remove_file(&fresh);
+        save_config(&fresh, &config, false).unwrap();
+        assert_eq!(load_config(&fresh).unwrap().services.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn saved_config_recreates_a_stopped_advertisement() {
+        use crate::builder::{CharacteristicBuilder, ServiceBuilder};
+        use crate::history::History;
+        use crate::mock::MockPeripheral;
+
+        let declared = ServiceBuilder::new(parse_uuid("180F").unwrap())
+            // Declared without the CCCD the device adds to it.
+            .characteristic(Characteristic {
+                uuid: parse_uuid("2A19").unwrap(),
+                properties: vec![CharacteristicProperty::Read, CharacteristicProperty::Notify],
+                permissions: vec![AttributePermission::Readable],
+                value: None,
+                descriptors: Vec::new(),
+            })
+            .build()
+            .unwrap();
+        let (mock, _events) = MockPeripheral::channel(1);
+        let mut device = Device::mock(mock);
+        device.add_service(&declared).await.unwrap();
+        device
+            .add_characteristic(
+                CharacteristicBuilder::new(parse_uuid("2A6E").unwrap())
+                    .readable()
+                    .build()
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        device
+            .start_advertising("Lamp", &[declared.uuid])
+            .await
+            .unwrap();
+        device.stop_advertising().await.unwrap();
+        let switches = SwitchBank::new(&[], SwitchState::Off, &History::new(0));
+
+        let path = temp_file("capture", "saved.toml", "");
+        let config = Config::capture(&device, &switches, &TransitionTable::default(), 0);
+        save_config(&path, &config, true).unwrap();
+        let saved = load_config(&path).unwrap();
+
+        let advertising = saved.advertising.expect("advertising was saved");
+        assert_eq!(advertising.name.as_deref(), Some("Lamp"));
+        assert_eq!(advertising.service_uuids, [declared.uuid]);
+        assert_eq!(saved.services.len(), 1);
+        assert_eq!(saved.services[0].uuid, declared.uuid);
+        assert!(saved.services[0].characteristics[0].descriptors.is_empty());
+        assert_eq!(layout(&saved), layout(&config));
+    }
+
+    /// The services as `save_config` would write them, as bytes to compare.
+    fn layout(config: &Config) -> Vec<u8> {
+        let raw = services_to_raw(
+            &config.services,
+            &config.mirrors,
+            &config.strict_formats,
+            format_uuid,
+        );
+        serde_json::to_vec(&raw).unwrap()
+    }
+
+    #[test]
+    fn yaml_and_toml_give_the_same_layout() {
+        let toml = r#"
+[[services]]
+uuid = "1234"
+
+[[services.characteristics]]
+uuid = "2A19"
+properties = ["read", "notify"]
+permissions = ["readable"]
+value = [100]
+
+[[services.characteristics.descriptors]]
+uuid = "2901"
+permissions = ["readable"]
+value = "Battery"
+
+[[services.characteristics]]
+uuid = "12345678-1234-5678-1234-56789abcdef0"
+properties = ["write"]
+permissions = ["writeable"]
+value = "off"
+"#;
+        let yaml = r#"
+services:
+  - uuid: "1234"
+    characteristics:
+      - uuid: "2A19"
+        properties: [read, notify]
+        permissions: [readable]
+        value: [100]
+        descriptors:
+          - uuid: "2901"
+            permissions: [readable]
+            value: Battery
+      - uuid: 12345678-1234-5678-1234-56789abcdef0
+        properties: [write]
+        permissions: [writeable]
+        value: "off"
+"#;
+        let from_toml = load_config(&temp_file("formats", "services.toml", toml)).unwrap();
+        let from_yaml = load_config(&temp_file("formats", "services.yaml", yaml)).unwrap();
+        assert_eq!(from_toml.services.len(), 1);
+        assert_eq!(from_toml.services[0].characteristics.len(), 2);
+        assert_eq!(layout(&from_yaml), layout(&from_toml));
+    }
+}
@@ -0,0 +1,696 @@
+use std::fmt;
+use std::path::PathBuf;
+
+use uuid::Uuid;
+
+use ble_peripheral_rust::gatt::properties::CharacteristicProperty;
+
+use crate::aliases::Aliases;
+use crate::central::CentralId;
+use crate::config::{parse_property, parse_uuid};
+use crate::profile::switch::{self, SwitchState};
+
+/// A console command and the usage line `help` prints for it.
+pub struct CommandInfo {
+    pub name: &'static str,
+    pub usage: &'static str,
+    pub summary: &'static str,
+}
+
+/// Every console command, in the order `help` lists them.
+pub const COMMANDS: &[CommandInfo] = &[
+    CommandInfo {
+        name: "help",
+        usage: "help",
+        summary: "list the console commands",
+    },
+    CommandInfo {
+        name: "status",
+        usage: "status",
+        summary: "print the switches, advertising and subscribers",
+    },
+    CommandInfo {
+        name: "gatt",
+        usage: "gatt",
+        summary: "print the registered GATT table with values and subscribers",
+    },
+    CommandInfo {
+        name: "subs",
+        usage: "subs",
+        summary: "list who is subscribed to what, and for how long",
+    },
+    CommandInfo {
+        name: "on",
+        usage: "on|off",
+        summary: "switch the primary switch (aliases work too)",
+    },
+    CommandInfo {
+        name: "set",
+        usage: "set <name> on|off",
+        summary: "switch one named switch",
+    },
+    CommandInfo {
+        name: "brightness",
+        usage: "brightness <0-255>",
+        summary: "set the brightness level",
+    },
+    CommandInfo {
+        name: "adv",
+        usage: "adv start [name]|stop",
+        summary: "resume advertising, optionally renamed, or pause it",
+    },
+    CommandInfo {
+        name: "name",
+        usage: "name <new-name>",
+        summary: "change the advertised local name",
+    },
+    CommandInfo {
+        name: "send",
+        usage: "send <text>",
+        summary: "write text to the console characteristic",
+    },
+    CommandInfo {
+        name: "write",
+        usage: "write <uuid> <text>",
+        summary: "write text to any characteristic, as a central would",
+    },
+    CommandInfo {
+        name: "hex",
+        usage: "hex [uuid] <bytes>",
+        summary: "write raw bytes, e.g. 01ff00, to a characteristic",
+    },
+    CommandInfo {
+        name: "char",
+        usage: "char add <uuid> [read,write,notify]|remove <uuid>",
+        summary: "add an echoing characteristic, or remove one",
+    },
+    CommandInfo {
+        name: "timeout",
+        usage: "timeout <secs>",
+        summary: "change the auto-off delay, 0 disables it",
+    },
+    CommandInfo {
+        name: "stats",
+        usage: "stats [reset]",
+        summary: "print event counters since startup, or zero them",
+    },
+    CommandInfo {
+        name: "history",
+        usage: "history",
+        summary: "print the recent state changes",
+    },
+    CommandInfo {
+        name: "pair",
+        usage: "pair accept|reject",
+        summary: "answer a waiting pairing request",
+    },
+    CommandInfo {
+        name: "disconnect",
+        usage: "disconnect <central>",
+        summary: "drop a connected central",
+    },
+    CommandInfo {
+        name: "snapshot",
+        usage: "snapshot <path>",
+        summary: "write the runtime state to a JSON file",
+    },
+    CommandInfo {
+        name: "save-config",
+        usage: "save-config <path> [--force]",
+        summary: "write the running layout back out as TOML",
+    },
+    CommandInfo {
+        name: "reload",
+        usage: "reload",
+        summary: "re-read the service config",
+    },
+    CommandInfo {
+        name: "quit",
+        usage: "quit",
+        summary: "stop advertising and exit",
+    },
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Help,
+    Status,
+    Quit,
+    Subscriptions,
+    Gatt,
+    /// `adv stop`: stop advertising without dropping connections.
+    AdvertiseStop,
+    /// `adv start [name]`: advertise what was advertised before, under a new
+    /// name if one is given.
+    AdvertiseStart(Option<String>),
+    /// A new local name to advertise under, exactly as typed.
+    Rename(String),
+    /// Text to write to the console characteristic, exactly as typed.
+    Send(String),
+    /// Text for any characteristic, handled like a central's write.
+    Write {
+        characteristic: Uuid,
+        text: String,
+    },
+    /// Raw bytes for a characteristic, or the console characteristic when
+    /// `characteristic` is `None`.
+    Hex {
+        characteristic: Option<Uuid>,
+        value: Vec<u8>,
+    },
+    /// `char add`: a characteristic that echoes what is written to it.
+    AddCharacteristic {
+        uuid: Uuid,
+        properties: Vec<CharacteristicProperty>,
+    },
+    RemoveCharacteristic(Uuid),
+    /// `on`, `off` or an alias, for the primary switch.
+    Power(SwitchState),
+    Set {
+        switch: String,
+        state: SwitchState,
+    },
+    Brightness(u8),
+    Timeout(u64),
+    /// `stats` prints the event counters, `stats reset` zeroes them.
+    Stats {
+        reset: bool,
+    },
+    History,
+    Pair {
+        accept: bool,
+    },
+    Disconnect(CentralId),
+    Snapshot(PathBuf),
+    SaveConfig {
+        path: PathBuf,
+        force: bool,
+    },
+    Reload,
+}
+
+impl Command {
+    /// The command word, as `--output json` acknowledges it. `on`, `off`
+    /// and aliases are all `power`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Command::Help => "help",
+            Command::Status => "status",
+            Command::Quit => "quit",
+            Command::Subscriptions => "subs",
+            Command::Gatt => "gatt",
+            Command::AdvertiseStop | Command::AdvertiseStart(_) => "adv",
+            Command::Rename(_) => "name",
+            Command::Send(_) => "send",
+            Command::Write { .. } => "write",
+            Command::Hex { .. } => "hex",
+            Command::AddCharacteristic { .. } | Command::RemoveCharacteristic(_) => "char",
+            Command::Power(_) => "power",
+            Command::Set { .. } => "set",
+            Command::Brightness(_) => "brightness",
+            Command::Timeout(_) => "timeout",
+            Command::Stats { .. } => "stats",
+            Command::History => "history",
+            Command::Pair { .. } => "pair",
+            Command::Disconnect(_) => "disconnect",
+            Command::Snapshot(_) => "snapshot",
+            Command::SaveConfig { .. } => "save-config",
+            Command::Reload => "reload",
+        }
+    }
+}
+
+/// Why a console line is not a command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// The line was blank.
+    Empty,
+    /// A known command with missing or malformed arguments.
+    Usage(&'static str),
+    /// A known command with an argument that does not parse.
+    Invalid(String),
+    /// The first word is not a command, with the closest one if any is near.
+    Unknown {
+        input: String,
+        suggestion: Option<&'static str>,
+    },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Empty => write!(f, "empty command"),
+            ParseError::Usage(usage) => write!(f, "Usage: {}", usage),
+            ParseError::Invalid(message) => write!(f, "{}", message),
+            ParseError::Unknown {
+                input,
+                suggestion: Some(suggestion),
+            } => write!(
+                f,
+                "Unknown command '{}', did you mean '{}'? Type 'help' for a list.",
+                input, suggestion
+            ),
+            ParseError::Unknown {
+                input,
+                suggestion: None,
+            } => write!(
+                f,
+                "Unknown command '{}'. Use 'send {}' to write it to the characteristic, \
+                 or 'help' for a list.",
+                input, input
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+/// Parses one console line. Arguments are split on whitespace, except for
+/// `send`, `name`, the text given to `write` and the name given to
+/// `adv start`, which keep the rest of the line. Anything else that is one
+/// of `aliases` switches the primary switch.
+pub fn parse(line: &str, aliases: &Aliases) -> Result<Command, ParseError> {
+    let line = line.trim();
+    let (word, rest) = line
+        .split_once(char::is_whitespace)
+        .map_or((line, ""), |(word, rest)| (word, rest.trim_start()));
+    let mut args = rest.split_whitespace();
+    let command = match word.to_lowercase().as_str() {
+        "" => return Err(ParseError::Empty),
+        "help" => Command::Help,
+        "status" => Command::Status,
+        "quit" => Command::Quit,
+        "subs" => Command::Subscriptions,
+        "gatt" => Command::Gatt,
+        "history" => Command::History,
+        "reload" => Command::Reload,
+        "adv" => match rest.split_once(char::is_whitespace) {
+            _ if rest.eq_ignore_ascii_case("stop") => Command::AdvertiseStop,
+            _ if rest.eq_ignore_ascii_case("start") => Command::AdvertiseStart(None),
+            Some((action, name)) if action.eq_ignore_ascii_case("start") => {
+                Command::AdvertiseStart(Some(name.trim().to_string()))
+            }
+            _ => return Err(usage("adv")),
+        },
+        "name" if !rest.is_empty() => Command::Rename(rest.to_string()),
+        "send" if !rest.is_empty() => Command::Send(rest.to_string()),
+        "write" => match rest.split_once(char::is_whitespace) {
+            Some((uuid, text)) => Command::Write {
+                characteristic: parse_uuid(uuid).map_err(ParseError::Invalid)?,
+                text: text.trim_start().to_string(),
+            },
+            None => return Err(usage("write")),
+        },
+        "set" => match (args.next(), args.next().and_then(switch::resolve)) {
+            (Some(name), Some(state)) => Command::Set {
+                switch: name.to_string(),
+                state,
+            },
+            _ => return Err(usage("set")),
+        },
+        "hex" => match (args.next(), args.next(), args.next()) {
+            (Some(bytes), None, None) => Command::Hex {
+                characteristic: None,
+                value: parse_hex(bytes)?,
+            },
+            (Some(uuid), Some(bytes), None) => Command::Hex {
+                characteristic: Some(parse_uuid(uuid).map_err(ParseError::Invalid)?),
+                value: parse_hex(bytes)?,
+            },
+            _ => return Err(usage("hex")),
+        },
+        "char" => match (args.next(), args.next(), args.next(), args.next()) {
+            (Some("add"), Some(uuid), properties, None) => Command::AddCharacteristic {
+                uuid: parse_uuid(uuid).map_err(ParseError::Invalid)?,
+                properties: parse_properties(properties.unwrap_or(DEFAULT_PROPERTIES))?,
+            },
+            (Some("remove"), Some(uuid), None, None) => {
+                Command::RemoveCharacteristic(parse_uuid(uuid).map_err(ParseError::Invalid)?)
+            }
+            _ => return Err(usage("char")),
+        },
+        "brightness" => match args.next().and_then(|level| level.parse().ok()) {
+            Some(level) => Command::Brightness(level),
+            None => return Err(usage("brightness")),
+        },
+        "timeout" => match args.next().and_then(|secs| secs.parse().ok()) {
+            Some(secs) => Command::Timeout(secs),
+            None => return Err(usage("timeout")),
+        },
+        "stats" => match (args.next(), args.next()) {
+            (None, _) => Command::Stats { reset: false },
+            (Some("reset"), None) => Command::Stats { reset: true },
+            _ => return Err(usage("stats")),
+        },
+        "pair" => match args.next() {
+            Some("accept") => Command::Pair { accept: true },
+            Some("reject") => Command::Pair { accept: false },
+            _ => return Err(usage("pair")),
+        },
+        "disconnect" => match args.next() {
+            Some(central) => Command::Disconnect(CentralId(central.to_string())),
+            None => return Err(usage("disconnect")),
+        },
+        "snapshot" => match args.next() {
+            Some(path) => Command::Snapshot(PathBuf::from(path)),
+            None => return Err(usage("snapshot")),
+        },
+        "save-config" => {
+            let mut force = false;
+            let mut path = None;
+            for arg in args {
+                match arg {
+                    "--force" => force = true,
+                    _ => path = Some(PathBuf::from(arg)),
+                }
+            }
+            match path {
+                Some(path) => Command::SaveConfig { path, force },
+                None => return Err(usage("save-config")),
+            }
+        }
+        _ => match switch::resolve(line, aliases) {
+            Some(state) => Command::Power(state),
+            None if word.eq_ignore_ascii_case("send") => return Err(usage("send")),
+            None if word.eq_ignore_ascii_case("name") => return Err(usage("name")),
+            None => {
+                return Err(ParseError::Unknown {
+                    input: line.to_string(),
+                    suggestion: suggest(word),
+                })
+            }
+        },
+    };
+    Ok(command)
+}
+
+/// The text `help` prints.
+pub fn help() -> String {
+    let width = COMMANDS
+        .iter()
+        .map(|info| info.usage.len())
+        .max()
+        .unwrap_or(0);
+    COMMANDS
+        .iter()
+        .map(|info| format!("  {:width$}  {}\n", info.usage, info.summary, width = width))
+        .collect()
+}
+
+/// Properties `char add` gives a characteristic when none are listed.
+const DEFAULT_PROPERTIES: &str = "read,write,notify";
+
+/// Parses comma-separated property names as in the config file.
+fn parse_properties(list: &str) -> Result<Vec<CharacteristicProperty>, ParseError> {
+    list.split(',')
+        .map(|name| {
+            parse_property(name.trim())
+                .ok_or_else(|| ParseError::Invalid(format!("unknown property '{}'", name)))
+        })
+        .collect()
+}
+
+/// Decodes an even number of hex digits, e.g. `01ff00`, into bytes.
+fn parse_hex(digits: &str) -> Result<Vec<u8>, ParseError> {
+    if !digits.bytes().all(|digit| digit.is_ascii_hexdigit()) {
+        return Err(ParseError::Invalid(format!(
+            "'{}' is not valid hex",
+            digits
+        )));
+    }
+    if digits.len() % 2 != 0 {
+        return Err(ParseError::Invalid(format!(
+            "'{}' has an odd number of hex digits",
+            digits
+        )));
+    }
+    Ok((0..digits.len())
+        .step_by(2)
+        .map(|start| u8::from_str_radix(&digits[start..start + 2], 16).unwrap_or_default())
+        .collect())
+}
+
+fn usage(name: &str) -> ParseError {
+    let usage = COMMANDS
+        .iter()
+        .find(|info| info.name == name)
+        .map_or("help", |info| info.usage);
+    ParseError::Usage(usage)
+}
+
+/// The command a mistyped word was most likely meant to be: one at most two
+/// edits away, or one it is a prefix of.
+fn suggest(word: &str) -> Option<&'static str> {
+    let word = word.to_lowercase();
+    COMMANDS
+        .iter()
+        .map(|info| info.name)
+        .chain(["off"])
+        .map(|name| (edit_distance(&word, name), name))
+        .filter(|&(distance, name)| {
+            distance <= 2.min(name.len() / 2) || (word.len() >= 2 && name.starts_with(&word))
+        })
+        .min_by_key(|&(distance, _)| distance)
+        .map(|(_, name)| name)
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, &cb) in b.iter().enumerate() {
+            let substitution = diagonal + usize::from(ca != cb);
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(diagonal + 1);
+        }
+    }
+    row[b.len()]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(line: &str) -> Result<Command, ParseError> {
+        super::parse(line, &Aliases::default())
+    }
+
+    fn uuid(text: &str) -> Uuid {
+        parse_uuid(text).unwrap()
+    }
+
+    #[test]
+    fn parses_commands_without_arguments() {
+        let cases = [
+            ("help", Command::Help),
+            ("status", Command::Status),
+            ("quit", Command::Quit),
+            ("subs", Command::Subscriptions),
+            ("gatt", Command::Gatt),
+            ("history", Command::History),
+            ("reload", Command::Reload),
+            ("  STATUS  ", Command::Status),
+        ];
+        for (line, command) in cases {
+            assert_eq!(parse(line), Ok(command), "{:?}", line);
+        }
+    }
+
+    #[test]
+    fn parses_power_and_set() {
+        assert_eq!(parse("on"), Ok(Command::Power(SwitchState::On)));
+        assert_eq!(parse("OFF"), Ok(Command::Power(SwitchState::Off)));
+        let aliases = Aliases::from_pairs([("an", "on")]).unwrap();
+        assert_eq!(
+            super::parse("An", &aliases),
+            Ok(Command::Power(SwitchState::On))
+        );
+        assert!(matches!(parse("an"), Err(ParseError::Unknown { .. })));
+        assert_eq!(
+            parse("set lamp on"),
+            Ok(Command::Set {
+                switch: "lamp".to_string(),
+                state: SwitchState::On,
+            })
+        );
+        assert_eq!(
+            parse("set lamp"),
+            Err(ParseError::Usage("set <name> on|off"))
+        );
+        assert_eq!(
+            parse("set lamp dim"),
+            Err(ParseError::Usage("set <name> on|off"))
+        );
+    }
+
+    #[test]
+    fn parses_advertising_commands() {
+        assert_eq!(parse("adv stop"), Ok(Command::AdvertiseStop));
+        assert_eq!(parse("adv start"), Ok(Command::AdvertiseStart(None)));
+        assert_eq!(
+            parse("adv start Kitchen Lamp"),
+            Ok(Command::AdvertiseStart(Some("Kitchen Lamp".to_string())))
+        );
+        assert_eq!(
+            parse("adv pause"),
+            Err(ParseError::Usage("adv start [name]|stop"))
+        );
+        assert_eq!(
+            parse("name  My Device"),
+            Ok(Command::Rename("My Device".to_string()))
+        );
+        assert_eq!(parse("name"), Err(ParseError::Usage("name <new-name>")));
+    }
+
+    #[test]
+    fn text_commands_keep_the_rest_of_the_line() {
+        assert_eq!(
+            parse("send hello  world"),
+            Ok(Command::Send("hello  world".to_string()))
+        );
+        assert_eq!(parse("send"), Err(ParseError::Usage("send <text>")));
+        assert_eq!(
+            parse("write 2A19 50 %"),
+            Ok(Command::Write {
+                characteristic: uuid("2A19"),
+                text: "50 %".to_string(),
+            })
+        );
+        assert_eq!(
+            parse("write 2A19"),
+            Err(ParseError::Usage("write <uuid> <text>"))
+        );
+        assert!(matches!(
+            parse("write not-a-uuid hi"),
+            Err(ParseError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn parses_hex() {
+        assert_eq!(
+            parse("hex 01ff00"),
+            Ok(Command::Hex {
+                characteristic: None,
+                value: vec![0x01, 0xFF, 0x00],
+            })
+        );
+        assert_eq!(
+            parse("hex 2A19 64"),
+            Ok(Command::Hex {
+                characteristic: Some(uuid("2A19")),
+                value: vec![0x64],
+            })
+        );
+        assert_eq!(
+            parse("hex 0g"),
+            Err(ParseError::Invalid("'0g' is not valid hex".to_string()))
+        );
+        assert_eq!(
+            parse("hex 123"),
+            Err(ParseError::Invalid(
+                "'123' has an odd number of hex digits".to_string()
+            ))
+        );
+        assert_eq!(parse("hex"), Err(ParseError::Usage("hex [uuid] <bytes>")));
+    }
+
+    #[test]
+    fn parses_char() {
+        assert_eq!(
+            parse("char add 2A19"),
+            Ok(Command::AddCharacteristic {
+                uuid: uuid("2A19"),
+                properties: vec![
+                    CharacteristicProperty::Read,
+                    CharacteristicProperty::Write,
+                    CharacteristicProperty::Notify,
+                ],
+            })
+        );
+        assert_eq!(
+            parse("char add 2A19 read"),
+            Ok(Command::AddCharacteristic {
+                uuid: uuid("2A19"),
+                properties: vec![CharacteristicProperty::Read],
+            })
+        );
+        assert_eq!(
+            parse("char remove 2A19"),
+            Ok(Command::RemoveCharacteristic(uuid("2A19")))
+        );
+        assert_eq!(
+            parse("char add 2A19 read,fly"),
+            Err(ParseError::Invalid("unknown property 'fly'".to_string()))
+        );
+        assert!(matches!(parse("char drop 2A19"), Err(ParseError::Usage(_))));
+    }
+
+    #[test]
+    fn parses_numeric_arguments() {
+        assert_eq!(parse("brightness 128"), Ok(Command::Brightness(128)));
+        assert_eq!(
+            parse("brightness 256"),
+            Err(ParseError::Usage("brightness <0-255>"))
+        );
+        assert_eq!(parse("timeout 30"), Ok(Command::Timeout(30)));
+        assert_eq!(
+            parse("timeout -1"),
+            Err(ParseError::Usage("timeout <secs>"))
+        );
+    }
+
+    #[test]
+    fn parses_the_remaining_commands() {
+        assert_eq!(parse("stats"), Ok(Command::Stats { reset: false }));
+        assert_eq!(parse("stats reset"), Ok(Command::Stats { reset: true }));
+        assert_eq!(
+            parse("stats clear"),
+            Err(ParseError::Usage("stats [reset]"))
+        );
+        assert_eq!(parse("pair accept"), Ok(Command::Pair { accept: true }));
+        assert_eq!(parse("pair reject"), Ok(Command::Pair { accept: false }));
+        assert_eq!(parse("pair"), Err(ParseError::Usage("pair accept|reject")));
+        assert_eq!(
+            parse("disconnect AA:BB"),
+            Ok(Command::Disconnect(CentralId("AA:BB".to_string())))
+        );
+        assert_eq!(
+            parse("snapshot state.json"),
+            Ok(Command::Snapshot(PathBuf::from("state.json")))
+        );
+        assert_eq!(
+            parse("save-config --force out.toml"),
+            Ok(Command::SaveConfig {
+                path: PathBuf::from("out.toml"),
+                force: true,
+            })
+        );
+        assert_eq!(
+            parse("save-config --force"),
+            Err(ParseError::Usage("save-config <path> [--force]"))
+        );
+    }
+
+    #[test]
+    fn rejects_blank_and_unknown_lines() {
+        assert_eq!(parse(""), Err(ParseError::Empty));
+        assert_eq!(parse("   "), Err(ParseError::Empty));
+        assert_eq!(
+            parse("stauts"),
+            Err(ParseError::Unknown {
+                input: "stauts".to_string(),
+                suggestion: Some("status"),
+            })
+        );
+        assert_eq!(
+            parse("xyzzy"),
+            Err(ParseError::Unknown {
+                input: "xyzzy".to_string(),
+                suggestion: None,
+            })
+        );
+    }
+}
@@ -0,0 +1,99 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use uuid::Uuid;
+
+use crate::device::Device;
+
+/// Coalesces notifications per characteristic, so a central hammering writes
+/// produces one notification per window carrying the last value instead of
+/// one per write.
+#[derive(Debug)]
+pub struct NotifyDebouncer {
+    window: Duration,
+    pending: Arc<Mutex<HashMap<Uuid, Vec<u8>>>>,
+}
+
+impl NotifyDebouncer {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            pending: Arc::default(),
+        }
+    }
+
+    pub fn window(&self) -> Duration {
+        self.window
+    }
+
+    /// Queues `value` for `uuid` and returns immediately.
+    ///
+    /// The first value after a quiet period opens the window; values
+    /// submitted before it closes replace it, and only the latest one is
+    /// sent when it does.
+    pub fn submit(&self, uuid: Uuid, value: Vec<u8>, device: &Arc<tokio::sync::Mutex<Device>>) {
+        if self.pending.lock().unwrap().insert(uuid, value).is_some() {
+            return;
+        }
+        let pending = self.pending.clone();
+        let window = self.window;
+        let device = device.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(window).await;
+            let Some(value) = pending.lock().unwrap().remove(&uuid) else {
+                return;
+            };
+            if let Err(err) = device.lock().await.update_characteristic(uuid, value).await {
+                log::error!("Error sending debounced update for {:?}: {:?}", uuid, err);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::central::CentralId;
+    use crate::mock::MockPeripheral;
+    use crate::profile::switch::{self, SwitchState};
+    use crate::switches::DEFAULT_SWITCH_NAME;
+
+    const WINDOW: Duration = Duration::from_millis(100);
+
+    /// A device on the mock with a subscriber to `uuid`, so every update
+    /// is sent.
+    async fn subscribed_device(uuid: Uuid) -> (MockPeripheral, Arc<tokio::sync::Mutex<Device>>) {
+        let (mock, _events) = MockPeripheral::channel(1);
+        let service = switch::service(
+            Uuid::from_u128(0x1000),
+            &[(DEFAULT_SWITCH_NAME.to_string(), uuid)],
+            SwitchState::Off,
+        )
+        .unwrap();
+        let mut device = Device::mock(mock.clone());
+        device.add_service(&service).await.unwrap();
+        device.update_subscription(CentralId("central".to_string()), uuid, true);
+        (mock, Arc::new(tokio::sync::Mutex::new(device)))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn toggles_within_the_window_send_one_notification() {
+        let uuid = Uuid::from_u128(0x1001);
+        let (mock, device) = subscribed_device(uuid).await;
+        let debouncer = NotifyDebouncer::new(WINDOW);
+        debouncer.submit(uuid, b"on".to_vec(), &device);
+        debouncer.submit(uuid, b"off".to_vec(), &device);
+        tokio::time::sleep(WINDOW / 2).await;
+        debouncer.submit(uuid, b"on".to_vec(), &device);
+        assert!(mock.sent_values(uuid).is_empty());
+
+        tokio::time::sleep(WINDOW).await;
+        assert_eq!(mock.sent_values(uuid), [b"on".to_vec()]);
+
+        // The next value opens a new window.
+        debouncer.submit(uuid, b"off".to_vec(), &device);
+        tokio::time::sleep(WINDOW * 2).await;
+        assert_eq!(mock.sent_values(uuid), [b"on".to_vec(), b"off".to_vec()]);
+    }
+}
@@ -0,0 +1,1253 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use tokio::sync::oneshot;
+use tokio_stream::Stream;
+use uuid::Uuid;
+
+use ble_peripheral_rust::{
+    gatt::{
+        characteristic::Characteristic,
+        peripheral_event::{PeripheralRequest, ReadRequestResponse, WriteRequestResponse},
+        properties::CharacteristicProperty,
+        service::Service,
+    },
+    uuid::ShortUuid,
+    Peripheral,
+};
+
+use crate::advertising::{validate_local_name, AdvertisementData, AdvertisementHandle};
+use crate::broadcaster::{self, Broadcast};
+use crate::builder::{self, CCCD_UUID};
+use crate::central::{self, CentralId};
+use crate::completion::KnownUuids;
+use crate::error::Error;
+use crate::events::{EventHub, EventNotice, RestoreStep};
+use crate::gatt_serde::GattTable;
+use crate::long_write::{Assembly, WriteAssembler};
+#[cfg(any(test, feature = "mock"))]
+use crate::mock::MockPeripheral;
+use crate::peripheral::{Backend, Capabilities, PeripheralApi};
+use crate::presentation;
+use crate::responses::ReadRequestResponseExt;
+use crate::stats::EventStats;
+use crate::subscriptions::SubscriptionRegistry;
+use crate::validate::{is_write_permission, validate_services, DEFAULT_MAX_ATTRIBUTE_LENGTH};
+
+/// Answers a `WriteRequest`, as the backend hands it over.
+pub type WriteResponder = oneshot::Sender<WriteRequestResponse>;
+
+/// ATT header bytes taken out of each notification.
+const ATT_NOTIFY_OVERHEAD: u16 = 3;
+
+/// Wraps the backend `Peripheral` and keeps track of what the app asked it to do.
+pub struct Device {
+    peripheral: Box<dyn PeripheralApi>,
+    advertisement: Option<AdvertisementData>,
+    /// Set while advertising as a non-connectable broadcaster, which goes
+    /// around the backend.
+    broadcast: Option<Broadcast>,
+    /// What was advertised before the last stop, for `restart_advertising`.
+    stopped_advertisement: Option<AdvertisementData>,
+    /// The handle `add_advertisement` gave the primary advertisement, while
+    /// it runs.
+    primary_handle: Option<AdvertisementHandle>,
+    /// Advertisements added next to the primary one, each registered with
+    /// BlueZ as a set of its own.
+    advertising_sets: BTreeMap<AdvertisementHandle, (AdvertisementData, Broadcast)>,
+    /// Advertising sets torn down because the adapter powered off, to start
+    /// again once it is back on.
+    lost_advertising_sets: BTreeMap<AdvertisementHandle, AdvertisementData>,
+    next_handle: u32,
+    services: Vec<Service>,
+    included_services: HashMap<Uuid, Vec<Uuid>>,
+    retired_characteristics: HashSet<Uuid>,
+    /// Characteristics added with `add_characteristic`, and the service each
+    /// was registered in.
+    runtime_characteristics: HashMap<Uuid, Uuid>,
+    /// Characteristics `add_service` gave a CCCD they did not declare.
+    implicit_cccds: HashSet<Uuid>,
+    subscriptions: SubscriptionRegistry,
+    mtus: HashMap<CentralId, u16>,
+    centrals: BTreeSet<CentralId>,
+    sequence_numbers: Option<HashMap<Uuid, u16>>,
+    /// Characteristics whose updates must match their CPF format's size.
+    strict_formats: HashSet<Uuid>,
+    updated_at: HashMap<Uuid, SystemTime>,
+    events: EventHub,
+    stats: Arc<EventStats>,
+    known_uuids: KnownUuids,
+    long_writes: WriteAssembler,
+    auto_restore: bool,
+    /// Set when the adapter powered off, until it is back on.
+    power_lost: bool,
+    /// Advertising that stopped because the adapter powered off.
+    advertising_lost: bool,
+}
+
+impl Device {
+    pub fn new(peripheral: Peripheral) -> Self {
+        Self::with_peripheral(Box::new(peripheral))
+    }
+
+    /// A device driving a `MockPeripheral` instead of the platform backend.
+    #[cfg(any(test, feature = "mock"))]
+    pub fn mock(peripheral: MockPeripheral) -> Self {
+        Self::with_peripheral(Box::new(peripheral))
+    }
+
+    /// A device driving any backend, e.g. one chosen per platform at runtime.
+    pub fn with_peripheral(peripheral: Box<dyn PeripheralApi>) -> Self {
+        Self {
+            peripheral,
+            advertisement: None,
+            broadcast: None,
+            stopped_advertisement: None,
+            primary_handle: None,
+            advertising_sets: BTreeMap::new(),
+            lost_advertising_sets: BTreeMap::new(),
+            next_handle: 0,
+            services: Vec::new(),
+            included_services: HashMap::new(),
+            retired_characteristics: HashSet::new(),
+            runtime_characteristics: HashMap::new(),
+            implicit_cccds: HashSet::new(),
+            subscriptions: SubscriptionRegistry::default(),
+            mtus: HashMap::new(),
+            centrals: BTreeSet::new(),
+            sequence_numbers: None,
+            strict_formats: HashSet::new(),
+            updated_at: HashMap::new(),
+            events: EventHub::default(),
+            stats: Arc::default(),
+            known_uuids: KnownUuids::default(),
+            long_writes: WriteAssembler::new(DEFAULT_MAX_ATTRIBUTE_LENGTH),
+            auto_restore: false,
+            power_lost: false,
+            advertising_lost: false,
+        }
+    }
+
+    /// Hub the event loop publishes every backend event to.
+    pub fn events(&self) -> &EventHub {
+        &self.events
+    }
+
+    /// Counters for the `stats` console command. Notifications are counted
+    /// here; the event loop counts the rest.
+    pub fn stats(&self) -> &Arc<EventStats> {
+        &self.stats
+    }
+
+    /// Stream of peripheral events for observers other than the event loop.
+    pub fn event_stream(&self) -> impl Stream<Item = EventNotice> {
+        self.events.event_stream()
+    }
+
+    /// Re-registers the services and resumes advertising, advertising sets
+    /// from `add_advertisement` included, when the adapter powers back on
+    /// after being off, publishing each step as an `EventNotice::Restore`.
+    /// Off by default: the stack drops the GATT server and advertisements on
+    /// power-off, and without this the app has to rebuild them itself.
+    pub fn set_auto_restore(&mut self, enabled: bool) {
+        self.auto_restore = enabled;
+    }
+
+    /// Tracks a `StateUpdate` from the backend and, with `set_auto_restore`,
+    /// restores what a power cycle lost.
+    pub async fn power_changed(&mut self, is_powered: bool) {
+        if !is_powered {
+            self.power_lost = true;
+            if let Some(data) = self.advertisement.take() {
+                self.broadcast = None;
+                self.stopped_advertisement = Some(data);
+                self.advertising_lost = true;
+            }
+            // BlueZ drops the sets with the adapter; unregister ours too.
+            let sets = std::mem::take(&mut self.advertising_sets);
+            self.lost_advertising_sets
+                .extend(sets.into_iter().map(|(handle, (data, _))| (handle, data)));
+            return;
+        }
+        if !std::mem::take(&mut self.power_lost) || !self.auto_restore {
+            return;
+        }
+        self.restore().await;
+    }
+
+    async fn restore(&mut self) {
+        log::info!(
+            "Adapter powered on again, restoring {} service(s)",
+            self.services.len()
+        );
+        self.publish_restore(RestoreStep::Started {
+            services: self.services.len(),
+        });
+        for service in &self.services {
+            let step = match self.peripheral.add_service(service).await {
+                Ok(()) => RestoreStep::ServiceRegistered { uuid: service.uuid },
+                Err(err) => {
+                    log::error!("Error restoring service {}: {}", service.uuid, err);
+                    RestoreStep::Failed {
+                        step: format!("service {}", service.uuid),
+                        error: err.to_string(),
+                    }
+                }
+            };
+            self.events.publish_notice(EventNotice::Restore(step));
+        }
+        if std::mem::take(&mut self.advertising_lost) {
+            let step = match self.restart_advertising(None).await {
+                Ok(()) => RestoreStep::AdvertisingResumed {
+                    name: self.local_name().unwrap_or_default().to_string(),
+                },
+                Err(err) => {
+                    log::error!("Error resuming advertising: {}", err);
+                    RestoreStep::Failed {
+                        step: "advertising".to_string(),
+                        error: err.to_string(),
+                    }
+                }
+            };
+            self.publish_restore(step);
+        }
+        for (handle, data) in std::mem::take(&mut self.lost_advertising_sets) {
+            let step = match self.start_set(&data).await {
+                Ok(broadcast) => {
+                    let name = data.advertised_name().to_string();
+                    self.advertising_sets.insert(handle, (data, broadcast));
+                    RestoreStep::AdvertisingResumed { name }
+                }
+                Err(err) => {
+                    log::error!("Error resuming advertisement {}: {}", handle, err);
+                    RestoreStep::Failed {
+                        step: format!("advertisement {}", handle),
+                        error: err.to_string(),
+                    }
+                }
+            };
+            self.publish_restore(step);
+        }
+        self.publish_restore(RestoreStep::Finished);
+    }
+
+    /// Registers `data` as an advertising set with BlueZ, or only in the
+    /// device with the mock backend.
+    async fn start_set(&mut self, data: &AdvertisementData) -> Result<Broadcast, Error> {
+        #[cfg(any(test, feature = "mock"))]
+        if self.backend() == Backend::Mock {
+            return Ok(Broadcast::detached());
+        }
+        broadcaster::start(data).await
+    }
+
+    fn publish_restore(&self, step: RestoreStep) {
+        self.events.publish_notice(EventNotice::Restore(step));
+    }
+
+    /// The platform stack this device drives, e.g. to skip what it returns
+    /// `Error::UnsupportedOperation` for.
+    pub fn backend(&self) -> Backend {
+        self.peripheral.backend()
+    }
+
+    pub fn capabilities(&self) -> Capabilities {
+        self.peripheral.capabilities()
+    }
+
+    pub async fn is_powered(&mut self) -> Result<bool, Error> {
+        self.peripheral.is_powered().await
+    }
+
+    /// Reports whether advertising is active.
+    ///
+    /// The backend is asked first so advertising stopped behind our back is
+    /// noticed; if it cannot answer, the last start/stop call decides.
+    pub async fn is_advertising(&mut self) -> Result<bool, Error> {
+        if self.broadcast.is_some() {
+            return Ok(true);
+        }
+        match self.peripheral.is_advertising().await {
+            Ok(advertising) => {
+                if !advertising && self.advertisement.is_some() {
+                    log::warn!("Advertising was stopped outside of the app");
+                    self.stopped_advertisement = self.advertisement.take();
+                }
+                Ok(advertising)
+            }
+            Err(err) => {
+                log::debug!("Backend cannot report advertising state: {}", err);
+                Ok(self.advertisement.is_some())
+            }
+        }
+    }
+
+    async fn ensure_powered(&mut self) -> Result<(), Error> {
+        if self.is_powered().await? {
+            Ok(())
+        } else {
+            Err(Error::NotPoweredOn)
+        }
+    }
+
+    /// Parses a JSON array of services in the `gatt_serde::GattTable`
+    /// layout, validates it and registers the services in order.
+    ///
+    /// Parse and validation errors such as duplicate UUIDs or a notify
+    /// characteristic without readable permission are returned as
+    /// `Error::InvalidGattTable`, naming the offending `services[index]`.
+    /// Nothing is registered unless the whole document is valid.
+    pub async fn add_services_from_reader<R: std::io::Read>(
+        &mut self,
+        reader: R,
+    ) -> Result<(), Error> {
+        let GattTable(services) = serde_json::from_reader(reader)
+            .map_err(|err| Error::InvalidGattTable(err.to_string()))?;
+        let issues = validate_services(&services, DEFAULT_MAX_ATTRIBUTE_LENGTH);
+        if !issues.is_empty() {
+            let issues: Vec<String> = issues.iter().map(ToString::to_string).collect();
+            return Err(Error::InvalidGattTable(issues.join("; ")));
+        }
+        for service in &services {
+            self.add_service(service).await?;
+        }
+        Ok(())
+    }
+
+    /// Registers a service with the backend.
+    ///
+    /// Descriptor reads are answered from the declared value and writes never
+    /// reach the app, so a writable descriptor other than the CCCD, which the
+    /// stack manages itself, is rejected with `Error::UnsupportedOperation`.
+    ///
+    /// A notify or indicate characteristic without a CCCD gets one, as the
+    /// spec requires; centrals writing to it show up as
+    /// `CharacteristicSubscriptionUpdate` events like any other CCCD.
+    pub async fn add_service(&mut self, service: &Service) -> Result<(), Error> {
+        let cccd_uuid = Uuid::from_short(CCCD_UUID);
+        let mut service = service.clone();
+        let mut implicit_cccds = Vec::new();
+        for characteristic in &mut service.characteristics {
+            if builder::needs_cccd(&characteristic.properties)
+                && !characteristic
+                    .descriptors
+                    .iter()
+                    .any(|descriptor| descriptor.uuid == cccd_uuid)
+            {
+                log::debug!("Adding a CCCD to {:?}", characteristic.uuid);
+                characteristic.descriptors.push(builder::cccd());
+                implicit_cccds.push(characteristic.uuid);
+            }
+        }
+        let writable_descriptor = service
+            .characteristics
+            .iter()
+            .flat_map(|characteristic| characteristic.descriptors.iter())
+            .any(|descriptor| {
+                descriptor.uuid != cccd_uuid
+                    && descriptor.permissions.iter().any(is_write_permission)
+            });
+        if writable_descriptor {
+            return Err(Error::UnsupportedOperation("writable descriptors"));
+        }
+        self.ensure_powered().await?;
+        self.peripheral.add_service(&service).await?;
+        for characteristic in &service.characteristics {
+            self.retired_characteristics.remove(&characteristic.uuid);
+        }
+        self.implicit_cccds.extend(implicit_cccds);
+        self.services.push(service);
+        self.refresh_known_uuids();
+        Ok(())
+    }
+
+    /// Adds a service that includes other, already added, services.
+    ///
+    /// Every UUID in `included` must name a registered service, otherwise
+    /// `Error::ServiceNotFound` is returned and nothing is added. The backend
+    /// has no way to declare include attributes, so the relationship is only
+    /// tracked here and is not visible to centrals.
+    pub async fn add_service_including(
+        &mut self,
+        service: &Service,
+        included: &[Uuid],
+    ) -> Result<(), Error> {
+        if let Some(missing) = included.iter().find(|uuid| self.service(**uuid).is_none()) {
+            return Err(Error::ServiceNotFound(*missing));
+        }
+        self.add_service(service).await?;
+        if !included.is_empty() {
+            self.included_services
+                .insert(service.uuid, included.to_vec());
+        }
+        Ok(())
+    }
+
+    /// Services included by `uuid` through `add_service_including`.
+    pub fn included_services(&self, uuid: Uuid) -> &[Uuid] {
+        self.included_services.get(&uuid).map_or(&[], Vec::as_slice)
+    }
+
+    /// Services added so far, in registration order.
+    ///
+    /// This is the app's view of the GATT table, so it is available before
+    /// the adapter is powered on and reflects `remove_service` calls.
+    pub fn services(&self) -> &[Service] {
+        &self.services
+    }
+
+    /// UUIDs of the registered services, in registration order.
+    pub fn service_uuids(&self) -> Vec<Uuid> {
+        self.services.iter().map(|service| service.uuid).collect()
+    }
+
+    /// The advertisement last started, if advertising is active.
+    pub fn advertisement(&self) -> Option<&AdvertisementData> {
+        self.advertisement.as_ref()
+    }
+
+    /// The advertisement being advertised, or the one advertising would
+    /// resume with after a stop.
+    pub fn last_advertisement(&self) -> Option<&AdvertisementData> {
+        self.advertisement
+            .as_ref()
+            .or(self.stopped_advertisement.as_ref())
+    }
+
+    /// The services as they were declared, for writing back to a config:
+    /// without those `add_characteristic` registered and without the CCCDs
+    /// `add_service` added on its own.
+    pub fn declared_services(&self) -> Vec<Service> {
+        let cccd_uuid = Uuid::from_short(CCCD_UUID);
+        let runtime: HashSet<Uuid> = self.runtime_characteristics.values().copied().collect();
+        self.services
+            .iter()
+            .filter(|service| !runtime.contains(&service.uuid))
+            .cloned()
+            .map(|mut service| {
+                for characteristic in &mut service.characteristics {
+                    if self.implicit_cccds.contains(&characteristic.uuid) {
+                        characteristic
+                            .descriptors
+                            .retain(|descriptor| descriptor.uuid != cccd_uuid);
+                    }
+                }
+                service
+            })
+            .collect()
+    }
+
+    /// Removes a service from the app's GATT table.
+    ///
+    /// The backend cannot unregister services, so the service stays visible
+    /// to centrals, but its characteristics are retired: updates are dropped
+    /// and the event loop rejects reads and writes to them.
+    pub fn remove_service(&mut self, uuid: Uuid) -> Result<(), Error> {
+        let index = self
+            .services
+            .iter()
+            .position(|service| service.uuid == uuid)
+            .ok_or(Error::ServiceNotFound(uuid))?;
+        let service = self.services.remove(index);
+        self.included_services.remove(&uuid);
+        for characteristic in &service.characteristics {
+            self.retired_characteristics.insert(characteristic.uuid);
+        }
+        self.refresh_known_uuids();
+        Ok(())
+    }
+
+    /// Registers a characteristic while running.
+    ///
+    /// No backend can add a characteristic to a service that is already
+    /// registered, so it gets a service of its own with a random UUID, which
+    /// is returned. Centrals that already discovered the GATT table have to
+    /// discover it again to see it.
+    pub async fn add_characteristic(
+        &mut self,
+        characteristic: Characteristic,
+    ) -> Result<Uuid, Error> {
+        let uuid = characteristic.uuid;
+        if self.characteristic(uuid).is_some() {
+            return Err(Error::CharacteristicExists(uuid));
+        }
+        let service = Service {
+            uuid: Uuid::new_v4(),
+            primary: true,
+            characteristics: vec![characteristic],
+        };
+        self.add_service(&service).await?;
+        self.runtime_characteristics.insert(uuid, service.uuid);
+        Ok(service.uuid)
+    }
+
+    /// Whether `uuid` was added with `add_characteristic`.
+    pub fn is_runtime_characteristic(&self, uuid: Uuid) -> bool {
+        self.runtime_characteristics.contains_key(&uuid)
+    }
+
+    /// Removes a characteristic from the app's GATT table and drops its
+    /// subscriptions.
+    ///
+    /// As with `remove_service`, the backend keeps it registered, so centrals
+    /// still see it until the app restarts; it is retired instead and
+    /// requests to it are rejected. A characteristic added with
+    /// `add_characteristic` takes its service with it.
+    pub fn remove_characteristic(&mut self, uuid: Uuid) -> Result<(), Error> {
+        if let Some(service) = self.runtime_characteristics.remove(&uuid) {
+            self.remove_service(service)?;
+        } else {
+            let service = self
+                .services
+                .iter_mut()
+                .find(|service| service.characteristics.iter().any(|c| c.uuid == uuid))
+                .ok_or(Error::CharacteristicNotFound(uuid))?;
+            service.characteristics.retain(|c| c.uuid != uuid);
+            self.retired_characteristics.insert(uuid);
+            self.refresh_known_uuids();
+        }
+        self.subscriptions.remove_characteristic(uuid);
+        Ok(())
+    }
+
+    /// Removes every service from the app's GATT table, like CoreBluetooth's
+    /// `removeAllServices`.
+    ///
+    /// Advertising is stopped first if it names any of the removed services,
+    /// so centrals are not pointed at UUIDs that no longer answer. As with
+    /// `remove_service`, the backend keeps the services registered and their
+    /// characteristics are retired instead.
+    pub async fn remove_all_services(&mut self) -> Result<(), Error> {
+        let advertises_removed = self.advertisement.as_ref().is_some_and(|data| {
+            data.all_service_uuids()
+                .iter()
+                .any(|uuid| self.service(*uuid).is_some())
+        });
+        if advertises_removed {
+            self.stop_advertising().await?;
+        }
+        self.included_services.clear();
+        for service in self.services.drain(..) {
+            for characteristic in &service.characteristics {
+                self.retired_characteristics.insert(characteristic.uuid);
+            }
+        }
+        self.refresh_known_uuids();
+        Ok(())
+    }
+
+    /// Stops every advertisement, removes every service and forgets all
+    /// centrals.
+    ///
+    /// This is what `shutdown` does, for callers that share the device and
+    /// cannot give up ownership. The backend offers no way to disconnect
+    /// centrals or unregister services, so those go away when the
+    /// `Peripheral` itself is dropped.
+    pub async fn close(&mut self) -> Result<(), Error> {
+        self.stop_advertising().await?;
+        self.advertising_sets.clear();
+        self.remove_all_services().await?;
+        let centrals = std::mem::take(&mut self.centrals);
+        for central in &centrals {
+            self.forget_central(central);
+        }
+        log::info!("Peripheral shut down");
+        Ok(())
+    }
+
+    /// Tears the peripheral down in a fixed order instead of relying on
+    /// `Drop`, releasing the backend before this returns.
+    pub async fn shutdown(mut self) -> Result<(), Error> {
+        self.close().await?;
+        drop(self.peripheral);
+        Ok(())
+    }
+
+    /// Returns the service registered under `uuid`, if any.
+    pub fn service(&self, uuid: Uuid) -> Option<&Service> {
+        self.services.iter().find(|service| service.uuid == uuid)
+    }
+
+    /// Looks up a registered characteristic by UUID across all services.
+    pub fn characteristic(&self, uuid: Uuid) -> Option<&Characteristic> {
+        self.services
+            .iter()
+            .flat_map(|service| service.characteristics.iter())
+            .find(|characteristic| characteristic.uuid == uuid)
+    }
+
+    /// Whether writes to `uuid` can only be Write Commands: the
+    /// characteristic has `WriteWithoutResponse` but not `Write`.
+    ///
+    /// The backend does not pass on the ATT opcode, so a characteristic
+    /// offering both cannot tell the two apart and its writes are treated
+    /// as requests.
+    pub fn is_write_command(&self, uuid: Uuid) -> bool {
+        self.characteristic(uuid).is_some_and(|characteristic| {
+            characteristic
+                .properties
+                .contains(&CharacteristicProperty::WriteWithoutResponse)
+                && !characteristic
+                    .properties
+                    .contains(&CharacteristicProperty::Write)
+        })
+    }
+
+    fn characteristic_mut(&mut self, uuid: Uuid) -> Option<&mut Characteristic> {
+        self.services
+            .iter_mut()
+            .flat_map(|service| service.characteristics.iter_mut())
+            .find(|characteristic| characteristic.uuid == uuid)
+    }
+
+    /// The value last passed to `update_characteristic`, or the one the
+    /// characteristic was registered with.
+    pub fn characteristic_value(&self, uuid: Uuid) -> Result<Option<Vec<u8>>, Error> {
+        self.characteristic(uuid)
+            .map(|characteristic| characteristic.value.clone())
+            .ok_or(Error::CharacteristicNotFound(uuid))
+    }
+
+    /// Answers a read from the characteristic's stored value, honouring the
+    /// offset.
+    ///
+    /// A characteristic registered with `value: Some(..)` is static: reads
+    /// return that value until `update_characteristic` replaces it. Whether
+    /// the backend answers such reads itself differs by platform, so the
+    /// event loop calls this for every read no profile handles. `None` means
+    /// the characteristic has no value and the read is left to the app.
+    pub fn stored_read(&self, uuid: Uuid, offset: u64) -> Option<ReadRequestResponse> {
+        let value = self.characteristic(uuid)?.value.clone()?;
+        Some(ReadRequestResponse::from_value(
+            self.read_envelope(uuid, value),
+            offset,
+        ))
+    }
+
+    /// Stops pushing updates to a characteristic that is no longer configured.
+    ///
+    /// The backend offers no way to unregister it, so it stays visible to
+    /// centrals but its value is frozen.
+    pub fn retire_characteristic(&mut self, uuid: Uuid) {
+        self.retired_characteristics.insert(uuid);
+        self.refresh_known_uuids();
+    }
+
+    pub fn is_retired(&self, uuid: Uuid) -> bool {
+        self.retired_characteristics.contains(&uuid)
+    }
+
+    /// A handle on the live characteristic UUIDs, e.g. for completing them
+    /// at the prompt. It stays current as services come and go.
+    pub fn known_uuids(&self) -> KnownUuids {
+        self.known_uuids.clone()
+    }
+
+    fn refresh_known_uuids(&self) {
+        let uuids = self
+            .services
+            .iter()
+            .flat_map(|service| &service.characteristics)
+            .map(|characteristic| characteristic.uuid)
+            .filter(|uuid| !self.is_retired(*uuid))
+            .collect();
+        self.known_uuids.set(uuids);
+    }
+
+    /// Starts advertising a name and service UUIDs, leaving it to the backend
+    /// to fit them into the advertising packets.
+    pub async fn start_advertising(&mut self, name: &str, uuids: &[Uuid]) -> Result<(), Error> {
+        self.ensure_powered().await?;
+        self.peripheral.start_advertising(name, uuids).await?;
+        self.advertisement = Some(AdvertisementData::new(name, uuids));
+        log::info!("Advertising started as '{}'", name);
+        Ok(())
+    }
+
+    /// Starts advertising with the given payload, rejecting it if either the
+    /// advertisement or the scan response overflows its packet.
+    ///
+    /// The backend only takes a local name and service UUIDs and splits them
+    /// across both packets itself, so manufacturer or service data is
+    /// rejected instead of being dropped, on every platform. WinRT's
+    /// `BluetoothLEAdvertisementPublisher` could carry service data in its
+    /// `DataSections`, but the backend builds the publisher internally and
+    /// does not expose it.
+    ///
+    /// Advertising parameters are rejected the same way. The platforms could
+    /// honour some of them, but the backend passes none through:
+    ///
+    /// | Parameter      | BlueZ          | CoreBluetooth | WinRT          |
+    /// |----------------|----------------|---------------|----------------|
+    /// | service data   | `ServiceData`  | no            | `DataSections` |
+    /// | interval       | `MinInterval`  | no            | no             |
+    /// | TX power       | `TxPower`      | partially     | yes            |
+    /// | appearance     | `Appearance`   | no            | `DataSections` |
+    /// | AD flags       | `Data`         | no            | no             |
+    ///
+    /// Non-connectable (broadcaster) advertising goes to BlueZ directly as
+    /// an advertisement of type `broadcast`, which can carry manufacturer
+    /// and service data, the appearance and flags, so beacons such as
+    /// `AdvertisementData::ibeacon` work there. CoreBluetooth only advertises connectable peripherals and
+    /// the backend keeps WinRT's publisher to itself, so elsewhere it returns
+    /// `Error::UnsupportedOperation`.
+    pub async fn start_advertising_with(&mut self, data: AdvertisementData) -> Result<(), Error> {
+        data.validate()?;
+        if data.has_manufacturer_or_service_data() && !data.parameters.is_broadcaster() {
+            return Err(Error::UnsupportedOperation(
+                "advertising manufacturer or service data",
+            ));
+        }
+        if data.has_appearance_or_flags() && !data.parameters.is_broadcaster() {
+            return Err(Error::UnsupportedOperation(
+                "advertising the appearance or AD flags",
+            ));
+        }
+        if data.parameters.interval_min.is_some() || data.parameters.interval_max.is_some() {
+            return Err(Error::UnsupportedOperation(
+                "setting the advertising interval",
+            ));
+        }
+        if data.parameters.tx_power_level.is_some() {
+            return Err(Error::UnsupportedOperation(
+                "setting the advertising TX power",
+            ));
+        }
+        self.ensure_powered().await?;
+        if data.parameters.is_broadcaster() {
+            self.broadcast = Some(self.start_set(&data).await?);
+            log::info!(
+                "Advertising started as '{}' (broadcaster)",
+                data.advertised_name()
+            );
+            self.advertisement = Some(data);
+            return Ok(());
+        }
+        self.peripheral
+            .start_advertising(data.advertised_name(), &data.all_service_uuids())
+            .await?;
+        log::info!("Advertising started as '{}'", data.advertised_name());
+        self.advertisement = Some(data);
+        Ok(())
+    }
+
+    /// Starts advertising again with what was advertised before the last
+    /// stop, under a new local name if one is given.
+    ///
+    /// Returns `Error::AlreadyAdvertising` instead of restarting a running
+    /// advertisement, so centrals scanning at the time do not see a gap.
+    pub async fn restart_advertising(&mut self, name: Option<&str>) -> Result<(), Error> {
+        if self.is_advertising().await? {
+            return Err(Error::AlreadyAdvertising);
+        }
+        let mut data = self
+            .stopped_advertisement
+            .clone()
+            .ok_or(Error::NoPreviousAdvertisement)?;
+        if let Some(name) = name {
+            data.local_name = name.to_string();
+        }
+        if data.parameters.is_default()
+            && !data.has_manufacturer_or_service_data()
+            && !data.has_appearance_or_flags()
+        {
+            self.start_advertising(&data.local_name, &data.service_uuids)
+                .await
+        } else {
+            self.start_advertising_with(data).await
+        }
+    }
+
+    /// The local name being advertised, or the one advertising would resume
+    /// with.
+    pub fn local_name(&self) -> Option<&str> {
+        self.last_advertisement()
+            .map(AdvertisementData::advertised_name)
+    }
+
+    /// Changes the advertised local name, keeping the service UUIDs.
+    ///
+    /// Advertising is restarted under the new name; if it is stopped, the
+    /// name is used when it resumes.
+    pub async fn set_local_name(&mut self, name: &str) -> Result<(), Error> {
+        validate_local_name(name).map_err(Error::InvalidLocalName)?;
+        if self.is_advertising().await? {
+            self.stop_advertising().await?;
+            self.restart_advertising(Some(name)).await
+        } else {
+            let data = self
+                .stopped_advertisement
+                .as_mut()
+                .ok_or(Error::NoPreviousAdvertisement)?;
+            data.local_name = name.to_string();
+            Ok(())
+        }
+    }
+
+    /// Stops advertising. Calling this while not advertising is a no-op.
+    pub async fn stop_advertising(&mut self) -> Result<(), Error> {
+        if self.advertisement.is_none() {
+            return Ok(());
+        }
+        // Dropping the handle unregisters a broadcaster advertisement.
+        if self.broadcast.take().is_none() {
+            self.peripheral.stop_advertising().await?;
+        }
+        self.stopped_advertisement = self.advertisement.take();
+        self.primary_handle = None;
+        log::info!("Advertising stopped");
+        Ok(())
+    }
+
+    /// Starts one more advertisement, e.g. an iBeacon next to the connectable
+    /// GATT advertisement, and returns the handle to remove it with.
+    ///
+    /// With nothing advertising, `data` becomes the primary advertisement as
+    /// with `start_advertising_with`. Otherwise it is registered with BlueZ
+    /// as an advertising set of its own, which can carry manufacturer and
+    /// service data whether or not it is connectable. Controllers with BLE 5
+    /// extended advertising run the sets side by side; BlueZ rotates them on
+    /// older ones and refuses sets beyond what the controller supports.
+    /// Other backends advertise one payload only and return
+    /// `Error::UnsupportedOperation` for the second.
+    pub async fn add_advertisement(
+        &mut self,
+        data: AdvertisementData,
+    ) -> Result<AdvertisementHandle, Error> {
+        let handle = AdvertisementHandle(self.next_handle);
+        if self.advertisement.is_none() {
+            self.start_advertising_with(data).await?;
+            self.next_handle += 1;
+            self.primary_handle = Some(handle);
+            return Ok(handle);
+        }
+        if !self.capabilities().supports_multiple_advertisements {
+            return Err(Error::UnsupportedOperation(
+                "more than one advertisement at a time",
+            ));
+        }
+        data.validate()?;
+        if data.parameters.interval_min.is_some() || data.parameters.interval_max.is_some() {
+            return Err(Error::UnsupportedOperation(
+                "setting the advertising interval",
+            ));
+        }
+        if data.parameters.tx_power_level.is_some() {
+            return Err(Error::UnsupportedOperation(
+                "setting the advertising TX power",
+            ));
+        }
+        self.ensure_powered().await?;
+        let broadcast = self.start_set(&data).await?;
+        self.next_handle += 1;
+        log::info!(
+            "Advertisement {} started as '{}'",
+            handle,
+            data.advertised_name()
+        );
+        self.advertising_sets.insert(handle, (data, broadcast));
+        Ok(handle)
+    }
+
+    /// Stops an advertisement from `add_advertisement`. Removing the primary
+    /// one is `stop_advertising`; the others keep running either way.
+    pub async fn remove_advertisement(&mut self, handle: AdvertisementHandle) -> Result<(), Error> {
+        if self.primary_handle == Some(handle) {
+            return self.stop_advertising().await;
+        }
+        // Dropping the registration unregisters the set.
+        self.advertising_sets
+            .remove(&handle)
+            .ok_or(Error::AdvertisementNotFound(handle))?;
+        log::info!("Advertisement {} stopped", handle);
+        Ok(())
+    }
+
+    /// The advertisements `add_advertisement` started that are still
+    /// running, primary first.
+    pub fn advertisements(&self) -> Vec<(AdvertisementHandle, &AdvertisementData)> {
+        let primary = self.primary_handle.zip(self.advertisement.as_ref());
+        primary
+            .into_iter()
+            .chain(
+                self.advertising_sets
+                    .iter()
+                    .map(|(handle, (data, _))| (*handle, data)),
+            )
+            .collect()
+    }
+
+    /// Sets and notifies several characteristics under one borrow of the
+    /// device, e.g. a state and the timestamp that goes with it.
+    ///
+    /// Every update is attempted even if an earlier one fails; the result
+    /// for each is returned in the order given.
+    pub async fn update_characteristics(
+        &mut self,
+        updates: Vec<(Uuid, Vec<u8>)>,
+    ) -> Vec<Result<(), Error>> {
+        let mut results = Vec::with_capacity(updates.len());
+        for (uuid, value) in updates {
+            results.push(self.update_characteristic(uuid, value).await);
+        }
+        results
+    }
+
+    /// Sets a characteristic's value and notifies its subscribers. With no
+    /// subscribers the value is only stored, for reads.
+    ///
+    /// Values are never split across notifications: if the value is longer
+    /// than `mtu - 3` for a subscriber whose MTU was given to `record_mtu`,
+    /// the update is rejected with `Error::ValueTooLargeForMtu` and nothing
+    /// is sent. No backend reports the MTU, so without `record_mtu` the
+    /// platform truncates or rejects oversized values itself.
+    /// Characteristics marked with `set_strict_format` also reject values
+    /// of the wrong width.
+    ///
+    /// The backend does not say whether the platform queued or sent the
+    /// notification (CoreBluetooth's `updateValue` returning false is
+    /// swallowed), so a successful return only means the backend accepted
+    /// the value. Callers sending bursts should pace themselves.
+    pub async fn update_characteristic(&mut self, uuid: Uuid, value: Vec<u8>) -> Result<(), Error> {
+        if self.is_retired(uuid) {
+            log::debug!("Dropping update for retired characteristic {:?}", uuid);
+            return Ok(());
+        }
+        self.check_format(uuid, &value)?;
+        let sequence = self
+            .sequence_numbers
+            .as_ref()
+            .map(|counters| counters.get(&uuid).copied().unwrap_or(0).wrapping_add(1));
+        let payload = match sequence {
+            Some(sequence) => envelope(sequence, &value),
+            None => value.clone(),
+        };
+        if let Some(limit) = self.notify_limit(uuid) {
+            if payload.len() > limit {
+                return Err(Error::ValueTooLargeForMtu {
+                    uuid,
+                    size: payload.len(),
+                    limit,
+                });
+            }
+        }
+        if !self.subscriptions.is_subscribed(uuid) {
+            log::debug!(
+                "Nobody is subscribed to {:?}, storing without notifying",
+                uuid
+            );
+        } else {
+            self.peripheral.update_characteristic(uuid, payload).await?;
+            self.stats.record_notification();
+            if let (Some(counters), Some(sequence)) = (&mut self.sequence_numbers, sequence) {
+                counters.insert(uuid, sequence);
+            }
+        }
+        if let Some(characteristic) = self.characteristic_mut(uuid) {
+            characteristic.value = Some(value);
+        }
+        self.updated_at.insert(uuid, SystemTime::now());
+        Ok(())
+    }
+
+    /// Makes `update_characteristic` reject values for `uuid` that are not
+    /// as long as its Presentation Format descriptor (0x2904) declares,
+    /// e.g. 2 bytes for `uint16`, with `Error::InvalidAttributeLength`.
+    ///
+    /// Off by default. Characteristics without a CPF descriptor, or with a
+    /// variable-length format such as `utf8s`, are never checked.
+    pub fn set_strict_format(&mut self, uuid: Uuid, strict: bool) {
+        if strict {
+            self.strict_formats.insert(uuid);
+        } else {
+            self.strict_formats.remove(&uuid);
+        }
+    }
+
+    pub fn is_strict_format(&self, uuid: Uuid) -> bool {
+        self.strict_formats.contains(&uuid)
+    }
+
+    fn check_format(&self, uuid: Uuid, value: &[u8]) -> Result<(), Error> {
+        if !self.is_strict_format(uuid) {
+            return Ok(());
+        }
+        match self
+            .characteristic(uuid)
+            .and_then(presentation::declared_len)
+        {
+            Some(expected) if value.len() != expected => Err(Error::InvalidAttributeLength {
+                uuid,
+                expected,
+                actual: value.len(),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    /// When `update_characteristic` last sent a value for `uuid`.
+    pub fn updated_at(&self, uuid: Uuid) -> Option<SystemTime> {
+        self.updated_at.get(&uuid).copied()
+    }
+
+    /// Prefixes every value sent by `update_characteristic` with a
+    /// little-endian `u16` that counts up per characteristic and wraps after
+    /// 65535, so subscribers can spot missed notifications.
+    ///
+    /// This changes the wire format; read responses passed through
+    /// `read_envelope` carry the last number sent so centrals can resync.
+    pub fn enable_sequence_numbers(&mut self) {
+        self.sequence_numbers.get_or_insert_with(HashMap::new);
+    }
+
+    /// The sequence number last sent on `uuid`, if sequence numbers are on.
+    pub fn sequence_number(&self, uuid: Uuid) -> Option<u16> {
+        self.sequence_numbers
+            .as_ref()
+            .map(|counters| counters.get(&uuid).copied().unwrap_or(0))
+    }
+
+    /// Wraps a read response the same way notifications are wrapped, using
+    /// the last sequence number sent. Without sequence numbers `value` is
+    /// returned unchanged.
+    pub fn read_envelope(&self, uuid: Uuid, value: Vec<u8>) -> Vec<u8> {
+        match self.sequence_number(uuid) {
+            Some(sequence) => envelope(sequence, &value),
+            None => value,
+        }
+    }
+
+    /// Sends `value` as an indication, which centrals have to acknowledge.
+    ///
+    /// The backend chooses indication over notification from the
+    /// characteristic's properties, so this only succeeds for characteristics
+    /// declared with `Indicate`. Acknowledgements are handled by the stack and
+    /// are not reported back.
+    pub async fn indicate_characteristic(
+        &mut self,
+        uuid: Uuid,
+        value: Vec<u8>,
+    ) -> Result<(), Error> {
+        let characteristic = self
+            .characteristic(uuid)
+            .ok_or(Error::CharacteristicNotFound(uuid))?;
+        if !characteristic
+            .properties
+            .contains(&CharacteristicProperty::Indicate)
+        {
+            return Err(Error::UnsupportedOperation(
+                "indicating a characteristic without the Indicate property",
+            ));
+        }
+        self.update_characteristic(uuid, value).await
+    }
+
+    /// Reads the signal strength of a connection.
+    ///
+    /// | Platform             | Peripheral-side RSSI                          |
+    /// |----------------------|-----------------------------------------------|
+    /// | macOS/iOS            | not available, CoreBluetooth only offers it   |
+    /// |                      | to centrals                                   |
+    /// | Linux (BlueZ)        | the controller knows it, but the backend does |
+    /// |                      | not expose connections                        |
+    /// | Windows (WinRT)      | not available for GATT server sessions        |
+    ///
+    /// None of them can answer through this backend, so this always returns
+    /// `Error::UnsupportedOperation` rather than a made-up value.
+    pub fn read_rssi(&self, central: &CentralId) -> Result<i16, Error> {
+        log::debug!("RSSI requested for central {}", central);
+        Err(Error::UnsupportedOperation(
+            "reading the RSSI of a connected central",
+        ))
+    }
+
+    /// Records the ATT MTU negotiated with `central` and publishes
+    /// `EventNotice::MtuChanged`.
+    ///
+    /// Unsupported on every backend: ble-peripheral-rust reports no MTU
+    /// exchanges and keeps BlueZ's per-request `mtu` to itself, so nothing
+    /// in this crate calls this. It is for integrations that learn the MTU
+    /// some other way; `Capabilities::reports_mtu` is false until a backend
+    /// does.
+    pub fn record_mtu(&mut self, central: CentralId, mtu: u16) {
+        if self.mtus.insert(central.clone(), mtu) != Some(mtu) {
+            self.events
+                .publish_notice(EventNotice::MtuChanged { central, mtu });
+        }
+    }
+
+    /// The MTU last given to `record_mtu` for `central`, if any. Always
+    /// `None` unless something calls `record_mtu`.
+    pub fn current_mtu(&self, central: &CentralId) -> Option<u16> {
+        self.mtus.get(central).copied()
+    }
+
+    /// Largest notification payload every subscriber with a known MTU accepts.
+    fn notify_limit(&self, uuid: Uuid) -> Option<usize> {
+        self.subscriptions
+            .subscribers(uuid)
+            .iter()
+            .filter_map(|central| self.current_mtu(central))
+            .min()
+            .map(|mtu| usize::from(mtu.saturating_sub(ATT_NOTIFY_OVERHEAD)))
+    }
+
+    /// Who is subscribed to what. Clone it to share the live table with
+    /// other tasks.
+    pub fn subscriptions(&self) -> &SubscriptionRegistry {
+        &self.subscriptions
+    }
+
+    /// Records a subscription change reported by the backend.
+    pub fn update_subscription(&mut self, central: CentralId, uuid: Uuid, subscribed: bool) {
+        self.note_central(central.clone());
+        self.subscriptions.update(central, uuid, subscribed);
+    }
+
+    /// Remembers a central that made a request.
+    pub fn note_central(&mut self, central: CentralId) {
+        if self.centrals.insert(central.clone()) {
+            log::info!("Central {} connected", central);
+        }
+    }
+
+    /// Forgets a central, along with its subscriptions and MTU.
+    pub fn forget_central(&mut self, central: &CentralId) {
+        if self.centrals.remove(central) {
+            log::info!("Central {} forgotten", central);
+        }
+        self.mtus.remove(central);
+        self.subscriptions.remove_central(central);
+        self.long_writes.remove_central(central);
+    }
+
+    /// Longest value a central may write across several Prepare Write
+    /// fragments. Defaults to 512 bytes, the ATT maximum.
+    pub fn set_max_write_length(&mut self, len: usize) {
+        self.long_writes.set_max_len(len);
+    }
+
+    /// Adds one fragment of a possibly long write to the central's buffer.
+    /// A write at offset 0 comes straight back as `Complete`.
+    pub fn assemble_write(
+        &mut self,
+        request: &PeripheralRequest,
+        offset: u64,
+        value: Vec<u8>,
+        responder: WriteResponder,
+    ) -> Assembly<WriteResponder> {
+        self.long_writes.push(request, offset, value, responder)
+    }
+
+    /// The long write buffered for `request`'s central and characteristic,
+    /// if no fragment arrived for `idle`.
+    pub fn take_stale_write(
+        &mut self,
+        request: &PeripheralRequest,
+        idle: Duration,
+    ) -> Option<(PeripheralRequest, Vec<u8>)> {
+        self.long_writes.take_stale(request, idle)
+    }
+
+    /// Drops the connection to `central`, forgets it and publishes
+    /// `EventNotice::CentralDisconnected`.
+    ///
+    /// Only BlueZ supports this; elsewhere it returns
+    /// `Error::UnsupportedOperation` and the central is kept.
+    pub async fn disconnect(&mut self, central: CentralId) -> Result<(), Error> {
+        central::disconnect(&central).await?;
+        self.forget_central(&central);
+        self.events
+            .publish_notice(EventNotice::CentralDisconnected { central });
+        Ok(())
+    }
+
+    /// Centrals that have talked to the peripheral, sorted by ID.
+    ///
+    /// The backend reports no connection events, so a central shows up with
+    /// its first read, write or subscription, not when it connects, and
+    /// stays listed until `forget_central` is called.
+    pub fn connected_centrals(&self) -> Vec<CentralId> {
+        self.centrals.iter().cloned().collect()
+    }
+
+    /// Sends `value` to a single subscribed central.
+    ///
+    /// The backend can only notify every subscriber at once, so this works
+    /// while `central` is the sole subscriber and is unsupported otherwise.
+    pub async fn update_characteristic_for(
+        &mut self,
+        uuid: Uuid,
+        value: Vec<u8>,
+        central: CentralId,
+    ) -> Result<(), Error> {
+        if !self.subscriptions.is_central_subscribed(&central, uuid) {
+            return Err(Error::NotSubscribed { central, uuid });
+        }
+        if self.subscriptions.subscriber_count(uuid) > 1 {
+            return Err(Error::UnsupportedOperation(
+                "notifying one of several subscribed centrals",
+            ));
+        }
+        self.update_characteristic(uuid, value).await
+    }
+}
+
+fn envelope(sequence: u16, value: &[u8]) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(value.len() + 2);
+    payload.extend_from_slice(&sequence.to_le_bytes());
+    payload.extend_from_slice(value);
+    payload
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn power_cycle_restores_services_and_advertising() {
+        let (mock, _events) = MockPeripheral::channel(1);
+        let mut device = Device::mock(mock.clone());
+        device.set_auto_restore(true);
+        let service = Service {
+            uuid: Uuid::from_short(0x180F),
+            primary: true,
+            characteristics: Vec::new(),
+        };
+        device.add_service(&service).await.unwrap();
+        let primary = device
+            .add_advertisement(AdvertisementData::new("Lamp", &[service.uuid]))
+            .await
+            .unwrap();
+        let beacon = if device.capabilities().supports_multiple_advertisements {
+            Some(
+                device
+                    .add_advertisement(AdvertisementData::new("Beacon", &[]))
+                    .await
+                    .unwrap(),
+            )
+        } else {
+            None
+        };
+
+        mock.set_powered(false);
+        device.power_changed(false).await;
+        assert!(device.advertisements().is_empty());
+
+        mock.set_powered(true);
+        device.power_changed(true).await;
+        assert_eq!(mock.added_services().len(), 2);
+        assert_eq!(mock.advertising().unwrap().0, "Lamp");
+        let names: Vec<(AdvertisementHandle, &str)> = device
+            .advertisements()
+            .into_iter()
+            .map(|(handle, data)| (handle, data.advertised_name()))
+            .collect();
+        let expected: Vec<_> = [(primary, "Lamp")]
+            .into_iter()
+            .chain(beacon.map(|handle| (handle, "Beacon")))
+            .collect();
+        assert_eq!(names, expected);
+    }
+}
@@ -0,0 +1,614 @@
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::sync::Mutex;
+
+use ble_peripheral_rust::gatt::peripheral_event::{
+    PeripheralEvent, PeripheralRequest, ReadRequestResponse, RequestResponse, WriteRequestResponse,
+};
+
+use crate::central::CentralId;
+use crate::device::{Device, WriteResponder};
+use crate::events::{WriteCommand, LOG_TARGET};
+use crate::handler::HandlerRegistry;
+use crate::long_write::Assembly;
+use crate::responses::ReadRequestResponseExt;
+use crate::stats::EventStats;
+
+/// How long a long write may go without a fragment before what has arrived
+/// is taken as the whole value. Fragments arrive back to back, each one as
+/// soon as the previous was answered, so this only needs to cover the gap
+/// between them.
+pub const LONG_WRITE_IDLE: Duration = Duration::from_millis(50);
+
+/// Splits Write Commands off from write requests.
+///
+/// The backend hands both over as `WriteRequest` with a responder. For a
+/// characteristic that only takes Write Commands the central is not waiting
+/// for an answer, so the responder is acknowledged here and the handler gets
+/// a `WriteCommand` without one. Any other event is given back unchanged.
+pub async fn take_write_command(
+    event: PeripheralEvent,
+    peripheral: &Mutex<Device>,
+) -> Result<WriteCommand, PeripheralEvent> {
+    let is_command = match &event {
+        PeripheralEvent::WriteRequest { request, .. } => peripheral
+            .lock()
+            .await
+            .is_write_command(request.characteristic),
+        _ => false,
+    };
+    match event {
+        PeripheralEvent::WriteRequest {
+            request,
+            value,
+            responder,
+            ..
+        } if is_command => {
+            let _ = responder.send(WriteRequestResponse {
+                response: RequestResponse::Success,
+            });
+            Ok(WriteCommand { request, value })
+        }
+        event => Err(event),
+    }
+}
+
+pub async fn handle_write_command(
+    command: WriteCommand,
+    handlers: &HandlerRegistry,
+    peripheral: &Arc<Mutex<Device>>,
+    stats: &EventStats,
+) {
+    peripheral
+        .lock()
+        .await
+        .note_central(CentralId::from_request(&command.request));
+    log::info!(
+        target: LOG_TARGET,
+        "WriteCommand: {:?} Value: {:?}",
+        command.request,
+        command.value
+    );
+    let response = dispatch_write(&command.request, command.value, handlers, peripheral).await;
+    // There is nobody to send a refusal to, so it is only logged.
+    if !matches!(response.response, RequestResponse::Success) {
+        log::warn!(
+            target: LOG_TARGET,
+            "WriteCommand: {:?} refused with {:?}, not sent",
+            command.request,
+            response.response
+        );
+    }
+    stats.record_write(matches!(response.response, RequestResponse::Success));
+}
+
+/// Routes a write to the handler registered for the characteristic.
+/// Centrals' writes and `write` on the console both go through here, so they
+/// behave the same.
+pub async fn dispatch_write(
+    request: &PeripheralRequest,
+    value: Vec<u8>,
+    handlers: &HandlerRegistry,
+    peripheral: &Arc<Mutex<Device>>,
+) -> WriteRequestResponse {
+    let (retired, runtime) = {
+        let periph = peripheral.lock().await;
+        (
+            periph.is_retired(request.characteristic),
+            periph.is_runtime_characteristic(request.characteristic),
+        )
+    };
+    if retired {
+        log::warn!(
+            target: LOG_TARGET,
+            "Write: {:?} targets a removed characteristic",
+            request
+        );
+        return WriteRequestResponse {
+            response: RequestResponse::InvalidHandle,
+        };
+    }
+    // Added from the console, so whatever it shadows is not served.
+    let handler = if runtime {
+        handlers.fallback()
+    } else {
+        handlers.lookup(request.characteristic)
+    };
+    handler.on_write(request, 0, value).await
+}
+
+/// Hands a long write to its handler once its central has stopped sending
+/// fragments. This ends long writes whose last fragment was full-sized.
+/// Every fragment has been acknowledged already, so a refusal is only
+/// logged.
+async fn flush_long_write(
+    request: PeripheralRequest,
+    handlers: Arc<HandlerRegistry>,
+    peripheral: Arc<Mutex<Device>>,
+    stats: Arc<EventStats>,
+) {
+    tokio::time::sleep(LONG_WRITE_IDLE).await;
+    let Some((request, value)) = peripheral
+        .lock()
+        .await
+        .take_stale_write(&request, LONG_WRITE_IDLE)
+    else {
+        return;
+    };
+    log::info!(
+        target: LOG_TARGET,
+        "WriteRequest: {:?} -> long write of {} byte(s) complete",
+        request,
+        value.len()
+    );
+    let response = dispatch_write(&request, value, &handlers, &peripheral).await;
+    if !matches!(response.response, RequestResponse::Success) {
+        log::warn!(
+            target: LOG_TARGET,
+            "WriteRequest: {:?} long write refused with {:?}, not sent",
+            request,
+            response.response
+        );
+    }
+    stats.record_write(matches!(response.response, RequestResponse::Success));
+}
+
+fn send_write_response(responder: WriteResponder, response: WriteRequestResponse) {
+    if let Err(e) = responder.send(response) {
+        log::error!(target: LOG_TARGET, "Failed to send write response: {:?}", e);
+    }
+}
+
+/// Hands each request to the handler registered for its characteristic, or
+/// the registry's fallback.
+pub async fn handle_updates(
+    event: PeripheralEvent,
+    handlers: &Arc<HandlerRegistry>,
+    peripheral: Arc<Mutex<Device>>,
+    stats: &Arc<EventStats>,
+) {
+    match event {
+        PeripheralEvent::StateUpdate { is_powered } => {
+            log::info!(target: LOG_TARGET, "PowerOn: {:?}", is_powered);
+            stats.record_power_change();
+            peripheral.lock().await.power_changed(is_powered).await;
+        }
+        PeripheralEvent::CharacteristicSubscriptionUpdate {
+            request,
+            subscribed,
+        } => {
+            let central = CentralId::from_request(&request);
+            log::info!(
+                target: LOG_TARGET,
+                "CharacteristicSubscriptionUpdate: Central {} Subscribed {} {:?}",
+                central,
+                subscribed,
+                request
+            );
+            stats.record_subscription_change();
+            peripheral.lock().await.update_subscription(
+                central,
+                request.characteristic,
+                subscribed,
+            );
+            handlers
+                .lookup(request.characteristic)
+                .on_subscribe(&request, subscribed)
+                .await;
+        }
+        PeripheralEvent::ReadRequest {
+            request,
+            offset,
+            responder,
+        } => {
+            stats.record_read();
+            let (retired, runtime, enveloped) = {
+                let mut periph = peripheral.lock().await;
+                periph.note_central(CentralId::from_request(&request));
+                (
+                    periph.is_retired(request.characteristic),
+                    periph.is_runtime_characteristic(request.characteristic),
+                    periph.sequence_number(request.characteristic).is_some(),
+                )
+            };
+            let response = if retired {
+                log::warn!(
+                    target: LOG_TARGET,
+                    "ReadRequest: {:?} targets a removed characteristic",
+                    request
+                );
+                ReadRequestResponse {
+                    value: Vec::new(),
+                    response: RequestResponse::InvalidHandle,
+                }
+            } else {
+                // Added from the console, so whatever it shadows is not served.
+                let handler = if runtime {
+                    None
+                } else {
+                    handlers.get(request.characteristic)
+                };
+                match handler {
+                    // The fallback serves stored values, which are enveloped
+                    // already.
+                    None => handlers.fallback().on_read(&request, offset).await,
+                    Some(handler) if enveloped => {
+                        // The sequence number goes in front of the whole value,
+                        // so a Read Blob has to be sliced from the enveloped
+                        // value rather than the handler slicing the bare one.
+                        let response = handler.on_read(&request, 0).await;
+                        match response.response {
+                            RequestResponse::Success => {
+                                let value = peripheral
+                                    .lock()
+                                    .await
+                                    .read_envelope(request.characteristic, response.value);
+                                ReadRequestResponse::from_value(value, offset)
+                            }
+                            _ => response,
+                        }
+                    }
+                    Some(handler) => handler.on_read(&request, offset).await,
+                }
+            };
+            if let Err(e) = responder.send(response) {
+                log::error!(target: LOG_TARGET, "Failed to send read response: {:?}", e);
+            }
+        }
+        PeripheralEvent::WriteRequest {
+            request,
+            offset,
+            value,
+            responder,
+        } => {
+            let assembly = {
+                let mut periph = peripheral.lock().await;
+                periph.note_central(CentralId::from_request(&request));
+                periph.assemble_write(&request, offset, value, responder)
+            };
+            match assembly {
+                Assembly::Complete { value, responder } => {
+                    let response = dispatch_write(&request, value, handlers, &peripheral).await;
+                    stats.record_write(matches!(response.response, RequestResponse::Success));
+                    send_write_response(responder, response);
+                }
+                Assembly::Partial { ack } => {
+                    // Answered at once: the backend may hold the next
+                    // fragment back until it has this answer.
+                    send_write_response(
+                        ack,
+                        WriteRequestResponse {
+                            response: RequestResponse::Success,
+                        },
+                    );
+                    tokio::spawn(flush_long_write(
+                        request.clone(),
+                        handlers.clone(),
+                        peripheral.clone(),
+                        stats.clone(),
+                    ));
+                }
+                Assembly::Rejected { failure, responder } => {
+                    log::warn!(
+                        target: LOG_TARGET,
+                        "WriteRequest: {:?} Offset: {} -> long write rejected: {:?} ({})",
+                        request,
+                        offset,
+                        failure,
+                        failure.att_error()
+                    );
+                    stats.record_write(false);
+                    send_write_response(responder, failure.write_response());
+                }
+            }
+        }
+        _ => {
+            log::info!(target: LOG_TARGET, "Unhandled event: {:?}", event);
+            stats.record_unhandled(&event);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::{mpsc, oneshot};
+    use uuid::Uuid;
+
+    use ble_peripheral_rust::{
+        gatt::{
+            characteristic::Characteristic,
+            properties::{AttributePermission, CharacteristicProperty},
+        },
+        uuid::ShortUuid,
+    };
+
+    use super::*;
+    use crate::builder::{CharacteristicBuilder, ServiceBuilder, CCCD_UUID};
+    use crate::handler::StoredValueHandler;
+    use crate::mock::MockPeripheral;
+
+    const SERVICE: Uuid = Uuid::from_u128(0x1000);
+    const CHARACTERISTIC: Uuid = Uuid::from_u128(0x1001);
+
+    fn request_from(client: &str, characteristic: Uuid) -> PeripheralRequest {
+        PeripheralRequest {
+            client: client.to_string(),
+            service: SERVICE,
+            characteristic,
+        }
+    }
+
+    /// A device on the mock with one characteristic, by default readable,
+    /// writable and notifying, answered by `StoredValueHandler`.
+    struct Fixture {
+        mock: MockPeripheral,
+        events: mpsc::Receiver<PeripheralEvent>,
+        handlers: Arc<HandlerRegistry>,
+        device: Arc<Mutex<Device>>,
+        stats: Arc<EventStats>,
+    }
+
+    impl Fixture {
+        async fn new() -> Self {
+            let characteristic = CharacteristicBuilder::new(CHARACTERISTIC)
+                .readable()
+                .writable()
+                .notify()
+                .value(b"off".to_vec())
+                .build()
+                .unwrap();
+            Self::with_characteristic(characteristic).await
+        }
+
+        async fn with_characteristic(characteristic: Characteristic) -> Self {
+            let (mock, events) = MockPeripheral::channel(8);
+            let mut device = Device::mock(mock.clone());
+            let service = ServiceBuilder::new(SERVICE)
+                .characteristic(characteristic)
+                .build()
+                .unwrap();
+            device.add_service(&service).await.unwrap();
+            let stats = device.stats().clone();
+            let device = Arc::new(Mutex::new(device));
+            let handlers = Arc::new(HandlerRegistry::new(Arc::new(StoredValueHandler::new(
+                device.clone(),
+            ))));
+            Self {
+                mock,
+                events,
+                handlers,
+                device,
+                stats,
+            }
+        }
+
+        fn request(&self, characteristic: Uuid) -> PeripheralRequest {
+            request_from("central", characteristic)
+        }
+
+        /// Injects `event` into the mock and handles what arrives, as the
+        /// event loop does.
+        async fn deliver(&mut self, event: PeripheralEvent) {
+            self.mock.inject_event(event).await.unwrap();
+            let event = self.events.recv().await.unwrap();
+            handle_updates(event, &self.handlers, self.device.clone(), &self.stats).await;
+        }
+
+        async fn subscribe(&mut self, client: &str, subscribed: bool) {
+            self.deliver(PeripheralEvent::CharacteristicSubscriptionUpdate {
+                request: request_from(client, CHARACTERISTIC),
+                subscribed,
+            })
+            .await;
+        }
+
+        async fn update(&self, value: &[u8]) {
+            self.device
+                .lock()
+                .await
+                .update_characteristic(CHARACTERISTIC, value.to_vec())
+                .await
+                .unwrap();
+        }
+
+        async fn read(&mut self, characteristic: Uuid, offset: u64) -> ReadRequestResponse {
+            let (responder, response) = oneshot::channel();
+            let request = self.request(characteristic);
+            self.deliver(PeripheralEvent::ReadRequest {
+                request,
+                offset,
+                responder,
+            })
+            .await;
+            response.await.unwrap()
+        }
+
+        async fn write(&mut self, characteristic: Uuid, value: &[u8]) -> WriteRequestResponse {
+            self.write_at(characteristic, 0, value).await.await.unwrap()
+        }
+
+        /// Delivers a write at `offset` and hands back where its answer
+        /// arrives.
+        async fn write_at(
+            &mut self,
+            characteristic: Uuid,
+            offset: u64,
+            value: &[u8],
+        ) -> oneshot::Receiver<WriteRequestResponse> {
+            let (responder, response) = oneshot::channel();
+            let request = self.request(characteristic);
+            self.deliver(PeripheralEvent::WriteRequest {
+                request,
+                offset,
+                value: value.to_vec(),
+                responder,
+            })
+            .await;
+            response
+        }
+
+        /// Writes a fragment at `offset`, which must be answered before the
+        /// event loop gets to the next event, as a backend that waits for
+        /// each answer needs.
+        async fn write_fragment(&mut self, offset: u64, value: &[u8]) -> WriteRequestResponse {
+            let mut response = self.write_at(CHARACTERISTIC, offset, value).await;
+            response.try_recv().expect("fragment answered at once")
+        }
+
+        async fn stored(&mut self) -> Vec<u8> {
+            self.read(CHARACTERISTIC, 0).await.value
+        }
+    }
+
+    #[tokio::test]
+    async fn read_is_answered_with_the_stored_value() {
+        let mut fixture = Fixture::new().await;
+        let response = fixture.read(CHARACTERISTIC, 0).await;
+        assert!(matches!(response.response, RequestResponse::Success));
+        assert_eq!(response.value, b"off");
+
+        let response = fixture.read(CHARACTERISTIC, 1).await;
+        assert_eq!(response.value, b"ff");
+    }
+
+    #[tokio::test]
+    async fn accepted_write_is_stored() {
+        let mut fixture = Fixture::new().await;
+        let response = fixture.write(CHARACTERISTIC, b"on").await;
+        assert!(matches!(response.response, RequestResponse::Success));
+        assert_eq!(fixture.read(CHARACTERISTIC, 0).await.value, b"on");
+        assert_eq!(fixture.stats.snapshot().writes_accepted, 1);
+    }
+
+    #[tokio::test]
+    async fn unknown_characteristic_is_an_invalid_handle() {
+        let mut fixture = Fixture::new().await;
+        let unknown = Uuid::from_u128(0x2000);
+        let response = fixture.read(unknown, 0).await;
+        assert!(matches!(response.response, RequestResponse::InvalidHandle));
+        let response = fixture.write(unknown, b"on").await;
+        assert!(matches!(response.response, RequestResponse::InvalidHandle));
+        assert_eq!(fixture.stats.snapshot().writes_rejected, 1);
+    }
+
+    #[tokio::test]
+    async fn notify_only_characteristic_subscribes_without_declared_cccd() {
+        let mut fixture = Fixture::with_characteristic(Characteristic {
+            uuid: CHARACTERISTIC,
+            properties: vec![CharacteristicProperty::Notify],
+            permissions: vec![AttributePermission::Readable],
+            value: None,
+            descriptors: Vec::new(),
+        })
+        .await;
+        let added = fixture.mock.added_services();
+        let descriptors = &added[0].characteristics[0].descriptors;
+        assert!(descriptors
+            .iter()
+            .any(|descriptor| descriptor.uuid == Uuid::from_short(CCCD_UUID)));
+
+        fixture.subscribe("central", true).await;
+        assert!(fixture
+            .device
+            .lock()
+            .await
+            .subscriptions()
+            .is_subscribed(CHARACTERISTIC));
+        fixture.update(b"on").await;
+        assert_eq!(fixture.mock.sent_values(CHARACTERISTIC), [b"on".to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn subscription_updates_are_recorded_per_central() {
+        let mut fixture = Fixture::new().await;
+        fixture.subscribe("first", true).await;
+        fixture.subscribe("second", true).await;
+        // Subscribing again keeps the one entry.
+        fixture.subscribe("first", true).await;
+        {
+            let device = fixture.device.lock().await;
+            let mut subscribers = device.subscriptions().subscribers(CHARACTERISTIC);
+            subscribers.sort();
+            assert_eq!(
+                subscribers,
+                [
+                    CentralId("first".to_string()),
+                    CentralId("second".to_string())
+                ]
+            );
+        }
+
+        fixture.subscribe("first", false).await;
+        {
+            let device = fixture.device.lock().await;
+            let subscriptions = device.subscriptions();
+            assert_eq!(
+                subscriptions.subscribers(CHARACTERISTIC),
+                [CentralId("second".to_string())]
+            );
+            assert!(!subscriptions
+                .is_central_subscribed(&CentralId("first".to_string()), CHARACTERISTIC));
+        }
+
+        fixture.subscribe("second", false).await;
+        assert!(fixture.device.lock().await.subscriptions().is_empty());
+        assert_eq!(fixture.stats.snapshot().subscription_changes, 5);
+    }
+
+    #[tokio::test]
+    async fn updates_only_reach_subscribed_characteristics() {
+        let mut fixture = Fixture::new().await;
+        fixture.update(b"stored").await;
+        fixture.subscribe("central", true).await;
+        fixture.update(b"sent").await;
+        fixture.subscribe("central", false).await;
+        fixture.update(b"stored again").await;
+        assert_eq!(fixture.mock.sent_values(CHARACTERISTIC), [b"sent".to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn long_write_fragments_are_answered_one_at_a_time() {
+        let mut fixture = Fixture::new().await;
+        let first = [b'a'; 18];
+        let second = [b'b'; 18];
+        let last = [b'c'; 4];
+
+        let response = fixture.write_fragment(0, &first).await;
+        assert!(matches!(response.response, RequestResponse::Success));
+        // Nothing says it is a long write yet, so it is a value of its own.
+        assert_eq!(fixture.stored().await, first);
+
+        let response = fixture.write_fragment(18, &second).await;
+        assert!(matches!(response.response, RequestResponse::Success));
+        let response = fixture.write_fragment(36, &last).await;
+        assert!(matches!(response.response, RequestResponse::Success));
+        assert_eq!(
+            fixture.stored().await,
+            [&first[..], &second, &last].concat()
+        );
+        assert_eq!(fixture.stats.snapshot().writes_accepted, 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn full_sized_last_fragment_is_handled_once_idle() {
+        let mut fixture = Fixture::new().await;
+        let first = [b'a'; 18];
+        let second = [b'b'; 18];
+        fixture.write_fragment(0, &first).await;
+        fixture.write_fragment(18, &second).await;
+        assert_eq!(fixture.stored().await, first);
+
+        tokio::time::sleep(LONG_WRITE_IDLE * 2).await;
+        assert_eq!(fixture.stored().await, [first, second].concat());
+    }
+
+    #[tokio::test]
+    async fn fragment_that_does_not_continue_is_an_invalid_offset() {
+        let mut fixture = Fixture::new().await;
+        fixture.write_fragment(0, &[b'a'; 18]).await;
+        let response = fixture.write_fragment(20, &[b'b'; 4]).await;
+        assert!(matches!(response.response, RequestResponse::InvalidOffset));
+        assert_eq!(fixture.stored().await, [b'a'; 18]);
+    }
+}
@@ -0,0 +1,117 @@
+use std::fmt;
+
+use uuid::Uuid;
+
+use crate::advertising::{AdvertisementHandle, Packet};
+use crate::central::CentralId;
+
+#[derive(Debug)]
+pub enum Error {
+    /// The adapter is off or not available yet.
+    NotPoweredOn,
+    ServiceNotFound(Uuid),
+    CharacteristicNotFound(Uuid),
+    /// A characteristic with this UUID is already registered.
+    CharacteristicExists(Uuid),
+    /// The encoded payload does not fit in its advertising packet.
+    AdvertisementTooLarge {
+        packet: Packet,
+        size: usize,
+        limit: usize,
+    },
+    /// The advertisement cannot be encoded, e.g. a URL with an unknown scheme.
+    InvalidAdvertisement(String),
+    /// The local name is empty or too long to advertise.
+    InvalidLocalName(String),
+    /// Advertising was asked to start while it is already running.
+    AlreadyAdvertising,
+    /// Advertising was asked to restart before it was ever started.
+    NoPreviousAdvertisement,
+    /// The handle names no running advertisement, e.g. because it was
+    /// removed already.
+    AdvertisementNotFound(AdvertisementHandle),
+    NotSubscribed {
+        central: CentralId,
+        uuid: Uuid,
+    },
+    /// The value does not fit in one notification at a subscriber's MTU.
+    ValueTooLargeForMtu {
+        uuid: Uuid,
+        size: usize,
+        limit: usize,
+    },
+    /// The value's length differs from the one its characteristic's
+    /// Presentation Format descriptor declares.
+    InvalidAttributeLength {
+        uuid: Uuid,
+        expected: usize,
+        actual: usize,
+    },
+    /// A GATT table document could not be parsed or failed validation.
+    InvalidGattTable(String),
+    /// The backend has no way to perform the requested operation.
+    UnsupportedOperation(&'static str),
+    /// Any other failure reported by the platform backend.
+    Backend(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::NotPoweredOn => write!(f, "adapter is not powered on"),
+            Error::ServiceNotFound(uuid) => write!(f, "service {} not found", uuid),
+            Error::CharacteristicNotFound(uuid) => write!(f, "characteristic {} not found", uuid),
+            Error::CharacteristicExists(uuid) => {
+                write!(f, "characteristic {} is already registered", uuid)
+            }
+            Error::AdvertisementTooLarge {
+                packet,
+                size,
+                limit,
+            } => write!(
+                f,
+                "{} is {} bytes, more than the {} byte limit",
+                packet, size, limit
+            ),
+            Error::InvalidAdvertisement(message) => {
+                write!(f, "invalid advertisement: {}", message)
+            }
+            Error::InvalidLocalName(message) => write!(f, "invalid local name: {}", message),
+            Error::AlreadyAdvertising => write!(f, "already advertising"),
+            Error::NoPreviousAdvertisement => write!(f, "advertising was never started"),
+            Error::AdvertisementNotFound(handle) => {
+                write!(f, "advertisement {} is not running", handle)
+            }
+            Error::NotSubscribed { central, uuid } => {
+                write!(f, "central {} is not subscribed to {}", central, uuid)
+            }
+            Error::ValueTooLargeForMtu { uuid, size, limit } => write!(
+                f,
+                "value for {} is {} bytes, more than the {} bytes the MTU allows",
+                uuid, size, limit
+            ),
+            Error::InvalidAttributeLength {
+                uuid,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "value for {} is {} bytes, but its presentation format takes {}",
+                uuid, actual, expected
+            ),
+            Error::InvalidGattTable(message) => write!(f, "invalid GATT table: {}", message),
+            Error::UnsupportedOperation(operation) => {
+                write!(f, "unsupported operation: {}", operation)
+            }
+            Error::Backend(message) => write!(f, "backend error: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<ble_peripheral_rust::error::Error> for Error {
+    fn from(err: ble_peripheral_rust::error::Error) -> Self {
+        Error::Backend(err.to_string())
+    }
+}
@@ -0,0 +1,407 @@
+use std::collections::VecDeque;
+use std::sync::{
+    atomic::{AtomicBool, AtomicU64, Ordering},
+    Arc, Mutex,
+};
+use std::time::SystemTime;
+
+use clap::ValueEnum;
+use tokio::sync::{mpsc, Notify};
+
+use ble_peripheral_rust::gatt::peripheral_event::PeripheralEvent;
+
+use crate::events::LOG_TARGET;
+
+/// Events buffered when no capacity is given.
+pub const DEFAULT_EVENT_CHANNEL_CAPACITY: usize = 256;
+
+/// How the channel between the backend and the event loop behaves.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeripheralOptions {
+    /// Events buffered before `overflow` applies.
+    pub event_channel_capacity: usize,
+    pub overflow: OverflowPolicy,
+}
+
+impl Default for PeripheralOptions {
+    fn default() -> Self {
+        Self {
+            event_channel_capacity: DEFAULT_EVENT_CHANNEL_CAPACITY,
+            overflow: OverflowPolicy::Block,
+        }
+    }
+}
+
+/// What to do with a new event once `capacity` events are waiting.
+///
+/// Read and write requests are never dropped, whatever the policy. Up to
+/// `capacity` of them wait in the queue; past that the relay stops draining
+/// the backend until the event loop takes one, so the backend waits as with
+/// `Block` and the queue never holds more than twice `capacity` events.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum OverflowPolicy {
+    /// Make the backend wait until the event loop catches up.
+    #[default]
+    Block,
+    /// Discard the oldest waiting informational event.
+    DropOldest,
+    /// Discard the incoming informational event.
+    DropNewest,
+}
+
+/// Short name of an event's variant for log messages.
+pub fn variant_name(event: &PeripheralEvent) -> &'static str {
+    match event {
+        PeripheralEvent::StateUpdate { .. } => "StateUpdate",
+        PeripheralEvent::CharacteristicSubscriptionUpdate { .. } => {
+            "CharacteristicSubscriptionUpdate"
+        }
+        PeripheralEvent::ReadRequest { .. } => "ReadRequest",
+        PeripheralEvent::WriteRequest { .. } => "WriteRequest",
+        #[allow(unreachable_patterns)]
+        _ => "Other",
+    }
+}
+
+/// Read and write requests carry a responder and must always be delivered.
+fn is_droppable(event: &PeripheralEvent) -> bool {
+    !matches!(
+        event,
+        PeripheralEvent::ReadRequest { .. } | PeripheralEvent::WriteRequest { .. }
+    )
+}
+
+/// Creates the channel handed to `Peripheral::new` and the receiving end the
+/// event loop reads from.
+///
+/// With `Block` this is a plain bounded channel. The drop policies put a
+/// relay task in between that drains the backend's channel right away and
+/// applies the policy to its own queue, so the backend never waits. Every
+/// event they discard is logged and counted in `EventReceiver::stats`.
+pub fn event_channel(options: PeripheralOptions) -> (mpsc::Sender<PeripheralEvent>, EventReceiver) {
+    let PeripheralOptions {
+        event_channel_capacity: capacity,
+        overflow: policy,
+    } = options;
+    let (sender, receiver) = mpsc::channel(capacity);
+    if policy == OverflowPolicy::Block {
+        return (
+            sender,
+            EventReceiver {
+                source: Source::Direct(receiver),
+                stats: ChannelStats::default(),
+            },
+        );
+    }
+
+    let queue = Arc::new(Queue::default());
+    let stats = ChannelStats::default();
+    tokio::spawn(relay(
+        receiver,
+        queue.clone(),
+        stats.clone(),
+        capacity,
+        policy,
+    ));
+    (
+        sender,
+        EventReceiver {
+            source: Source::Queued(queue),
+            stats,
+        },
+    )
+}
+
+async fn relay(
+    mut receiver: mpsc::Receiver<PeripheralEvent>,
+    queue: Arc<Queue>,
+    stats: ChannelStats,
+    capacity: usize,
+    policy: OverflowPolicy,
+) {
+    while let Some(event) = receiver.recv().await {
+        stats.0.emitted.fetch_add(1, Ordering::Relaxed);
+        if !is_droppable(&event) {
+            queue.wait_for_request_room(capacity).await;
+        }
+        let discarded = {
+            let mut events = queue.events.lock().unwrap();
+            if events.len() < capacity || !is_droppable(&event) {
+                events.push_back(event);
+                None
+            } else if policy == OverflowPolicy::DropNewest {
+                Some(event)
+            } else {
+                // Requests in the queue are kept; if nothing else is waiting
+                // the new event is the only one that can go.
+                let oldest = events.iter().position(is_droppable);
+                events.push_back(event);
+                let index = oldest.unwrap_or(events.len() - 1);
+                events.remove(index)
+            }
+        };
+        if let Some(event) = discarded {
+            let total = stats.record_drop();
+            log::warn!(
+                target: LOG_TARGET,
+                "Event queue full, dropped {} ({} dropped so far)",
+                variant_name(&event),
+                total
+            );
+        }
+        queue.ready.notify_one();
+    }
+    queue.closed.store(true, Ordering::SeqCst);
+    queue.ready.notify_one();
+}
+
+#[derive(Default)]
+struct Queue {
+    events: Mutex<VecDeque<PeripheralEvent>>,
+    closed: AtomicBool,
+    ready: Notify,
+    /// Signalled whenever the event loop takes an event.
+    taken: Notify,
+}
+
+impl Queue {
+    /// Waits until fewer than `capacity` requests are queued.
+    async fn wait_for_request_room(&self, capacity: usize) {
+        loop {
+            let requests = {
+                let events = self.events.lock().unwrap();
+                events.iter().filter(|event| !is_droppable(event)).count()
+            };
+            if requests < capacity {
+                return;
+            }
+            self.taken.notified().await;
+        }
+    }
+}
+
+enum Source {
+    Direct(mpsc::Receiver<PeripheralEvent>),
+    Queued(Arc<Queue>),
+}
+
+/// Receiving end of `event_channel`.
+pub struct EventReceiver {
+    source: Source,
+    stats: ChannelStats,
+}
+
+impl EventReceiver {
+    /// Waits for the next event, or `None` once the backend has gone away.
+    pub async fn recv(&mut self) -> Option<PeripheralEvent> {
+        match &mut self.source {
+            Source::Direct(receiver) => {
+                let event = receiver.recv().await;
+                if event.is_some() {
+                    self.stats.0.emitted.fetch_add(1, Ordering::Relaxed);
+                }
+                event
+            }
+            Source::Queued(queue) => loop {
+                let next = queue.events.lock().unwrap().pop_front();
+                if let Some(event) = next {
+                    queue.taken.notify_one();
+                    return Some(event);
+                }
+                if queue.closed.load(Ordering::SeqCst) {
+                    return None;
+                }
+                queue.ready.notified().await;
+            },
+        }
+    }
+
+    /// Number of informational events discarded by the overflow policy.
+    pub fn dropped(&self) -> u64 {
+        self.stats.dropped()
+    }
+
+    /// A handle on the channel's counters that outlives the receiver, e.g.
+    /// for `EventStats::watch_channel`.
+    pub fn stats(&self) -> ChannelStats {
+        self.stats.clone()
+    }
+}
+
+/// Counters for the channel between the backend and the event loop, shared
+/// by every clone.
+#[derive(Debug, Clone, Default)]
+pub struct ChannelStats(Arc<ChannelCounters>);
+
+#[derive(Debug, Default)]
+struct ChannelCounters {
+    emitted: AtomicU64,
+    dropped: AtomicU64,
+    last_drop: Mutex<Option<SystemTime>>,
+}
+
+impl ChannelStats {
+    /// Events the backend sent, including dropped ones.
+    pub fn emitted(&self) -> u64 {
+        self.0.emitted.load(Ordering::Relaxed)
+    }
+
+    /// Events the overflow policy discarded.
+    pub fn dropped(&self) -> u64 {
+        self.0.dropped.load(Ordering::Relaxed)
+    }
+
+    /// When the last event was discarded, if one was.
+    pub fn last_drop(&self) -> Option<SystemTime> {
+        *self.0.last_drop.lock().unwrap()
+    }
+
+    /// Counts a discarded event and returns the new total.
+    fn record_drop(&self) -> u64 {
+        *self.0.last_drop.lock().unwrap() = Some(SystemTime::now());
+        self.0.dropped.fetch_add(1, Ordering::Relaxed) + 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::sync::oneshot;
+    use uuid::Uuid;
+
+    use ble_peripheral_rust::gatt::peripheral_event::PeripheralRequest;
+
+    use super::*;
+
+    fn options(capacity: usize, overflow: OverflowPolicy) -> PeripheralOptions {
+        PeripheralOptions {
+            event_channel_capacity: capacity,
+            overflow,
+        }
+    }
+
+    fn power(is_powered: bool) -> PeripheralEvent {
+        PeripheralEvent::StateUpdate { is_powered }
+    }
+
+    fn read() -> PeripheralEvent {
+        let (responder, _) = oneshot::channel();
+        PeripheralEvent::ReadRequest {
+            request: PeripheralRequest {
+                client: "central".to_string(),
+                service: Uuid::nil(),
+                characteristic: Uuid::nil(),
+            },
+            offset: 0,
+            responder,
+        }
+    }
+
+    /// Lets the relay catch up; the paused clock only moves once every task
+    /// is idle.
+    async fn settle() {
+        tokio::time::sleep(Duration::from_millis(1)).await;
+    }
+
+    /// Events in the relay's queue.
+    fn queued(receiver: &EventReceiver) -> usize {
+        match &receiver.source {
+            Source::Queued(queue) => queue.events.lock().unwrap().len(),
+            Source::Direct(_) => panic!("no relay with Block"),
+        }
+    }
+
+    /// What is waiting, as variant names, and power states for `StateUpdate`.
+    async fn drain(receiver: &mut EventReceiver) -> Vec<String> {
+        let mut events = Vec::new();
+        while let Ok(Some(event)) =
+            tokio::time::timeout(Duration::from_millis(1), receiver.recv()).await
+        {
+            events.push(match event {
+                PeripheralEvent::StateUpdate { is_powered } => format!("power {}", is_powered),
+                other => variant_name(&other).to_string(),
+            });
+        }
+        events
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn drop_oldest_keeps_the_latest_events() {
+        let (sender, mut receiver) = event_channel(options(2, OverflowPolicy::DropOldest));
+        for is_powered in [false, true, false] {
+            sender.send(power(is_powered)).await.unwrap();
+        }
+        settle().await;
+
+        assert_eq!(drain(&mut receiver).await, ["power true", "power false"]);
+        assert_eq!(receiver.dropped(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn drop_newest_keeps_the_earliest_events() {
+        let (sender, mut receiver) = event_channel(options(2, OverflowPolicy::DropNewest));
+        for is_powered in [false, true, false] {
+            sender.send(power(is_powered)).await.unwrap();
+        }
+        settle().await;
+
+        assert_eq!(drain(&mut receiver).await, ["power false", "power true"]);
+        assert_eq!(receiver.dropped(), 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn requests_are_never_dropped() {
+        for policy in [OverflowPolicy::DropOldest, OverflowPolicy::DropNewest] {
+            let (sender, mut receiver) = event_channel(options(2, policy));
+            sender.send(read()).await.unwrap();
+            sender.send(read()).await.unwrap();
+            sender.send(power(true)).await.unwrap();
+            settle().await;
+
+            assert_eq!(
+                drain(&mut receiver).await,
+                ["ReadRequest", "ReadRequest"],
+                "{:?}",
+                policy
+            );
+            assert_eq!(receiver.dropped(), 1, "{:?}", policy);
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn requests_past_the_capacity_wait_for_the_event_loop() {
+        let (sender, mut receiver) = event_channel(options(2, OverflowPolicy::DropOldest));
+        for _ in 0..3 {
+            sender.send(read()).await.unwrap();
+        }
+        settle().await;
+        assert_eq!(queued(&receiver), 2, "the third request waits in the relay");
+
+        assert!(matches!(
+            receiver.recv().await,
+            Some(PeripheralEvent::ReadRequest { .. })
+        ));
+        settle().await;
+        assert_eq!(queued(&receiver), 2);
+        assert_eq!(drain(&mut receiver).await, ["ReadRequest", "ReadRequest"]);
+        assert_eq!(receiver.dropped(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn drops_are_counted_with_the_time_of_the_last() {
+        let (sender, receiver) = event_channel(options(1, OverflowPolicy::DropNewest));
+        let stats = receiver.stats();
+        assert_eq!(stats.last_drop(), None);
+
+        for is_powered in [true, false, true] {
+            sender.send(power(is_powered)).await.unwrap();
+        }
+        settle().await;
+
+        assert_eq!(stats.emitted(), 3);
+        assert_eq!(stats.dropped(), 2);
+        assert_eq!(receiver.dropped(), 2);
+        assert!(stats.last_drop().is_some());
+    }
+}
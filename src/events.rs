@@ -0,0 +1,167 @@
+use tokio::sync::broadcast;
+use tokio_stream::{wrappers::BroadcastStream, Stream, StreamExt};
+
+use uuid::Uuid;
+
+use ble_peripheral_rust::gatt::peripheral_event::{PeripheralEvent, PeripheralRequest};
+
+use crate::central::CentralId;
+
+/// Log target for peripheral events, so they can be filtered on their own,
+/// e.g. `--log-level ble::events=debug`.
+pub const LOG_TARGET: &str = "ble::events";
+
+/// Number of notices kept for observers that fall behind.
+const OBSERVER_CAPACITY: usize = 64;
+
+/// A `PeripheralEvent` without its responder, so it can be shared.
+///
+/// Only the main event loop can answer read and write requests; observers
+/// see that they happened.
+#[derive(Debug, Clone)]
+pub enum EventNotice {
+    StateUpdate {
+        is_powered: bool,
+    },
+    SubscriptionUpdate {
+        /// The central that subscribed or unsubscribed.
+        central: CentralId,
+        request: PeripheralRequest,
+        subscribed: bool,
+    },
+    ReadRequest {
+        request: PeripheralRequest,
+        offset: u64,
+    },
+    WriteRequest {
+        request: PeripheralRequest,
+        offset: u64,
+        value: Vec<u8>,
+    },
+    /// A write to a characteristic that only takes Write Commands; nothing
+    /// answered it.
+    WriteCommandReceived {
+        request: PeripheralRequest,
+        value: Vec<u8>,
+    },
+    /// A central's ATT MTU became known or changed. Only published by
+    /// `Device::record_mtu`, which no backend feeds.
+    MtuChanged {
+        central: CentralId,
+        mtu: u16,
+    },
+    /// The peripheral dropped a central with `Device::disconnect`.
+    CentralDisconnected {
+        central: CentralId,
+    },
+    /// A step of rebuilding the GATT table and advertising after the adapter
+    /// was powered back on; see `Device::set_auto_restore`.
+    Restore(RestoreStep),
+}
+
+/// What `Device::set_auto_restore` did after a power cycle, in order.
+#[derive(Debug, Clone)]
+pub enum RestoreStep {
+    Started {
+        services: usize,
+    },
+    ServiceRegistered {
+        uuid: Uuid,
+    },
+    AdvertisingResumed {
+        name: String,
+    },
+    /// A step failed; the remaining ones are still attempted.
+    Failed {
+        step: String,
+        error: String,
+    },
+    Finished,
+}
+
+/// A Write Command (Write Without Response): the central is not waiting for
+/// an answer, so unlike `PeripheralEvent::WriteRequest` it has no responder.
+#[derive(Debug, Clone)]
+pub struct WriteCommand {
+    pub request: PeripheralRequest,
+    pub value: Vec<u8>,
+}
+
+impl WriteCommand {
+    pub fn notice(&self) -> EventNotice {
+        EventNotice::WriteCommandReceived {
+            request: self.request.clone(),
+            value: self.value.clone(),
+        }
+    }
+}
+
+impl EventNotice {
+    pub fn from_event(event: &PeripheralEvent) -> Option<Self> {
+        let notice = match event {
+            PeripheralEvent::StateUpdate { is_powered } => EventNotice::StateUpdate {
+                is_powered: *is_powered,
+            },
+            PeripheralEvent::CharacteristicSubscriptionUpdate {
+                request,
+                subscribed,
+            } => EventNotice::SubscriptionUpdate {
+                central: CentralId::from_request(request),
+                request: request.clone(),
+                subscribed: *subscribed,
+            },
+            PeripheralEvent::ReadRequest {
+                request, offset, ..
+            } => EventNotice::ReadRequest {
+                request: request.clone(),
+                offset: *offset,
+            },
+            PeripheralEvent::WriteRequest {
+                request,
+                offset,
+                value,
+                ..
+            } => EventNotice::WriteRequest {
+                request: request.clone(),
+                offset: *offset,
+                value: value.clone(),
+            },
+            #[allow(unreachable_patterns)]
+            _ => return None,
+        };
+        Some(notice)
+    }
+}
+
+/// Fans peripheral events out to any number of stream subscribers.
+#[derive(Debug, Clone)]
+pub struct EventHub {
+    sender: broadcast::Sender<EventNotice>,
+}
+
+impl Default for EventHub {
+    fn default() -> Self {
+        let (sender, _) = broadcast::channel(OBSERVER_CAPACITY);
+        Self { sender }
+    }
+}
+
+impl EventHub {
+    pub fn publish(&self, event: &PeripheralEvent) {
+        if let Some(notice) = EventNotice::from_event(event) {
+            // No receivers is fine, nobody is observing yet.
+            let _ = self.sender.send(notice);
+        }
+    }
+
+    /// Publishes a notice that did not come from a backend event.
+    pub fn publish_notice(&self, notice: EventNotice) {
+        let _ = self.sender.send(notice);
+    }
+
+    /// A new stream of every event published from now on. Notices missed
+    /// by a lagging subscriber are skipped.
+    pub fn event_stream(&self) -> impl Stream<Item = EventNotice> {
+        BroadcastStream::new(self.sender.subscribe()).filter_map(|notice| notice.ok())
+    }
+}
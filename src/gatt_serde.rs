@@ -0,0 +1,41 @@
+use ble_peripheral_rust::gatt::service::Service;
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use uuid::Uuid;
+
+use crate::config::{self, RawService};
+
+/// A GATT table that serializes the same way a config file's `services`
+/// array is written, so a whole table can be loaded with
+/// `serde_json::from_str` or `toml::from_str`.
+///
+/// The backend's `Service` and friends live in another crate and have no
+/// serde impls, so this wrapper provides them:
+///
+/// - UUIDs are written as canonical hyphenated strings. On input the
+///   16-bit (`"2A19"`) and 32-bit short forms are accepted too.
+/// - Properties and permissions are snake_case names, e.g. `"notify"` and
+///   `"read_encryption_required"`.
+/// - Values are written as byte arrays (`[0, 1]`). On input a string is
+///   accepted too and stored as its UTF-8 bytes.
+#[derive(Debug, Clone, Default)]
+pub struct GattTable(pub Vec<Service>);
+
+impl Serialize for GattTable {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        config::services_to_raw(&self.0, &[], &[], canonical).serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for GattTable {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let raw = Vec::<RawService>::deserialize(deserializer)?;
+        config::services_from_raw(raw)
+            .map(GattTable)
+            .map_err(D::Error::custom)
+    }
+}
+
+fn canonical(uuid: &Uuid) -> String {
+    uuid.hyphenated().to_string()
+}
@@ -0,0 +1,144 @@
+use std::fmt::Write;
+
+use uuid::Uuid;
+
+use ble_peripheral_rust::gatt::service::Service;
+
+use crate::config::{permission_name, property_name, BLUETOOTH_BASE_UUID};
+use crate::device::Device;
+
+/// Names of the SIG-assigned 16-bit UUIDs most likely to show up here.
+const SIG_NAMES: &[(u16, &str)] = &[
+    (0x1800, "Generic Access"),
+    (0x1801, "Generic Attribute"),
+    (0x1809, "Health Thermometer"),
+    (0x180A, "Device Information"),
+    (0x180D, "Heart Rate"),
+    (0x180F, "Battery Service"),
+    (0x1812, "Human Interface Device"),
+    (0x181A, "Environmental Sensing"),
+    (0x2900, "Characteristic Extended Properties"),
+    (0x2901, "Characteristic User Description"),
+    (0x2902, "Client Characteristic Configuration"),
+    (0x2903, "Server Characteristic Configuration"),
+    (0x2904, "Characteristic Presentation Format"),
+    (0x2A00, "Device Name"),
+    (0x2A01, "Appearance"),
+    (0x2A13, "Time Accuracy"),
+    (0x2A19, "Battery Level"),
+    (0x2A1C, "Temperature Measurement"),
+    (0x2A21, "Measurement Interval"),
+    (0x2A24, "Model Number String"),
+    (0x2A25, "Serial Number String"),
+    (0x2A26, "Firmware Revision String"),
+    (0x2A27, "Hardware Revision String"),
+    (0x2A28, "Software Revision String"),
+    (0x2A29, "Manufacturer Name String"),
+    (0x2A37, "Heart Rate Measurement"),
+    (0x2A3D, "String"),
+    (0x2A6E, "Temperature"),
+    (0x2A6F, "Humidity"),
+];
+
+/// Returns the SIG name for UUIDs in the Bluetooth base range that we know.
+pub fn sig_name(uuid: &Uuid) -> Option<&'static str> {
+    let value = uuid.as_u128();
+    if value & 0xFFFF_FFFF_FFFF_FFFF_FFFF_FFFF != BLUETOOTH_BASE_UUID || value >> 96 > 0xFFFF {
+        return None;
+    }
+    let short = (value >> 96) as u16;
+    SIG_NAMES
+        .iter()
+        .find(|(assigned, _)| *assigned == short)
+        .map(|(_, name)| *name)
+}
+
+fn label(uuid: &Uuid) -> String {
+    match sig_name(uuid) {
+        Some(name) => format!("{} ({})", uuid.hyphenated(), name),
+        None => uuid.hyphenated().to_string(),
+    }
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// The bytes as ASCII, with `.` standing in for anything unprintable.
+fn ascii(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&byte| {
+            if byte.is_ascii_graphic() || byte == b' ' {
+                byte as char
+            } else {
+                '.'
+            }
+        })
+        .collect()
+}
+
+fn value(value: &Option<Vec<u8>>) -> String {
+    match value {
+        Some(bytes) if !bytes.is_empty() => format!("{}  \"{}\"", hex(bytes), ascii(bytes)),
+        Some(_) => "(empty)".to_string(),
+        None => "(none)".to_string(),
+    }
+}
+
+/// Renders services as an indented tree with full 128-bit UUIDs.
+pub fn render(services: &[Service]) -> String {
+    render_with(services, |_| None)
+}
+
+/// Renders what is registered on `device`, with the last value pushed to
+/// each characteristic and its subscriber count.
+pub fn render_device(device: &Device) -> String {
+    render_with(device.services(), |uuid| {
+        Some(device.subscriptions().subscriber_count(uuid))
+    })
+}
+
+fn render_with(services: &[Service], subscribers: impl Fn(Uuid) -> Option<usize>) -> String {
+    let mut out = String::new();
+    for service in services {
+        let kind = if service.primary {
+            "primary"
+        } else {
+            "secondary"
+        };
+        let _ = writeln!(out, "Service {} [{}]", label(&service.uuid), kind);
+        for characteristic in &service.characteristics {
+            let _ = writeln!(out, "  Characteristic {}", label(&characteristic.uuid));
+            let properties: Vec<_> = characteristic
+                .properties
+                .iter()
+                .map(property_name)
+                .collect();
+            let permissions: Vec<_> = characteristic
+                .permissions
+                .iter()
+                .map(permission_name)
+                .collect();
+            let _ = writeln!(out, "    properties:  {}", properties.join(", "));
+            let _ = writeln!(out, "    permissions: {}", permissions.join(", "));
+            let _ = writeln!(out, "    value:       {}", value(&characteristic.value));
+            if let Some(count) = subscribers(characteristic.uuid) {
+                let _ = writeln!(out, "    subscribers: {}", count);
+            }
+            for descriptor in &characteristic.descriptors {
+                let _ = writeln!(
+                    out,
+                    "    Descriptor {} = {}",
+                    label(&descriptor.uuid),
+                    value(&descriptor.value)
+                );
+            }
+        }
+    }
+    out
+}
@@ -0,0 +1,301 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use ble_peripheral_rust::gatt::peripheral_event::{
+    PeripheralRequest, ReadRequestResponse, RequestResponse, WriteRequestResponse,
+};
+
+use crate::device::Device;
+use crate::error::Error;
+use crate::events::LOG_TARGET;
+use crate::peripheral::BoxFuture;
+use crate::responses::{Failure, ReadRequestResponseExt};
+
+/// Serves the requests for one characteristic, or a family of them that
+/// share state, e.g. the switch channels.
+///
+/// Handlers keep whatever they need, such as the `Device` to notify
+/// through, so the event loop only has to find the one for a request.
+pub trait CharacteristicHandler: Send + Sync {
+    /// Answers a read at `offset`. Handlers return the whole value through
+    /// `ReadRequestResponse::from_value`, which slices it.
+    fn on_read<'a>(
+        &'a self,
+        request: &'a PeripheralRequest,
+        offset: u64,
+    ) -> BoxFuture<'a, ReadRequestResponse>;
+
+    /// Answers a write of `value` at `offset`. Long writes are reassembled
+    /// before they get here, so the event loop always passes 0.
+    fn on_write<'a>(
+        &'a self,
+        request: &'a PeripheralRequest,
+        offset: u64,
+        value: Vec<u8>,
+    ) -> BoxFuture<'a, WriteRequestResponse>;
+
+    /// Called after the `Device` has recorded a subscribe or unsubscribe.
+    fn on_subscribe<'a>(
+        &'a self,
+        _request: &'a PeripheralRequest,
+        _subscribed: bool,
+    ) -> BoxFuture<'a, ()> {
+        Box::pin(async {})
+    }
+}
+
+/// Which handler serves each characteristic UUID, plus the one for every
+/// other UUID.
+pub struct HandlerRegistry {
+    handlers: HashMap<Uuid, Arc<dyn CharacteristicHandler>>,
+    fallback: Arc<dyn CharacteristicHandler>,
+}
+
+impl HandlerRegistry {
+    /// A registry that sends everything to `fallback` until handlers are
+    /// registered.
+    pub fn new(fallback: Arc<dyn CharacteristicHandler>) -> Self {
+        Self {
+            handlers: HashMap::new(),
+            fallback,
+        }
+    }
+
+    /// Routes `uuid` to `handler`, replacing any handler it had.
+    pub fn register(&mut self, uuid: Uuid, handler: Arc<dyn CharacteristicHandler>) {
+        self.handlers.insert(uuid, handler);
+    }
+
+    /// Sends `uuid` back to the fallback.
+    pub fn unregister(&mut self, uuid: Uuid) {
+        self.handlers.remove(&uuid);
+    }
+
+    /// The handler registered for `uuid`, if any.
+    pub fn get(&self, uuid: Uuid) -> Option<&Arc<dyn CharacteristicHandler>> {
+        self.handlers.get(&uuid)
+    }
+
+    /// The handler that serves `uuid`: its own, or the fallback.
+    pub fn lookup(&self, uuid: Uuid) -> &Arc<dyn CharacteristicHandler> {
+        self.get(uuid).unwrap_or(&self.fallback)
+    }
+
+    pub fn fallback(&self) -> &Arc<dyn CharacteristicHandler> {
+        &self.fallback
+    }
+
+    pub fn contains(&self, uuid: Uuid) -> bool {
+        self.handlers.contains_key(&uuid)
+    }
+}
+
+/// Fallback for characteristics no profile owns, e.g. ones from a config
+/// file or added with `char add`: reads get the stored value and writes
+/// become it, notifying subscribers.
+pub struct StoredValueHandler {
+    device: Arc<Mutex<Device>>,
+}
+
+impl StoredValueHandler {
+    pub fn new(device: Arc<Mutex<Device>>) -> Self {
+        Self { device }
+    }
+}
+
+impl CharacteristicHandler for StoredValueHandler {
+    fn on_read<'a>(
+        &'a self,
+        request: &'a PeripheralRequest,
+        offset: u64,
+    ) -> BoxFuture<'a, ReadRequestResponse> {
+        Box::pin(async move {
+            let device = self.device.lock().await;
+            if device.characteristic(request.characteristic).is_none() {
+                log::warn!(
+                    target: LOG_TARGET,
+                    "ReadRequest: {:?} targets an unknown characteristic",
+                    request
+                );
+                return ReadRequestResponse {
+                    value: Vec::new(),
+                    response: RequestResponse::InvalidHandle,
+                };
+            }
+            log::info!(
+                target: LOG_TARGET,
+                "ReadRequest: {:?} Offset: {} -> Responding with stored value",
+                request,
+                offset
+            );
+            // Not written yet reads as empty.
+            device
+                .stored_read(request.characteristic, offset)
+                .unwrap_or_else(|| ReadRequestResponse::from_value(Vec::new(), offset))
+        })
+    }
+
+    fn on_write<'a>(
+        &'a self,
+        request: &'a PeripheralRequest,
+        _offset: u64,
+        value: Vec<u8>,
+    ) -> BoxFuture<'a, WriteRequestResponse> {
+        Box::pin(async move {
+            let mut device = self.device.lock().await;
+            if device.characteristic(request.characteristic).is_none() {
+                log::warn!(
+                    target: LOG_TARGET,
+                    "WriteRequest: {:?} targets an unknown characteristic",
+                    request
+                );
+                return WriteRequestResponse {
+                    response: RequestResponse::InvalidHandle,
+                };
+            }
+            match device
+                .update_characteristic(request.characteristic, value)
+                .await
+            {
+                Ok(()) => WriteRequestResponse {
+                    response: RequestResponse::Success,
+                },
+                Err(err) => {
+                    log::error!(
+                        "Error echoing write to {:?}: {}",
+                        request.characteristic,
+                        err
+                    );
+                    match err {
+                        Error::InvalidAttributeLength { .. } => Failure::WrongLength,
+                        _ => Failure::Internal,
+                    }
+                    .write_response()
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::history::History;
+    use crate::mock::MockPeripheral;
+    use crate::profile::switch::{self, SwitchHandler, SwitchState};
+    use crate::state::Source;
+    use crate::switches::SwitchBank;
+
+    const SERVICE: Uuid = Uuid::from_u128(0x1000);
+    const SWITCH: Uuid = Uuid::from_u128(0x1001);
+
+    /// A registry serving one switch through `SwitchHandler`, with
+    /// `StoredValueHandler` for everything else.
+    async fn registry(strict_writes: bool) -> (HandlerRegistry, Arc<SwitchBank>) {
+        let (mock, _events) = MockPeripheral::channel(1);
+        let mut device = Device::mock(mock);
+        let channels = [("switch".to_string(), SWITCH)];
+        device
+            .add_service(&switch::service(SERVICE, &channels, SwitchState::Off).unwrap())
+            .await
+            .unwrap();
+        let device = Arc::new(Mutex::new(device));
+        let switches = Arc::new(
+            SwitchBank::new(&channels, SwitchState::Off, &History::new(0))
+                .with_strict_writes(strict_writes),
+        );
+        let handler = SwitchHandler::new(switches.clone(), device.clone());
+        let mut registry = HandlerRegistry::new(Arc::new(StoredValueHandler::new(device)));
+        let uuids = handler.characteristics();
+        let handler: Arc<dyn CharacteristicHandler> = Arc::new(handler);
+        for uuid in uuids {
+            registry.register(uuid, handler.clone());
+        }
+        (registry, switches)
+    }
+
+    fn request(characteristic: Uuid) -> PeripheralRequest {
+        PeripheralRequest {
+            client: "central".to_string(),
+            service: SERVICE,
+            characteristic,
+        }
+    }
+
+    async fn read(registry: &HandlerRegistry, characteristic: Uuid) -> ReadRequestResponse {
+        let request = request(characteristic);
+        registry.lookup(characteristic).on_read(&request, 0).await
+    }
+
+    async fn write(
+        registry: &HandlerRegistry,
+        characteristic: Uuid,
+        value: &[u8],
+    ) -> WriteRequestResponse {
+        let request = request(characteristic);
+        registry
+            .lookup(characteristic)
+            .on_write(&request, 0, value.to_vec())
+            .await
+    }
+
+    fn power(switches: &SwitchBank) -> bool {
+        switches.primary().state.read().unwrap().power()
+    }
+
+    #[tokio::test]
+    async fn read_gives_the_switch_state() {
+        let (registry, switches) = registry(false).await;
+        let response = read(&registry, SWITCH).await;
+        assert!(matches!(response.response, RequestResponse::Success));
+        assert_eq!(response.value, b"off");
+
+        switches
+            .primary()
+            .state
+            .write()
+            .unwrap()
+            .set_power(true, Source::Stdin);
+        assert_eq!(read(&registry, SWITCH).await.value, b"on");
+    }
+
+    #[tokio::test]
+    async fn write_toggles_the_switch() {
+        let (registry, switches) = registry(false).await;
+        let response = write(&registry, SWITCH, b"on").await;
+        assert!(matches!(response.response, RequestResponse::Success));
+        assert!(power(&switches));
+        assert_eq!(read(&registry, SWITCH).await.value, b"on");
+
+        let response = write(&registry, SWITCH, b" OFF\n").await;
+        assert!(matches!(response.response, RequestResponse::Success));
+        assert!(!power(&switches));
+    }
+
+    #[tokio::test]
+    async fn malformed_payloads_are_rejected() {
+        let (registry, switches) = registry(true).await;
+        let response = write(&registry, SWITCH, &[0xFF, 0xFE]).await;
+        assert!(matches!(response.response, RequestResponse::UnlikelyError));
+        let response = write(&registry, SWITCH, b"maybe").await;
+        assert!(matches!(response.response, RequestResponse::UnlikelyError));
+        assert!(!power(&switches));
+    }
+
+    #[tokio::test]
+    async fn unknown_characteristics_fall_back_to_an_invalid_handle() {
+        let (registry, _) = registry(false).await;
+        let unknown = Uuid::from_u128(0x2000);
+        assert!(registry.contains(SWITCH));
+        assert!(!registry.contains(unknown));
+
+        let response = read(&registry, unknown).await;
+        assert!(matches!(response.response, RequestResponse::InvalidHandle));
+        assert!(response.value.is_empty());
+        let response = write(&registry, unknown, b"on").await;
+        assert!(matches!(response.response, RequestResponse::InvalidHandle));
+    }
+}
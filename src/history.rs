@@ -0,0 +1,90 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::profile::switch::SwitchState;
+use crate::state::Source;
+
+/// Transitions kept when `--history-size` is not given.
+pub const DEFAULT_HISTORY_SIZE: usize = 100;
+
+/// One recorded switch state change.
+#[derive(Debug, Clone)]
+pub struct Transition {
+    pub at: SystemTime,
+    pub switch: String,
+    pub old: SwitchState,
+    pub new: SwitchState,
+    pub source: Source,
+}
+
+impl fmt::Display for Transition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self
+            .at
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since| since.as_secs());
+        write!(
+            f,
+            "{:02}:{:02}:{:02} UTC {}: {} -> {} ({})",
+            secs / 3600 % 24,
+            secs / 60 % 60,
+            secs % 60,
+            self.switch,
+            self.old.as_str(),
+            self.new.as_str(),
+            self.source
+        )
+    }
+}
+
+/// Bounded log of recent transitions shared by every switch. Once full the
+/// oldest entry is dropped, so memory stays fixed however chatty centrals are.
+#[derive(Debug, Clone)]
+pub struct History {
+    entries: Arc<Mutex<VecDeque<Transition>>>,
+    capacity: usize,
+}
+
+impl History {
+    /// A history holding up to `capacity` transitions. Zero records nothing.
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            entries: Arc::new(Mutex::new(VecDeque::with_capacity(capacity))),
+            capacity,
+        }
+    }
+
+    pub fn record(&self, transition: Transition) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut entries = self.entries.lock().unwrap();
+        if entries.len() == self.capacity {
+            entries.pop_front();
+        }
+        entries.push_back(transition);
+    }
+
+    /// Up to `count` of the most recent transitions, oldest first.
+    pub fn recent(&self, count: usize) -> Vec<Transition> {
+        let entries = self.entries.lock().unwrap();
+        let skip = entries.len().saturating_sub(count);
+        entries.iter().skip(skip).cloned().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.lock().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self::new(DEFAULT_HISTORY_SIZE)
+    }
+}
@@ -0,0 +1,46 @@
+pub mod adapter;
+pub mod advertising;
+pub mod aliases;
+pub mod auto_off;
+mod broadcaster;
+pub mod builder;
+pub mod central;
+pub mod cli;
+pub mod completion;
+pub mod config;
+pub mod console;
+pub mod debounce;
+pub mod device;
+pub mod dispatch;
+pub mod error;
+pub mod event_queue;
+pub mod events;
+pub mod gatt_serde;
+pub mod gatt_table;
+pub mod handler;
+pub mod history;
+pub mod logging;
+pub mod long_write;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+pub mod output;
+pub mod pairing;
+pub mod peripheral;
+pub mod persist;
+pub mod presentation;
+pub mod profile;
+pub mod prompt;
+pub mod reload;
+pub mod responses;
+pub mod script;
+pub mod short_uuid;
+pub mod snapshot;
+pub mod state;
+pub mod state_machine;
+pub mod stats;
+pub mod subscriptions;
+pub mod switches;
+pub mod validate;
+
+pub use error::Error;
+pub use peripheral::{Backend, Capabilities, PeripheralApi};
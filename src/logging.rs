@@ -0,0 +1,122 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use pretty_env_logger::env_logger::Target;
+
+/// Filter used when neither `RUST_LOG` nor `--log-level` is given.
+pub const DEFAULT_LOG_LEVEL: &str = "info";
+
+/// Size at which the log file is moved aside to `<file>.1` and restarted.
+pub const MAX_LOG_FILE_BYTES: u64 = 10 * 1024 * 1024;
+
+type Printer = Box<dyn FnMut(String) + Send>;
+
+/// Where log lines go instead of stderr while an interactive prompt is up.
+static PRINTER: Mutex<Option<Printer>> = Mutex::new(None);
+
+/// Routes log lines through `printer` instead of stderr, so a line-editing
+/// prompt can redraw itself below them.
+pub fn set_printer(printer: impl FnMut(String) + Send + 'static) {
+    *PRINTER.lock().unwrap() = Some(Box::new(printer));
+}
+
+/// Sends log lines back to stderr.
+pub fn clear_printer() {
+    PRINTER.lock().unwrap().take();
+}
+
+/// Sets up logging to stderr (or the prompt's printer) and, if `file` is
+/// given, to a rotating log file.
+///
+/// `RUST_LOG` is honoured as the base filter and `level` (the same
+/// `module=level,...` syntax) is applied on top of it, so its directives win
+/// for the modules it names.
+pub fn init(level: Option<&str>, file: Option<&Path>) -> io::Result<()> {
+    let mut builder = pretty_env_logger::formatted_builder();
+    match std::env::var("RUST_LOG") {
+        Ok(filters) => builder.parse_filters(&filters),
+        Err(_) => builder.parse_filters(DEFAULT_LOG_LEVEL),
+    };
+    if let Some(level) = level {
+        builder.parse_filters(level);
+    }
+    let file = file.map(RotatingFile::open).transpose()?;
+    builder.target(Target::Pipe(Box::new(Tee { file })));
+    builder
+        .try_init()
+        .map_err(|err| io::Error::new(io::ErrorKind::Other, err))
+}
+
+/// Copies every log line to the console and, if there is one, the log file.
+struct Tee {
+    file: Option<RotatingFile>,
+}
+
+impl Write for Tee {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match PRINTER.lock().unwrap().as_mut() {
+            Some(printer) => printer(String::from_utf8_lossy(buf).into_owned()),
+            // A broken stderr should not stop the file from being written.
+            None => {
+                let _ = io::stderr().write_all(buf);
+            }
+        }
+        if let Some(file) = &mut self.file {
+            file.write_all(buf)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let _ = io::stderr().flush();
+        match &mut self.file {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Append-only file that keeps one previous generation once it grows past
+/// `MAX_LOG_FILE_BYTES`.
+struct RotatingFile {
+    path: PathBuf,
+    file: File,
+    written: u64,
+}
+
+impl RotatingFile {
+    fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let written = file.metadata()?.len();
+        Ok(Self {
+            path: path.to_path_buf(),
+            file,
+            written,
+        })
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        let mut backup = self.path.clone().into_os_string();
+        backup.push(".1");
+        fs::rename(&self.path, backup)?;
+        *self = Self::open(&self.path)?;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.written >= MAX_LOG_FILE_BYTES {
+            self.rotate()?;
+        }
+        let written = self.file.write(buf)?;
+        self.written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
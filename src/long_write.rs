@@ -0,0 +1,343 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use tokio::time::Instant;
+use uuid::Uuid;
+
+use ble_peripheral_rust::gatt::peripheral_event::PeripheralRequest;
+
+use crate::central::CentralId;
+use crate::responses::Failure;
+
+/// ATT header bytes of a Prepare Write Request: opcode, handle and offset.
+pub const ATT_PREPARE_WRITE_OVERHEAD: u16 = 5;
+
+/// The ATT MTU every central starts with.
+pub const ATT_DEFAULT_MTU: u16 = 23;
+
+/// Shortest first fragment of a long write. A central only splits a value
+/// that does not fit one Write Request, and then fills each Prepare Write,
+/// so a shorter write at offset 0 never has fragments after it, whatever
+/// the MTU.
+pub const MIN_LONG_WRITE_FRAGMENT: usize = (ATT_DEFAULT_MTU - ATT_PREPARE_WRITE_OVERHEAD) as usize;
+
+/// What one fragment of a write did to its central's reassembly buffer.
+/// `R` is whatever answers the fragment, the backend's responder in the
+/// event loop.
+#[derive(Debug)]
+pub enum Assembly<R> {
+    /// A value ready to hand to the handler: a write at offset 0, or the
+    /// last fragment of a long write with everything before it. `responder`
+    /// answers it with the handler's response.
+    Complete { value: Vec<u8>, responder: R },
+    /// The fragment continues a long write and is buffered. `ack` answers
+    /// it right away, since the backend may wait for that before passing on
+    /// the next one.
+    Partial { ack: R },
+    /// The fragment does not continue the buffered value, or makes it too
+    /// long. The buffer is dropped and `responder` gets `failure`.
+    Rejected { failure: Failure, responder: R },
+}
+
+struct Pending {
+    request: PeripheralRequest,
+    value: Vec<u8>,
+    fragment_len: usize,
+    last_fragment: Instant,
+    /// Whether a fragment past offset 0 arrived. Until then the value has
+    /// only gone to the handler as a write of its own.
+    continued: bool,
+}
+
+/// Reassembles long writes, which arrive as one `WriteRequest` per Prepare
+/// Write fragment with increasing offsets, per central and characteristic.
+///
+/// Every write at offset 0 is complete as it is, so ordinary writes are
+/// answered without waiting. One of `MIN_LONG_WRITE_FRAGMENT` bytes or more
+/// is also kept, and when a fragment continues it at the next offset the
+/// write is buffered from then on: each fragment is acknowledged at once,
+/// and the value completes when a fragment shorter than the first ends the
+/// sequence, or when `take_stale` finds no more arrived. The backend does
+/// not report the Execute Write that really ends it, nor the MTU, so only
+/// offsets and lengths are used.
+///
+/// The handler thus sees the first fragment of a long write on its own
+/// before it gets the whole value.
+pub struct WriteAssembler {
+    max_len: usize,
+    pending: HashMap<(CentralId, Uuid), Pending>,
+}
+
+impl WriteAssembler {
+    /// Rejects assembled values longer than `max_len` bytes.
+    pub fn new(max_len: usize) -> Self {
+        Self {
+            max_len,
+            pending: HashMap::new(),
+        }
+    }
+
+    pub fn set_max_len(&mut self, max_len: usize) {
+        self.max_len = max_len;
+    }
+
+    /// Adds a fragment written at `offset`, to be answered through
+    /// `responder`.
+    pub fn push<R>(
+        &mut self,
+        request: &PeripheralRequest,
+        offset: u64,
+        value: Vec<u8>,
+        responder: R,
+    ) -> Assembly<R> {
+        let key = (CentralId::from_request(request), request.characteristic);
+        if offset == 0 {
+            // A new write replaces one that never finished.
+            self.pending.remove(&key);
+            if value.len() > self.max_len {
+                return Assembly::Rejected {
+                    failure: Failure::WrongLength,
+                    responder,
+                };
+            }
+            if value.len() >= MIN_LONG_WRITE_FRAGMENT {
+                self.pending.insert(
+                    key,
+                    Pending {
+                        request: request.clone(),
+                        fragment_len: value.len(),
+                        value: value.clone(),
+                        last_fragment: Instant::now(),
+                        continued: false,
+                    },
+                );
+            }
+            return Assembly::Complete { value, responder };
+        }
+        let Some(mut pending) = self.pending.remove(&key) else {
+            return Assembly::Rejected {
+                failure: Failure::BadOffset,
+                responder,
+            };
+        };
+        // Out of order, overlapping or leaving a gap.
+        if usize::try_from(offset).ok() != Some(pending.value.len()) {
+            return Assembly::Rejected {
+                failure: Failure::BadOffset,
+                responder,
+            };
+        }
+        if pending.value.len() + value.len() > self.max_len {
+            return Assembly::Rejected {
+                failure: Failure::WrongLength,
+                responder,
+            };
+        }
+        let last = value.len() < pending.fragment_len;
+        pending.value.extend_from_slice(&value);
+        if last {
+            return Assembly::Complete {
+                value: pending.value,
+                responder,
+            };
+        }
+        pending.last_fragment = Instant::now();
+        pending.continued = true;
+        self.pending.insert(key, pending);
+        Assembly::Partial { ack: responder }
+    }
+
+    /// Completes the long write buffered for `request` if no fragment
+    /// arrived for `idle`. Every fragment has been answered by then, so
+    /// nobody waits for the handler's response.
+    pub fn take_stale(
+        &mut self,
+        request: &PeripheralRequest,
+        idle: Duration,
+    ) -> Option<(PeripheralRequest, Vec<u8>)> {
+        let key = (CentralId::from_request(request), request.characteristic);
+        let pending = self.pending.get(&key)?;
+        if !pending.continued || pending.last_fragment.elapsed() < idle {
+            return None;
+        }
+        self.pending
+            .remove(&key)
+            .map(|pending| (pending.request, pending.value))
+    }
+
+    /// Drops whatever `central` was in the middle of writing.
+    pub fn remove_central(&mut self, central: &CentralId) {
+        self.pending.retain(|(writer, _), _| writer != central);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request() -> PeripheralRequest {
+        PeripheralRequest {
+            client: "central".to_string(),
+            service: Uuid::from_u128(1),
+            characteristic: Uuid::from_u128(2),
+        }
+    }
+
+    fn fragment(byte: u8, len: usize) -> Vec<u8> {
+        vec![byte; len]
+    }
+
+    #[test]
+    fn single_fragment_completes_at_once() {
+        let mut assembler = WriteAssembler::new(512);
+        match assembler.push(&request(), 0, b"on".to_vec(), 1) {
+            Assembly::Complete { value, responder } => {
+                assert_eq!(value, b"on");
+                assert_eq!(responder, 1);
+            }
+            other => panic!("expected Complete, got {:?}", other),
+        }
+        assert!(assembler.take_stale(&request(), Duration::ZERO).is_none());
+    }
+
+    #[test]
+    fn long_write_at_offset_zero_completes_at_once() {
+        let mut assembler = WriteAssembler::new(512);
+        let value = fragment(b'a', 40);
+        match assembler.push(&request(), 0, value.clone(), 1) {
+            Assembly::Complete {
+                value: complete,
+                responder,
+            } => {
+                assert_eq!(complete, value);
+                assert_eq!(responder, 1);
+            }
+            other => panic!("expected Complete, got {:?}", other),
+        }
+        // Nothing continued it, so there is nothing to flush.
+        assert!(assembler.take_stale(&request(), Duration::ZERO).is_none());
+    }
+
+    #[test]
+    fn in_order_fragments_are_joined() {
+        let mut assembler = WriteAssembler::new(512);
+        let first = fragment(b'a', 18);
+        let second = fragment(b'b', 18);
+        let last = fragment(b'c', 4);
+        assert!(matches!(
+            assembler.push(&request(), 0, first.clone(), 1),
+            Assembly::Complete { responder: 1, .. }
+        ));
+        assert!(matches!(
+            assembler.push(&request(), 18, second.clone(), 2),
+            Assembly::Partial { ack: 2 }
+        ));
+        match assembler.push(&request(), 36, last.clone(), 3) {
+            Assembly::Complete { value, responder } => {
+                assert_eq!(value, [first, second, last].concat());
+                assert_eq!(responder, 3);
+            }
+            other => panic!("expected Complete, got {:?}", other),
+        }
+        assert!(assembler.take_stale(&request(), Duration::ZERO).is_none());
+    }
+
+    #[test]
+    fn full_sized_last_fragment_completes_when_idle() {
+        let mut assembler = WriteAssembler::new(512);
+        assembler.push(&request(), 0, fragment(b'a', 18), 1);
+        assembler.push(&request(), 18, fragment(b'b', 18), 2);
+        assert!(assembler
+            .take_stale(&request(), Duration::from_secs(60))
+            .is_none());
+        let (_, value) = assembler
+            .take_stale(&request(), Duration::ZERO)
+            .expect("buffered write");
+        assert_eq!(value.len(), 36);
+        assert!(assembler.take_stale(&request(), Duration::ZERO).is_none());
+    }
+
+    #[test]
+    fn out_of_order_fragment_is_rejected() {
+        let mut assembler = WriteAssembler::new(512);
+        assembler.push(&request(), 0, fragment(b'a', 18), 1);
+        match assembler.push(&request(), 36, fragment(b'c', 4), 2) {
+            Assembly::Rejected { failure, responder } => {
+                assert_eq!(failure, Failure::BadOffset);
+                assert_eq!(responder, 2);
+            }
+            other => panic!("expected Rejected, got {:?}", other),
+        }
+        assert!(assembler.take_stale(&request(), Duration::ZERO).is_none());
+    }
+
+    #[test]
+    fn overlapping_fragment_is_rejected() {
+        let mut assembler = WriteAssembler::new(512);
+        assembler.push(&request(), 0, fragment(b'a', 18), 1);
+        assert!(matches!(
+            assembler.push(&request(), 10, fragment(b'b', 18), 2),
+            Assembly::Rejected {
+                failure: Failure::BadOffset,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn continuation_without_start_is_rejected() {
+        let mut assembler = WriteAssembler::new(512);
+        assert!(matches!(
+            assembler.push(&request(), 18, fragment(b'b', 4), 1),
+            Assembly::Rejected {
+                failure: Failure::BadOffset,
+                ..
+            }
+        ));
+        // A short write is never the start of a long one.
+        assembler.push(&request(), 0, fragment(b'a', 4), 2);
+        assert!(matches!(
+            assembler.push(&request(), 4, fragment(b'b', 4), 3),
+            Assembly::Rejected {
+                failure: Failure::BadOffset,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn oversize_value_is_rejected() {
+        let mut assembler = WriteAssembler::new(30);
+        assembler.push(&request(), 0, fragment(b'a', 18), 1);
+        match assembler.push(&request(), 18, fragment(b'b', 18), 2) {
+            Assembly::Rejected { failure, responder } => {
+                assert_eq!(failure, Failure::WrongLength);
+                assert_eq!(responder, 2);
+            }
+            other => panic!("expected Rejected, got {:?}", other),
+        }
+        assert!(matches!(
+            assembler.push(&request(), 0, fragment(b'a', 31), 3),
+            Assembly::Rejected {
+                failure: Failure::WrongLength,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn writes_from_other_centrals_are_kept_apart() {
+        let mut assembler = WriteAssembler::new(512);
+        let other = PeripheralRequest {
+            client: "other".to_string(),
+            ..request()
+        };
+        for (request, byte) in [(request(), b'a'), (other.clone(), b'b')] {
+            assembler.push(&request, 0, fragment(byte, 18), 1);
+            assembler.push(&request, 18, fragment(byte, 18), 2);
+        }
+        assembler.remove_central(&CentralId::from_request(&other));
+        assert!(assembler.take_stale(&other, Duration::ZERO).is_none());
+        assert!(assembler.take_stale(&request(), Duration::ZERO).is_some());
+    }
+}
@@ -1,83 +1,343 @@
-use std::io::{self, BufRead};
-use std::sync::{
-    atomic::{AtomicBool, Ordering},
-    Arc,
-};
-use tokio::sync::{mpsc, Mutex};
+use std::fmt::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+use clap::Parser;
+use tokio::sync::Mutex;
 use uuid::Uuid;
 
 use ble_peripheral_rust::{
-    gatt::{
-        characteristic::Characteristic,
-        descriptor::Descriptor,
-        peripheral_event::{
-            PeripheralEvent, ReadRequestResponse, RequestResponse, WriteRequestResponse,
-        },
-        properties::{AttributePermission, CharacteristicProperty},
-        service::Service,
-    },
-    uuid::ShortUuid,
+    gatt::peripheral_event::{PeripheralRequest, RequestResponse},
     Peripheral, PeripheralImpl,
 };
 
-static STATE: AtomicBool = AtomicBool::new(false);
+use bluetooth::{
+    adapter,
+    advertising::AdvertisementData,
+    aliases::Aliases,
+    auto_off::AutoOff,
+    builder::CharacteristicBuilder,
+    cli::{Cli, Command, RunArgs},
+    config::{self, AdvertisingConfig, Config, ConfigSource},
+    console::{self, Command as ConsoleCommand, ParseError},
+    device::Device,
+    dispatch::{dispatch_write, handle_updates, handle_write_command, take_write_command},
+    event_queue::{self, PeripheralOptions},
+    gatt_table,
+    handler::HandlerRegistry,
+    history::History,
+    logging,
+    output::{Message, OutputFormat},
+    pairing::{self, PairingRequest},
+    persist,
+    profile::{
+        machine::SharedMachine,
+        switch::{self, SwitchState},
+        Profile,
+    },
+    prompt::{self, Input, Prompt},
+    reload,
+    script::{self, Script, Step},
+    snapshot::Snapshot,
+    state::Source,
+    state_machine::{StateMachine, TransitionTable},
+    switches::SwitchBank,
+    validate, Error,
+};
+
+const DEFAULT_NAME: &str = "RustBLE";
+
+/// Client ID `write` on the console sends its requests as.
+const CONSOLE_CLIENT: &str = "console";
+
+const EXIT_OK: i32 = 0;
+/// The service file or command line could not be turned into a GATT layout.
+const EXIT_CONFIG_ERROR: i32 = 2;
+/// The adapter or backend failed.
+const EXIT_BLUETOOTH_ERROR: i32 = 3;
+/// The log file could not be opened.
+const EXIT_LOG_ERROR: i32 = 4;
+/// A `--script` command failed with `--script-fatal`.
+const EXIT_SCRIPT_ERROR: i32 = 5;
 
 #[tokio::main]
 async fn main() {
-    std::env::set_var("RUST_LOG", "info");
-    if let Err(err) = pretty_env_logger::try_init() {
-        eprintln!("WARNING: failed to initialize logging framework: {}", err);
-    }
-    start_app().await;
-}
-
-async fn start_app() {
-    let char_uuid = Uuid::from_short(0x2A3D_u16);
-
-    // Define a service with characteristics.
-    let service = Service {
-        uuid: Uuid::from_short(0x1234_u16),
-        primary: true,
-        characteristics: vec![
-            Characteristic {
-                uuid: char_uuid,
-                properties: vec![
-                    CharacteristicProperty::Read,
-                    CharacteristicProperty::Write,
-                    CharacteristicProperty::Notify,
-                ],
-                permissions: vec![
-                    AttributePermission::Readable,
-                    AttributePermission::Writeable,
-                ],
-                value: None,
-                descriptors: vec![Descriptor {
-                    uuid: Uuid::from_short(0x2A13_u16),
-                    value: Some(vec![0, 1]),
-                    ..Default::default()
-                }],
-            },
-            Characteristic {
-                uuid: Uuid::from_string("1209"),
-                ..Default::default()
-            },
-        ],
+    let args = match Cli::parse().into_command() {
+        Command::Run(args) => args,
+        Command::ValidateConfig {
+            path,
+            max_attribute_length,
+        } => std::process::exit(validate_config(&path, max_attribute_length)),
+        Command::ListAdapters => std::process::exit(list_adapters().await),
+    };
+
+    if let Err(err) = logging::init(args.log_level.as_deref(), args.log_file.as_deref()) {
+        match &args.log_file {
+            Some(path) => eprintln!("Error opening log file {}: {}", path.display(), err),
+            None => eprintln!("Error initializing logging: {}", err),
+        }
+        std::process::exit(EXIT_LOG_ERROR);
+    }
+    std::process::exit(start_app(args).await);
+}
+
+/// Prints every adapter with its address and power state.
+async fn list_adapters() -> i32 {
+    match adapter::list_adapters().await {
+        Ok(adapters) => {
+            for adapter in &adapters {
+                println!(
+                    "{}\t{}\t{}",
+                    adapter.name,
+                    adapter.address,
+                    if adapter.powered { "powered" } else { "off" }
+                );
+            }
+            EXIT_OK
+        }
+        Err(err) => {
+            eprintln!("Error listing adapters: {}", err);
+            EXIT_BLUETOOTH_ERROR
+        }
+    }
+}
+
+/// Prints a validation report for `path` and returns the process exit code.
+fn validate_config(path: &Path, max_attribute_length: usize) -> i32 {
+    let services = match config::load_services(path) {
+        Ok(services) => services,
+        Err(err) => {
+            println!("error: {}", err);
+            return EXIT_CONFIG_ERROR;
+        }
+    };
+
+    let issues = validate::validate_services(&services, max_attribute_length);
+    for issue in &issues {
+        println!("error: {}", issue);
+    }
+    let characteristics: usize = services.iter().map(|s| s.characteristics.len()).sum();
+    println!(
+        "{}: {} service(s), {} characteristic(s), {} error(s)",
+        path.display(),
+        services.len(),
+        characteristics,
+        issues.len()
+    );
+    if issues.is_empty() {
+        EXIT_OK
+    } else {
+        EXIT_CONFIG_ERROR
+    }
+}
+
+/// Runs the peripheral until the console closes and returns the exit code.
+async fn start_app(cli: RunArgs) -> i32 {
+    let profile = cli.profile;
+    let mut char_uuid = profile.console_characteristic(cli.char_uuid);
+    let mut advertising = AdvertisingConfig::default();
+    let mut files = Vec::new();
+    let mut mirrors = Vec::new();
+    let mut strict_formats = Vec::new();
+    let mut aliases = Aliases::default();
+    let machine: SharedMachine = Arc::new(std::sync::Mutex::new(StateMachine::new(
+        TransitionTable::default(),
+    )));
+    let config_source = cli.config_source();
+    let persisted = cli.state_file.as_deref().and_then(persist::load);
+    let mut initial_state = persisted.or(cli.initial_state).unwrap_or(SwitchState::Off);
+    let mut auto_off_secs = cli.auto_off_secs.unwrap_or(0);
+
+    // Define the services, either from the config file(s) or the profile's layout.
+    let mut services = match &config_source {
+        Some(source) => match source.load() {
+            Ok(Config {
+                initial_state: configured_state,
+                auto_off_secs: configured_timeout,
+                advertising: configured,
+                aliases: configured_aliases,
+                mut services,
+                mirrors: configured_mirrors,
+                strict_formats: configured_strict,
+                state_machine,
+                files: loaded,
+            }) => {
+                advertising = configured.unwrap_or_default();
+                files = loaded;
+                mirrors = configured_mirrors;
+                strict_formats = configured_strict;
+                aliases = configured_aliases;
+                if let Some(table) = state_machine {
+                    machine.lock().unwrap().set_table(table);
+                }
+                initial_state = persisted
+                    .or(cli.initial_state)
+                    .or(configured_state)
+                    .unwrap_or(SwitchState::Off);
+                auto_off_secs = cli.auto_off_secs.or(configured_timeout).unwrap_or(0);
+                // The first characteristic of the first service receives console updates.
+                if let Some(first) = services
+                    .iter_mut()
+                    .next()
+                    .and_then(|service| service.characteristics.first_mut())
+                {
+                    char_uuid = first.uuid;
+                }
+                services
+            }
+            Err(err) => {
+                log::error!("Error loading config: {}", err);
+                return EXIT_CONFIG_ERROR;
+            }
+        },
+        None => match profile.services(
+            cli.service_uuid,
+            &cli.switch_channels(char_uuid),
+            initial_state,
+            &machine.lock().unwrap(),
+        ) {
+            Ok(services) => services,
+            Err(err) => {
+                log::error!("Error building service: {}", err);
+                return EXIT_CONFIG_ERROR;
+            }
+        },
+    };
+
+    let channels = cli.switch_channels(char_uuid);
+    let history = History::new(cli.history_size);
+    let switches = match SwitchBank::new(&channels, initial_state, &history)
+        .with_strict_writes(cli.strict_writes)
+        .with_aliases(aliases)
+        .with_notify_debounce(cli.notify_debounce())
+        .with_mirrors(mirrors)
+    {
+        Ok(switches) => Arc::new(switches),
+        Err(err) => {
+            log::error!("Error loading config: {}", err);
+            return EXIT_CONFIG_ERROR;
+        }
+    };
+    if profile == Profile::Switch {
+        char_uuid = switches.primary().characteristic;
+        if config_source.is_some() {
+            // Config files don't know the state, so seed the switch values here.
+            for characteristic in services
+                .iter_mut()
+                .flat_map(|service| service.characteristics.iter_mut())
+                .filter(|characteristic| switches.by_characteristic(characteristic.uuid).is_some())
+            {
+                characteristic.value = Some(initial_state.as_str().into());
+            }
+            for mirror in switches.mirrors() {
+                if let Some(characteristic) = services
+                    .iter_mut()
+                    .flat_map(|service| service.characteristics.iter_mut())
+                    .find(|characteristic| characteristic.uuid == mirror.characteristic)
+                {
+                    characteristic.value = Some(mirror.encoding.encode(initial_state));
+                }
+            }
+        }
+        log::info!("Initial STATE: {}", initial_state.as_str());
+    }
+    let state = switches.primary().state.clone();
+
+    if cli.dry_run {
+        print!("{}", gatt_table::render(&services));
+        return EXIT_OK;
+    }
+
+    let script = match &cli.script {
+        Some(path) => match Script::load(path) {
+            Ok(script) => Some(script),
+            Err(err) => {
+                log::error!("Error reading script {}: {}", path.display(), err);
+                return EXIT_CONFIG_ERROR;
+            }
+        },
+        None => None,
     };
 
-    let (sender_tx, mut receiver_rx) = mpsc::channel::<PeripheralEvent>(256);
+    if let Some(path) = cli.state_file.clone() {
+        persist::spawn_saver(path, &state);
+    }
+
+    if let Some(name) = &cli.adapter {
+        if let Err(err) = adapter::check_selected(name).await {
+            log::error!("Error selecting adapter: {}", err);
+            return EXIT_BLUETOOTH_ERROR;
+        }
+    }
+
+    let (sender_tx, mut receiver_rx) = event_queue::event_channel(PeripheralOptions {
+        event_channel_capacity: cli.event_channel_capacity,
+        overflow: cli.event_overflow,
+    });
 
     // Create the peripheral and wrap it in an Arc with a Mutex.
-    let peripheral = Arc::new(Mutex::new(
-        Peripheral::new(sender_tx).await.unwrap(),
-    ));
+    let peripheral = match Peripheral::new(sender_tx).await {
+        Ok(peripheral) => {
+            let mut device = Device::new(peripheral);
+            log::info!("Using the {} backend", device.backend());
+            device.stats().watch_channel(receiver_rx.stats());
+            if cli.seq_numbers {
+                device.enable_sequence_numbers();
+            }
+            device.set_auto_restore(cli.auto_restore);
+            device.set_max_write_length(cli.max_write_length);
+            for uuid in &strict_formats {
+                device.set_strict_format(*uuid, true);
+            }
+            Arc::new(Mutex::new(device))
+        }
+        Err(err) => {
+            log::error!("Error creating peripheral: {}", err);
+            return EXIT_BLUETOOTH_ERROR;
+        }
+    };
 
-    // Clone the peripheral and char_uuid for the event handler.
+    // Anything that should end the console without a `quit` reports here.
+    let (shutdown_tx, mut shutdown) = tokio::sync::mpsc::channel::<Shutdown>(1);
+    let shutdown_on_interrupt = shutdown_tx.clone();
+    #[cfg(unix)]
+    let shutdown_on_terminate = shutdown_tx.clone();
+    let handlers =
+        Arc::new(profile.handlers(peripheral.clone(), switches.clone(), machine.clone()));
+    // Clone the peripheral and handlers for the event handler.
     let peripheral_for_events = peripheral.clone();
-    let char_uuid_for_events = char_uuid.clone();
+    let handlers_for_events = handlers.clone();
+    let (event_hub, stats) = {
+        let periph = peripheral.lock().await;
+        (periph.events().clone(), periph.stats().clone())
+    };
     tokio::spawn(async move {
         while let Some(event) = receiver_rx.recv().await {
-            handle_updates(event, peripheral_for_events.clone(), char_uuid_for_events).await;
+            let event = match take_write_command(event, &peripheral_for_events).await {
+                Ok(command) => {
+                    event_hub.publish_notice(command.notice());
+                    handle_write_command(
+                        command,
+                        &handlers_for_events,
+                        &peripheral_for_events,
+                        &stats,
+                    )
+                    .await;
+                    continue;
+                }
+                Err(event) => event,
+            };
+            event_hub.publish(&event);
+            handle_updates(
+                event,
+                &handlers_for_events,
+                peripheral_for_events.clone(),
+                &stats,
+            )
+            .await;
         }
+        // The backend dropped its sender, so no more events will arrive.
+        let _ = shutdown_tx.send(Shutdown::EventsClosed).await;
     });
 
     // Wait until the peripheral is powered on.
@@ -92,144 +352,799 @@ async fn start_app() {
         tokio::time::sleep(std::time::Duration::from_millis(100)).await;
     }
 
-    // Add the service.
+    // Add the services.
     {
         let mut periph = peripheral.lock().await;
-        if let Err(err) = periph.add_service(&service).await {
-            log::error!("Error adding service: {}", err);
-            return;
+        for service in &services {
+            if let Err(err) = periph.add_service(service).await {
+                log::error!("Error adding service: {}", err);
+                return EXIT_BLUETOOTH_ERROR;
+            }
         }
     }
     log::info!("Service Added");
+    if !files.is_empty() {
+        let characteristics: usize = services
+            .iter()
+            .map(|service| service.characteristics.len())
+            .sum();
+        let names: Vec<String> = files
+            .iter()
+            .map(|file| {
+                file.file_name()
+                    .unwrap_or(file.as_os_str())
+                    .to_string_lossy()
+                    .into_owned()
+            })
+            .collect();
+        log::info!(
+            "Registered {} service(s) and {} characteristic(s) from {}",
+            services.len(),
+            characteristics,
+            names.join(", ")
+        );
+    }
 
     // Start advertising.
     {
         let mut periph = peripheral.lock().await;
-        if let Err(err) = periph.start_advertising("RustBLE", &[service.uuid]).await {
+        let name = cli
+            .name
+            .clone()
+            .or(advertising.name)
+            .unwrap_or_else(|| DEFAULT_NAME.to_string());
+        let service_uuids: Vec<Uuid> = if advertising.service_uuids.is_empty() {
+            services.iter().map(|service| service.uuid).collect()
+        } else {
+            advertising.service_uuids
+        };
+        let started = if advertising.parameters.is_default()
+            && advertising.appearance.is_none()
+            && advertising.flags.is_none()
+        {
+            periph.start_advertising(&name, &service_uuids).await
+        } else {
+            periph
+                .start_advertising_with(AdvertisementData {
+                    appearance: advertising.appearance,
+                    flags: advertising.flags,
+                    parameters: advertising.parameters,
+                    ..AdvertisementData::new(&name, &service_uuids)
+                })
+                .await
+        };
+        if let Err(err) = started {
             log::error!("Error starting advertising: {}", err);
-            return;
+            return EXIT_BLUETOOTH_ERROR;
         }
     }
-    log::info!("Advertising Started");
+    profile.spawn_tasks(peripheral.clone());
+    let auto_off = AutoOff::new(auto_off_secs);
+    auto_off.spawn(switches.clone(), peripheral.clone());
 
-    // Read from stdin to update the characteristic manually.
-    let stdin = io::stdin();
-    for line in stdin.lock().lines() {
-        match line {
-            Ok(input) => {
-                let trimmed_input = input.trim().to_lowercase();
-                match trimmed_input.as_str() {
-                    "on" => {
-                        STATE.store(true, Ordering::SeqCst);
-                        println!("STATE changed to: ON ✅");
-                    }
-                    "off" => {
-                        STATE.store(false, Ordering::SeqCst);
-                        println!("STATE changed to: OFF ❌");
-                    }
-                    _ => {
-                        println!("Writing: {} to {:?}", input, char_uuid);
-                    }
-                }
-                // Update the characteristic to notify subscribed clients.
-                let mut periph = peripheral.lock().await;
-                if let Err(e) = periph.update_characteristic(char_uuid, input.into()).await {
-                    log::error!("Error updating characteristic: {:?}", e);
+    // Re-read the service file(s) whenever the process receives SIGHUP.
+    #[cfg(unix)]
+    if let Some(source) = config_source.clone() {
+        let peripheral_for_reload = peripheral.clone();
+        let switches_for_reload = switches.clone();
+        let machine_for_reload = machine.clone();
+        tokio::spawn(async move {
+            use tokio::signal::unix::{signal, SignalKind};
+            let mut hangup = match signal(SignalKind::hangup()) {
+                Ok(hangup) => hangup,
+                Err(err) => {
+                    log::error!("Error installing SIGHUP handler: {}", err);
+                    return;
                 }
+            };
+            while hangup.recv().await.is_some() {
+                log::info!("SIGHUP received, reloading {}", source.path().display());
+                reload::reload_services(
+                    &peripheral_for_reload,
+                    &switches_for_reload,
+                    &machine_for_reload,
+                    &source,
+                )
+                .await;
+            }
+        });
+    }
+
+    // Forward pairing prompts to the console. The agent stays registered
+    // until it is dropped at the end of this function.
+    let pending_pairing: Arc<std::sync::Mutex<Option<PairingRequest>>> = Arc::default();
+    let _pairing_agent = if cli.pairing_agent {
+        let (pairing_tx, mut pairing_rx) = tokio::sync::mpsc::channel::<PairingRequest>(4);
+        match pairing::register_agent(pairing_tx).await {
+            Ok(agent) => {
+                let pending = pending_pairing.clone();
+                let output = cli.output;
+                tokio::spawn(async move {
+                    while let Some(request) = pairing_rx.recv().await {
+                        match request.passkey {
+                            _ if output == OutputFormat::Json => Message::PairingRequest {
+                                central: request.central.to_string(),
+                                passkey: request.passkey,
+                            }
+                            .print(),
+                            Some(passkey) => println!(
+                                "Central {} wants to pair, passkey {:06}. Type `pair accept` or `pair reject`",
+                                request.central, passkey
+                            ),
+                            None => println!(
+                                "Central {} wants to pair. Type `pair accept` or `pair reject`",
+                                request.central
+                            ),
+                        }
+                        // A newer request replaces, and so rejects, an unanswered one.
+                        *pending.lock().unwrap() = Some(request);
+                    }
+                });
+                Some(agent)
+            }
+            Err(err) => {
+                log::warn!("Pairing agent not available: {}", err);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let console = Console {
+        handlers,
+        peripheral,
+        switches,
+        char_uuid,
+        auto_off,
+        history,
+        history_size: cli.history_size,
+        pending_pairing,
+        config_source,
+        machine,
+        output: cli.output,
+    };
+
+    tokio::spawn(async move {
+        // Also catches Ctrl-C while a command or the script runs, when the
+        // prompt is not reading the terminal. Installed only now so Ctrl-C
+        // still kills the process during startup.
+        while tokio::signal::ctrl_c().await.is_ok() {
+            if shutdown_on_interrupt
+                .send(Shutdown::Interrupted)
+                .await
+                .is_err()
+            {
+                break;
             }
+        }
+    });
+
+    // systemd stops services with SIGTERM.
+    #[cfg(unix)]
+    tokio::spawn(async move {
+        use tokio::signal::unix::{signal, SignalKind};
+        let mut terminate = match signal(SignalKind::terminate()) {
+            Ok(terminate) => terminate,
             Err(err) => {
-                log::error!("Error reading from console: {}", err);
+                log::error!("Error installing SIGTERM handler: {}", err);
+                return;
+            }
+        };
+        while terminate.recv().await.is_some() {
+            if shutdown_on_terminate
+                .send(Shutdown::Terminated)
+                .await
+                .is_err()
+            {
                 break;
             }
         }
+    });
+
+    if cli.headless {
+        let mut surfaces = vec!["BLE writes"];
+        if cfg!(unix) && console.config_source.is_some() {
+            surfaces.push("SIGHUP (reload)");
+        }
+        if script.is_some() {
+            surfaces.push("--script");
+        }
+        log::info!(
+            "Running headless, console disabled. Control: {}",
+            surfaces.join(", ")
+        );
+        if cli.pairing_agent {
+            log::warn!("Pairing requests cannot be answered without the console");
+        }
+    }
+
+    let mut exit_code = EXIT_OK;
+    let finished = match &script {
+        Some(script) => match tokio::select! {
+            result = run_script(&console, script, cli.script_fatal) => result,
+            Some(reason) = shutdown.recv() => {
+                exit_code = console.shut_down(reason).await;
+                Ok(Flow::Quit)
+            }
+        } {
+            Ok(Flow::Quit) => true,
+            Ok(Flow::Continue) if cli.script_exit => {
+                console.stop_advertising().await;
+                true
+            }
+            Ok(Flow::Continue) => false,
+            Err(()) => {
+                console.stop_advertising().await;
+                exit_code = EXIT_SCRIPT_ERROR;
+                true
+            }
+        },
+        None => false,
+    };
+
+    if !finished && cli.headless {
+        // Nothing reads stdin, so it being closed does not matter.
+        if let Some(reason) = shutdown.recv().await {
+            exit_code = console.shut_down(reason).await;
+        }
     }
+
+    // Read console commands from the prompt; Ctrl-C shuts down like `quit`.
+    if !finished && !cli.headless {
+        let mut prompt = match console.output {
+            OutputFormat::Text => Prompt::spawn(
+                prompt::default_history_path(),
+                console.peripheral.lock().await.known_uuids(),
+            ),
+            OutputFormat::Json => Prompt::spawn_plain(),
+        };
+        loop {
+            let input = tokio::select! {
+                input = prompt.next() => input,
+                Some(reason) = shutdown.recv() => {
+                    exit_code = console.shut_down(reason).await;
+                    break;
+                }
+            };
+            let command = match input {
+                Input::Line(line) => match console::parse(&line, &console.switches.aliases()) {
+                    Ok(command) => command,
+                    Err(ParseError::Empty) => continue,
+                    Err(err) => {
+                        console.print_error(&err);
+                        continue;
+                    }
+                },
+                Input::Interrupted => ConsoleCommand::Quit,
+                Input::Closed => break,
+            };
+            match console.execute(command).await {
+                Ok(Flow::Continue) => {}
+                Ok(Flow::Quit) => break,
+                Err(err) => log::error!("{}", err),
+            }
+        }
+    }
+
+    // Leave the adapter clean instead of relying on drop order at exit.
+    if let Err(err) = console.peripheral.lock().await.close().await {
+        log::error!("Error shutting down: {}", err);
+        return EXIT_BLUETOOTH_ERROR;
+    }
+    exit_code
 }
 
-async fn handle_updates(
-    event: PeripheralEvent,
-    peripheral: Arc<Mutex<Peripheral>>,
+/// Why the console stopped without a `quit` or the end of its input.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Shutdown {
+    /// Ctrl-C while a command was running, or in headless mode.
+    Interrupted,
+    /// SIGTERM, e.g. from systemd.
+    Terminated,
+    /// The backend closed the event channel, e.g. because the adapter went
+    /// away.
+    EventsClosed,
+}
+
+/// Whether the console keeps reading commands after one has run.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Flow {
+    Continue,
+    Quit,
+}
+
+/// What console commands act on, shared by the prompt and `--script`.
+struct Console {
+    handlers: Arc<HandlerRegistry>,
+    peripheral: Arc<Mutex<Device>>,
+    switches: Arc<SwitchBank>,
     char_uuid: Uuid,
-) {
-    match event {
-        PeripheralEvent::StateUpdate { is_powered } => {
-            log::info!("PowerOn: {:?}", is_powered);
-        }
-        PeripheralEvent::CharacteristicSubscriptionUpdate { request, subscribed } => {
-            log::info!(
-                "CharacteristicSubscriptionUpdate: Subscribed {} {:?}",
-                subscribed,
-                request
-            );
-        }
-        PeripheralEvent::ReadRequest {
-            request,
-            offset,
-            responder,
-        } => {
-            let current_state = STATE.load(Ordering::SeqCst);
-            let response_value = if current_state { "on" } else { "off" };
-
-            log::info!(
-                "ReadRequest: {:?} Offset: {} -> Responding: {}",
-                request,
-                offset,
-                response_value
-            );
-
-            if let Err(e) = responder.send(ReadRequestResponse {
-                value: response_value.into(),
-                response: RequestResponse::Success,
-            }) {
-                log::error!("Failed to send read response: {:?}", e);
-            }
-        }
-        PeripheralEvent::WriteRequest {
-            request,
-            offset,
-            value,
-            responder,
-        } => {
-            if let Ok(msg) = String::from_utf8(value.clone()) {
-                log::info!("WriteRequest: Received message -> {}", msg);
-
-                let new_value = match msg.trim() {
-                    "on" => {
-                        STATE.store(true, Ordering::SeqCst);
-                        log::info!("STATE changed to: ON ✅");
-                        "on"
-                    }
-                    "off" => {
-                        STATE.store(false, Ordering::SeqCst);
-                        log::info!("STATE changed to: OFF ❌");
-                        "off"
+    auto_off: AutoOff,
+    history: History,
+    history_size: usize,
+    pending_pairing: Arc<std::sync::Mutex<Option<PairingRequest>>>,
+    config_source: Option<ConfigSource>,
+    machine: SharedMachine,
+    output: OutputFormat,
+}
+
+/// What a command shows once it has run.
+enum Reply {
+    /// Nothing in text mode; JSON mode still acknowledges the command.
+    Done,
+    /// Text for the user, and the `message` of the JSON `ack`.
+    Ack(String),
+    /// Output with a JSON form of its own.
+    Report { text: String, json: Message },
+}
+
+impl Console {
+    /// Runs one command and prints what it did, returning why it failed for
+    /// the caller to log.
+    async fn execute(&self, command: ConsoleCommand) -> Result<Flow, String> {
+        let name = command.name();
+        let flow = match command {
+            ConsoleCommand::Quit => Flow::Quit,
+            _ => Flow::Continue,
+        };
+        let reply = self.run(command).await.inspect_err(|err| {
+            if self.output == OutputFormat::Json {
+                Message::Error {
+                    message: err.clone(),
+                }
+                .print();
+            }
+        })?;
+        match (self.output, reply) {
+            (OutputFormat::Text, Reply::Done) => {}
+            (OutputFormat::Text, Reply::Ack(text)) => println!("{}", text),
+            (OutputFormat::Text, Reply::Report { text, .. }) => print!("{}", text),
+            (OutputFormat::Json, Reply::Report { json, .. }) => json.print(),
+            (OutputFormat::Json, Reply::Ack(message)) => Message::Ack {
+                command: name.to_string(),
+                message,
+            }
+            .print(),
+            (OutputFormat::Json, Reply::Done) => Message::Ack {
+                command: name.to_string(),
+                message: String::new(),
+            }
+            .print(),
+        }
+        Ok(flow)
+    }
+
+    /// Shows a line that is not a command: as is in text mode, as an `error`
+    /// object in JSON.
+    fn print_error(&self, err: &ParseError) {
+        match self.output {
+            OutputFormat::Text => println!("{}", err),
+            OutputFormat::Json => Message::Error {
+                message: err.to_string(),
+            }
+            .print(),
+        }
+    }
+
+    async fn run(&self, command: ConsoleCommand) -> Result<Reply, String> {
+        let Console {
+            handlers,
+            peripheral,
+            switches,
+            char_uuid,
+            auto_off,
+            history,
+            history_size,
+            pending_pairing,
+            config_source,
+            machine,
+            output: _,
+        } = self;
+        let char_uuid = *char_uuid;
+        let reply = match command {
+            ConsoleCommand::Help => Reply::Report {
+                text: console::help(),
+                json: Message::help(),
+            },
+            ConsoleCommand::Status => {
+                let periph = peripheral.lock().await;
+                Reply::Report {
+                    text: status_text(&periph, switches),
+                    json: Message::status(&periph, switches),
+                }
+            }
+            ConsoleCommand::Quit => {
+                self.stop_advertising().await;
+                Reply::Done
+            }
+            ConsoleCommand::Gatt => {
+                let periph = peripheral.lock().await;
+                Reply::Report {
+                    text: gatt_table::render_device(&periph),
+                    json: Message::gatt(&periph),
+                }
+            }
+            ConsoleCommand::Subscriptions => {
+                let periph = peripheral.lock().await;
+                Reply::Report {
+                    text: subscriptions_text(&periph),
+                    json: Message::subscriptions(&periph),
+                }
+            }
+            ConsoleCommand::AdvertiseStop => {
+                let mut periph = peripheral.lock().await;
+                match periph.is_advertising().await {
+                    Ok(false) => Reply::Ack("Advertising is already stopped".to_string()),
+                    _ => match periph.stop_advertising().await {
+                        Ok(()) => Reply::Ack("Advertising stopped".to_string()),
+                        Err(err) => return Err(format!("Error stopping advertising: {}", err)),
+                    },
+                }
+            }
+            ConsoleCommand::AdvertiseStart(name) => {
+                let mut periph = peripheral.lock().await;
+                match periph.restart_advertising(name.as_deref()).await {
+                    Ok(()) => Reply::Ack(advertising_text(&periph)),
+                    Err(Error::AlreadyAdvertising) => Reply::Ack(format!(
+                        "Advertising is already running\n{}",
+                        advertising_text(&periph)
+                    )),
+                    Err(err) => return Err(format!("Error starting advertising: {}", err)),
+                }
+            }
+            ConsoleCommand::Rename(name) => {
+                let mut periph = peripheral.lock().await;
+                match periph.set_local_name(&name).await {
+                    Ok(()) => Reply::Ack(advertising_text(&periph)),
+                    Err(err) => return Err(format!("Cannot rename to '{}': {}", name, err)),
+                }
+            }
+            ConsoleCommand::Send(text) => {
+                let ack = format!("Writing: {} to {:?}", text, char_uuid);
+                let mut periph = peripheral.lock().await;
+                if let Err(e) = periph.update_characteristic(char_uuid, text.into()).await {
+                    return Err(format!("Error updating characteristic: {:?}", e));
+                }
+                Reply::Ack(ack)
+            }
+            ConsoleCommand::Write {
+                characteristic,
+                text,
+            } => {
+                let service = find_service(&*peripheral.lock().await, characteristic)?;
+                let request = PeripheralRequest {
+                    client: CONSOLE_CLIENT.to_string(),
+                    service,
+                    characteristic,
+                };
+                let response =
+                    dispatch_write(&request, text.into_bytes(), handlers, peripheral).await;
+                match response.response {
+                    RequestResponse::Success => {
+                        Reply::Ack(format!("Wrote to {:?}", characteristic))
                     }
-                    _ => {
-                        log::warn!("WriteRequest: Unrecognized value -> {}", msg);
-                        msg.as_str()
+                    refused => {
+                        return Err(format!(
+                            "Write to {:?} refused with {:?}",
+                            characteristic, refused
+                        ))
                     }
-                };
-
-                // Update the characteristic to notify subscribed clients.
-                if let Err(e) = peripheral
+                }
+            }
+            ConsoleCommand::AddCharacteristic { uuid, properties } => {
+                let characteristic = properties
+                    .into_iter()
+                    .fold(
+                        CharacteristicBuilder::new(uuid),
+                        CharacteristicBuilder::property,
+                    )
+                    .build()
+                    .map_err(|err| format!("Cannot build characteristic {}: {}", uuid, err))?;
+                let service = peripheral
                     .lock()
                     .await
-                    .update_characteristic(char_uuid, new_value.into())
+                    .add_characteristic(characteristic)
                     .await
-                {
-                    log::error!("Error updating characteristic in WriteRequest: {:?}", e);
+                    .map_err(|err| format!("Error adding characteristic {}: {}", uuid, err))?;
+                Reply::Ack(format!(
+                    "Added {} in new service {}; the backend cannot extend a registered \
+                     service, and centrals must rediscover to see it",
+                    uuid, service
+                ))
+            }
+            ConsoleCommand::RemoveCharacteristic(uuid) => {
+                peripheral
+                    .lock()
+                    .await
+                    .remove_characteristic(uuid)
+                    .map_err(|err| format!("Error removing characteristic {}: {}", uuid, err))?;
+                Reply::Ack(format!(
+                    "Removed {}; the backend cannot unregister it, so centrals still see it \
+                     until restart but requests to it are rejected",
+                    uuid
+                ))
+            }
+            ConsoleCommand::Hex {
+                characteristic,
+                value,
+            } => {
+                let uuid = characteristic.unwrap_or(char_uuid);
+                let ack = format!("Writing {} byte(s) to {:?}", value.len(), uuid);
+                let mut periph = peripheral.lock().await;
+                if let Err(e) = periph.update_characteristic(uuid, value).await {
+                    return Err(format!("Error updating characteristic: {:?}", e));
+                }
+                Reply::Ack(ack)
+            }
+            ConsoleCommand::Power(new_state) => {
+                switch::set(switches.primary(), new_state, Source::Stdin, peripheral).await;
+                Reply::Done
+            }
+            ConsoleCommand::Set {
+                switch: name,
+                state: new_state,
+            } => {
+                set_switch(peripheral, switches, &name, new_state).await?;
+                Reply::Done
+            }
+            ConsoleCommand::Brightness(level) => {
+                switch::set_brightness(level, Source::Stdin, None, switches, peripheral).await;
+                Reply::Done
+            }
+            ConsoleCommand::Timeout(secs) => {
+                auto_off.set_timeout_secs(secs);
+                match secs {
+                    0 => Reply::Ack("Auto-off disabled".to_string()),
+                    _ => Reply::Ack(format!("Auto-off after {}s", secs)),
                 }
-            } else {
-                log::error!("WriteRequest: Received non-UTF8 data");
             }
+            ConsoleCommand::Stats { reset } => {
+                let periph = peripheral.lock().await;
+                if reset {
+                    periph.stats().reset();
+                    Reply::Ack("Statistics reset".to_string())
+                } else {
+                    let snapshot = periph.stats().snapshot();
+                    Reply::Report {
+                        text: snapshot.to_string(),
+                        json: Message::Stats(snapshot),
+                    }
+                }
+            }
+            ConsoleCommand::History => {
+                let transitions = history.recent(*history_size);
+                let text = if history.is_empty() {
+                    "No state changes yet\n".to_string()
+                } else {
+                    transitions
+                        .iter()
+                        .map(|transition| format!("{}\n", transition))
+                        .collect()
+                };
+                Reply::Report {
+                    text,
+                    json: Message::history(&transitions),
+                }
+            }
+            ConsoleCommand::Pair { accept } => {
+                let pending = pending_pairing.lock().unwrap().take();
+                match pending {
+                    Some(request) if accept => request.accept(),
+                    Some(request) => request.reject(),
+                    None => return Ok(Reply::Ack("No pairing request is waiting".to_string())),
+                }
+                Reply::Done
+            }
+            ConsoleCommand::Disconnect(central) => {
+                let result = peripheral.lock().await.disconnect(central.clone()).await;
+                match result {
+                    Ok(()) => Reply::Ack(format!("Disconnected {}", central)),
+                    Err(err) => return Err(format!("Error disconnecting {}: {}", central, err)),
+                }
+            }
+            ConsoleCommand::Snapshot(path) => {
+                let snapshot = Snapshot::capture(&*peripheral.lock().await, switches);
+                snapshot.write(&path).map_err(|err| {
+                    format!("Error writing snapshot to {}: {}", path.display(), err)
+                })?;
+                Reply::Ack(format!("Wrote snapshot to {}", path.display()))
+            }
+            ConsoleCommand::SaveConfig { path, force } => Reply::Ack(
+                save_config(peripheral, switches, machine, auto_off, &path, force).await?,
+            ),
+            ConsoleCommand::Reload => match config_source {
+                Some(source) => {
+                    reload::reload_services(peripheral, switches, machine, source).await;
+                    Reply::Done
+                }
+                None => Reply::Ack("No --config file or --config-dir to reload".to_string()),
+            },
+        };
+        Ok(reply)
+    }
+
+    /// Stops advertising on the way out, as `quit` does.
+    async fn stop_advertising(&self) {
+        if let Err(err) = self.peripheral.lock().await.stop_advertising().await {
+            log::error!("Error stopping advertising: {}", err);
+        }
+    }
 
-            if let Err(e) = responder.send(WriteRequestResponse {
-                response: RequestResponse::Success,
-            }) {
-                log::error!("Failed to send write response: {:?}", e);
+    /// Winds down for `reason` and returns the exit code to use.
+    async fn shut_down(&self, reason: Shutdown) -> i32 {
+        match reason {
+            Shutdown::Interrupted => {
+                self.stop_advertising().await;
+                EXIT_OK
+            }
+            Shutdown::Terminated => {
+                log::info!("SIGTERM received, shutting down");
+                self.stop_advertising().await;
+                EXIT_OK
+            }
+            Shutdown::EventsClosed => {
+                log::error!("The Bluetooth backend stopped sending events, shutting down");
+                EXIT_BLUETOOTH_ERROR
             }
         }
-        _ => {
-            log::info!("Unhandled event: {:?}", event);
+    }
+}
+
+/// The service `characteristic` is registered in, or an error listing the
+/// characteristics there are.
+fn find_service(device: &Device, characteristic: Uuid) -> Result<Uuid, String> {
+    if let Some(service) = device.services().iter().find(|service| {
+        service
+            .characteristics
+            .iter()
+            .any(|candidate| candidate.uuid == characteristic)
+    }) {
+        return Ok(service.uuid);
+    }
+    let registered: Vec<String> = device
+        .services()
+        .iter()
+        .flat_map(|service| &service.characteristics)
+        .map(|candidate| config::format_uuid(&candidate.uuid))
+        .collect();
+    Err(format!(
+        "Unknown characteristic {}, registered: {}",
+        config::format_uuid(&characteristic),
+        registered.join(", ")
+    ))
+}
+
+/// Runs `script` line by line after startup. A line that fails is logged,
+/// and with `fatal` stops the script with `Err`.
+async fn run_script(console: &Console, script: &Script, fatal: bool) -> Result<Flow, ()> {
+    for (number, line) in script.lines() {
+        log::info!("{}:{}: {}", script.path().display(), number, line);
+        let result = match script::parse_step(line, &console.switches.aliases()) {
+            Ok(Step::Sleep(duration)) => {
+                tokio::time::sleep(duration).await;
+                Ok(Flow::Continue)
+            }
+            Ok(Step::Command(command)) => console.execute(command).await,
+            Err(err) => {
+                if console.output == OutputFormat::Json {
+                    console.print_error(&err);
+                }
+                Err(err.to_string())
+            }
+        };
+        match result {
+            Ok(Flow::Continue) => {}
+            Ok(Flow::Quit) => return Ok(Flow::Quit),
+            Err(err) => {
+                log::error!("{}:{}: {}", script.path().display(), number, err);
+                if fatal {
+                    return Err(());
+                }
+            }
         }
     }
+    Ok(Flow::Continue)
+}
+
+/// Handles `set <name> on|off` from the console.
+async fn set_switch(
+    peripheral: &Arc<Mutex<Device>>,
+    switches: &SwitchBank,
+    name: &str,
+    new_state: SwitchState,
+) -> Result<(), String> {
+    let Some(target) = switches.by_name(name) else {
+        let names: Vec<&str> = switches.iter().map(|switch| switch.name.as_str()).collect();
+        return Err(format!(
+            "Unknown switch '{}', expected one of: {}",
+            name,
+            names.join(", ")
+        ));
+    };
+    switch::set(target, new_state, Source::Stdin, peripheral).await;
+    Ok(())
+}
+
+/// What `status` prints.
+fn status_text(device: &Device, switches: &SwitchBank) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "Uptime: {}s", switches.uptime().as_secs());
+    let _ = writeln!(out, "{}", advertising_text(device));
+    for switch in switches.iter() {
+        let state = switch.state.read().unwrap();
+        let origin = match state.last_origin() {
+            Some(origin) => format!(
+                " from central {} on {}",
+                origin.central, origin.characteristic
+            ),
+            None => String::new(),
+        };
+        let _ = writeln!(
+            out,
+            "{}: {}, brightness {}, changed {}s ago by {}{}",
+            switch.name,
+            state.switch_state().as_str(),
+            state.brightness(),
+            state.last_changed().elapsed().as_secs(),
+            state.last_source(),
+            origin
+        );
+    }
+    let _ = writeln!(
+        out,
+        "Connected centrals: {}, subscriptions: {}",
+        device.connected_centrals().len(),
+        device.subscriptions().len()
+    );
+    out
+}
+
+/// What `subs` prints.
+fn subscriptions_text(device: &Device) -> String {
+    let entries = device.subscriptions().entries();
+    if entries.is_empty() {
+        return "No subscriptions\n".to_string();
+    }
+    let mut out = String::new();
+    for (central, characteristic, since) in entries {
+        let secs = since.elapsed().map_or(0, |elapsed| elapsed.as_secs());
+        let _ = writeln!(out, "{} -> {} for {}s", central, characteristic, secs);
+    }
+    out
+}
+
+fn advertising_text(device: &Device) -> String {
+    match device.advertisement() {
+        Some(data) => format!(
+            "Advertising as '{}' with {} service UUID(s)",
+            data.advertised_name(),
+            data.all_service_uuids().len()
+        ),
+        None => match device.local_name() {
+            Some(name) => format!("Not advertising (name '{}')", name),
+            None => "Not advertising".to_string(),
+        },
+    }
+}
+
+/// Handles `save-config <path> [--force]` from the console.
+async fn save_config(
+    peripheral: &Mutex<Device>,
+    switches: &SwitchBank,
+    machine: &std::sync::Mutex<StateMachine>,
+    auto_off: &AutoOff,
+    path: &Path,
+    force: bool,
+) -> Result<String, String> {
+    let table = machine.lock().unwrap().table().clone();
+    let config = Config::capture(
+        &*peripheral.lock().await,
+        switches,
+        &table,
+        auto_off.timeout_secs(),
+    );
+    config::save_config(path, &config, force)
+        .map(|()| format!("Saved configuration to {}", path.display()))
+        .map_err(|err| format!("Error saving config: {}", err))
 }
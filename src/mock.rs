@@ -0,0 +1,133 @@
+use std::sync::{Arc, Mutex};
+
+use tokio::sync::mpsc::{self, error::SendError, Sender};
+use uuid::Uuid;
+
+use ble_peripheral_rust::{
+    error::Error, gatt::peripheral_event::PeripheralEvent, gatt::service::Service, PeripheralImpl,
+};
+
+#[derive(Debug, Default)]
+struct MockState {
+    powered: bool,
+    advertising: Option<(String, Vec<Uuid>)>,
+    services: Vec<Service>,
+    sent: Vec<(Uuid, Vec<u8>)>,
+}
+
+/// An in-memory backend that records what the app asks of it, so app logic
+/// can run without Bluetooth hardware.
+///
+/// Clones share their state: hand one to `Device::mock` and keep another to
+/// inject events and inspect what was sent.
+#[derive(Debug, Clone)]
+pub struct MockPeripheral {
+    sender: Sender<PeripheralEvent>,
+    state: Arc<Mutex<MockState>>,
+}
+
+impl MockPeripheral {
+    /// A powered mock that delivers injected events to `sender`, as the
+    /// platform backend does.
+    pub fn with_sender(sender: Sender<PeripheralEvent>) -> Self {
+        Self {
+            sender,
+            state: Arc::new(Mutex::new(MockState {
+                powered: true,
+                ..MockState::default()
+            })),
+        }
+    }
+
+    /// A mock together with the receiver its events arrive on.
+    pub fn channel(capacity: usize) -> (Self, mpsc::Receiver<PeripheralEvent>) {
+        let (sender, receiver) = mpsc::channel(capacity);
+        (Self::with_sender(sender), receiver)
+    }
+
+    /// Delivers `event` as if the platform had reported it. Read and write
+    /// requests carry a responder, so the caller can await the app's answer.
+    pub async fn inject_event(
+        &self,
+        event: PeripheralEvent,
+    ) -> Result<(), SendError<PeripheralEvent>> {
+        self.sender.send(event).await
+    }
+
+    /// Every value passed to `update_characteristic` for `uuid`, oldest first.
+    pub fn sent_values(&self, uuid: Uuid) -> Vec<Vec<u8>> {
+        self.state
+            .lock()
+            .unwrap()
+            .sent
+            .iter()
+            .filter(|(sent_uuid, _)| *sent_uuid == uuid)
+            .map(|(_, value)| value.clone())
+            .collect()
+    }
+
+    /// Services passed to `add_service`, in order.
+    pub fn added_services(&self) -> Vec<Service> {
+        self.state.lock().unwrap().services.clone()
+    }
+
+    /// The name and service UUIDs being advertised, if advertising.
+    pub fn advertising(&self) -> Option<(String, Vec<Uuid>)> {
+        self.state.lock().unwrap().advertising.clone()
+    }
+
+    /// Simulates the adapter being switched on or off. Powering off stops
+    /// advertising, as the platform stack does; send a `StateUpdate` with
+    /// `inject_event` to tell the app.
+    pub fn set_powered(&self, powered: bool) {
+        let mut state = self.state.lock().unwrap();
+        state.powered = powered;
+        if !powered {
+            state.advertising = None;
+        }
+    }
+}
+
+impl PeripheralImpl for MockPeripheral {
+    type Peripheral = MockPeripheral;
+
+    async fn new(sender_tx: Sender<PeripheralEvent>) -> Result<Self::Peripheral, Error> {
+        Ok(Self::with_sender(sender_tx))
+    }
+
+    async fn is_powered(&mut self) -> Result<bool, Error> {
+        Ok(self.state.lock().unwrap().powered)
+    }
+
+    async fn is_advertising(&mut self) -> Result<bool, Error> {
+        Ok(self.state.lock().unwrap().advertising.is_some())
+    }
+
+    async fn start_advertising(&mut self, name: &str, uuids: &[Uuid]) -> Result<(), Error> {
+        self.state.lock().unwrap().advertising = Some((name.to_string(), uuids.to_vec()));
+        Ok(())
+    }
+
+    async fn stop_advertising(&mut self) -> Result<(), Error> {
+        self.state.lock().unwrap().advertising = None;
+        Ok(())
+    }
+
+    async fn add_service(&mut self, service: &Service) -> Result<(), Error> {
+        self.state.lock().unwrap().services.push(service.clone());
+        Ok(())
+    }
+
+    async fn update_characteristic(
+        &mut self,
+        characteristic: Uuid,
+        value: Vec<u8>,
+    ) -> Result<(), Error> {
+        self.state
+            .lock()
+            .unwrap()
+            .sent
+            .push((characteristic, value));
+        Ok(())
+    }
+}
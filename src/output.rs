@@ -0,0 +1,395 @@
+use clap::ValueEnum;
+use serde::Serialize;
+
+use crate::config::property_name;
+use crate::console::COMMANDS;
+use crate::device::Device;
+use crate::history::Transition;
+use crate::snapshot::{hex, unix_secs, AdvertisingSnapshot, SwitchSnapshot};
+use crate::stats::StatsSnapshot;
+use crate::switches::SwitchBank;
+
+/// How console commands print what they did.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum OutputFormat {
+    /// Human-readable text.
+    #[default]
+    Text,
+    /// One `Message` per line on stdout, with log lines only on stderr.
+    Json,
+}
+
+/// One line of `--output json`. The `type` field names the variant, e.g.
+/// `{"type":"ack","command":"timeout","message":"Auto-off after 30s"}`.
+/// Fields are only ever added, so consumers can rely on the ones they know.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Message {
+    Status(StatusReport),
+    Subscriptions {
+        subscriptions: Vec<SubscriptionEntry>,
+    },
+    Stats(StatsSnapshot),
+    Gatt {
+        services: Vec<GattService>,
+    },
+    History {
+        transitions: Vec<TransitionEntry>,
+    },
+    Help {
+        commands: Vec<HelpEntry>,
+    },
+    /// A command succeeded. `message` is what text mode prints, empty for
+    /// commands that print nothing there.
+    Ack {
+        command: String,
+        message: String,
+    },
+    /// A line did not parse or a command failed.
+    Error {
+        message: String,
+    },
+    /// A central wants to pair; answer with `pair accept` or `pair reject`.
+    PairingRequest {
+        central: String,
+        passkey: Option<u32>,
+    },
+}
+
+/// What `status` prints.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct StatusReport {
+    pub uptime_secs: u64,
+    /// What is being advertised, or `None` when advertising is stopped.
+    pub advertising: Option<AdvertisingSnapshot>,
+    pub local_name: Option<String>,
+    pub switches: Vec<SwitchSnapshot>,
+    pub connected_centrals: usize,
+    pub subscriptions: usize,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SubscriptionEntry {
+    pub central: String,
+    pub characteristic: String,
+    pub secs: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct GattService {
+    pub uuid: String,
+    pub primary: bool,
+    pub characteristics: Vec<GattCharacteristic>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct GattCharacteristic {
+    pub uuid: String,
+    pub properties: Vec<&'static str>,
+    /// Last value set on the characteristic, as lowercase hex.
+    pub value: Option<String>,
+    pub subscribers: usize,
+    pub descriptors: Vec<GattDescriptor>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct GattDescriptor {
+    pub uuid: String,
+    pub value: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct TransitionEntry {
+    /// Unix time of the change, in seconds.
+    pub at: u64,
+    pub switch: String,
+    pub old: String,
+    pub new: String,
+    pub source: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct HelpEntry {
+    pub name: &'static str,
+    pub usage: &'static str,
+    pub summary: &'static str,
+}
+
+impl Message {
+    pub fn status(device: &Device, switches: &SwitchBank) -> Self {
+        Message::Status(StatusReport {
+            uptime_secs: switches.uptime().as_secs(),
+            advertising: AdvertisingSnapshot::of(device),
+            local_name: device.local_name().map(ToString::to_string),
+            switches: switches.iter().map(SwitchSnapshot::of).collect(),
+            connected_centrals: device.connected_centrals().len(),
+            subscriptions: device.subscriptions().len(),
+        })
+    }
+
+    pub fn subscriptions(device: &Device) -> Self {
+        let subscriptions = device
+            .subscriptions()
+            .entries()
+            .into_iter()
+            .map(|(central, characteristic, since)| SubscriptionEntry {
+                central: central.to_string(),
+                characteristic: characteristic.to_string(),
+                secs: since.elapsed().map_or(0, |elapsed| elapsed.as_secs()),
+            })
+            .collect();
+        Message::Subscriptions { subscriptions }
+    }
+
+    pub fn gatt(device: &Device) -> Self {
+        let services = device
+            .services()
+            .iter()
+            .map(|service| GattService {
+                uuid: service.uuid.to_string(),
+                primary: service.primary,
+                characteristics: service
+                    .characteristics
+                    .iter()
+                    .map(|characteristic| GattCharacteristic {
+                        uuid: characteristic.uuid.to_string(),
+                        properties: characteristic
+                            .properties
+                            .iter()
+                            .map(property_name)
+                            .collect(),
+                        value: characteristic.value.as_deref().map(hex),
+                        subscribers: device.subscriptions().subscriber_count(characteristic.uuid),
+                        descriptors: characteristic
+                            .descriptors
+                            .iter()
+                            .map(|descriptor| GattDescriptor {
+                                uuid: descriptor.uuid.to_string(),
+                                value: descriptor.value.as_deref().map(hex),
+                            })
+                            .collect(),
+                    })
+                    .collect(),
+            })
+            .collect();
+        Message::Gatt { services }
+    }
+
+    pub fn history(transitions: &[Transition]) -> Self {
+        let transitions = transitions
+            .iter()
+            .map(|transition| TransitionEntry {
+                at: unix_secs(transition.at),
+                switch: transition.switch.clone(),
+                old: transition.old.as_str().to_string(),
+                new: transition.new.as_str().to_string(),
+                source: transition.source.to_string(),
+            })
+            .collect();
+        Message::History { transitions }
+    }
+
+    pub fn help() -> Self {
+        let commands = COMMANDS
+            .iter()
+            .map(|info| HelpEntry {
+                name: info.name,
+                usage: info.usage,
+                summary: info.summary,
+            })
+            .collect();
+        Message::Help { commands }
+    }
+
+    /// Writes the message to stdout as one line of JSON.
+    pub fn print(&self) {
+        match serde_json::to_string(self) {
+            Ok(line) => println!("{}", line),
+            Err(err) => log::error!("Error encoding console output: {}", err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::*;
+    use crate::profile::switch::SwitchState;
+    use crate::state::Source;
+
+    fn line(message: &Message) -> String {
+        serde_json::to_string(message).unwrap()
+    }
+
+    #[test]
+    fn ack_and_error_lines() {
+        let ack = Message::Ack {
+            command: "timeout".to_string(),
+            message: "Auto-off after 30s".to_string(),
+        };
+        assert_eq!(
+            line(&ack),
+            r#"{"type":"ack","command":"timeout","message":"Auto-off after 30s"}"#
+        );
+        let error = Message::Error {
+            message: "Unknown command".to_string(),
+        };
+        assert_eq!(
+            line(&error),
+            r#"{"type":"error","message":"Unknown command"}"#
+        );
+    }
+
+    #[test]
+    fn pairing_request_line() {
+        let request = Message::PairingRequest {
+            central: "central".to_string(),
+            passkey: Some(123456),
+        };
+        assert_eq!(
+            line(&request),
+            r#"{"type":"pairing_request","central":"central","passkey":123456}"#
+        );
+        let request = Message::PairingRequest {
+            central: "central".to_string(),
+            passkey: None,
+        };
+        assert_eq!(
+            line(&request),
+            r#"{"type":"pairing_request","central":"central","passkey":null}"#
+        );
+    }
+
+    #[test]
+    fn status_line() {
+        let status = Message::Status(StatusReport {
+            uptime_secs: 42,
+            advertising: Some(AdvertisingSnapshot {
+                name: "Lamp".to_string(),
+                service_uuids: vec!["0000180f-0000-1000-8000-00805f9b34fb".to_string()],
+            }),
+            local_name: Some("Lamp".to_string()),
+            switches: vec![SwitchSnapshot {
+                name: "lamp".to_string(),
+                characteristic: "00002a56-0000-1000-8000-00805f9b34fb".to_string(),
+                power: true,
+                brightness: 100,
+                last_source: "stdin".to_string(),
+                secs_since_change: 3,
+                last_central: None,
+            }],
+            connected_centrals: 1,
+            subscriptions: 2,
+        });
+        assert_eq!(
+            line(&status),
+            concat!(
+                r#"{"type":"status","uptime_secs":42,"#,
+                r#""advertising":{"name":"Lamp","service_uuids":["0000180f-0000-1000-8000-00805f9b34fb"]},"#,
+                r#""local_name":"Lamp","#,
+                r#""switches":[{"name":"lamp","characteristic":"00002a56-0000-1000-8000-00805f9b34fb","#,
+                r#""power":true,"brightness":100,"last_source":"stdin","secs_since_change":3,"last_central":null}],"#,
+                r#""connected_centrals":1,"subscriptions":2}"#
+            )
+        );
+    }
+
+    #[test]
+    fn subscriptions_and_gatt_lines() {
+        let subscriptions = Message::Subscriptions {
+            subscriptions: vec![SubscriptionEntry {
+                central: "central".to_string(),
+                characteristic: "00002a56-0000-1000-8000-00805f9b34fb".to_string(),
+                secs: 5,
+            }],
+        };
+        assert_eq!(
+            line(&subscriptions),
+            concat!(
+                r#"{"type":"subscriptions","subscriptions":[{"central":"central","#,
+                r#""characteristic":"00002a56-0000-1000-8000-00805f9b34fb","secs":5}]}"#
+            )
+        );
+        let gatt = Message::Gatt {
+            services: vec![GattService {
+                uuid: "0000180f-0000-1000-8000-00805f9b34fb".to_string(),
+                primary: true,
+                characteristics: vec![GattCharacteristic {
+                    uuid: "00002a56-0000-1000-8000-00805f9b34fb".to_string(),
+                    properties: vec!["read", "notify"],
+                    value: Some("6f6e".to_string()),
+                    subscribers: 1,
+                    descriptors: vec![GattDescriptor {
+                        uuid: "00002902-0000-1000-8000-00805f9b34fb".to_string(),
+                        value: None,
+                    }],
+                }],
+            }],
+        };
+        assert_eq!(
+            line(&gatt),
+            concat!(
+                r#"{"type":"gatt","services":[{"uuid":"0000180f-0000-1000-8000-00805f9b34fb","primary":true,"#,
+                r#""characteristics":[{"uuid":"00002a56-0000-1000-8000-00805f9b34fb","#,
+                r#""properties":["read","notify"],"value":"6f6e","subscribers":1,"#,
+                r#""descriptors":[{"uuid":"00002902-0000-1000-8000-00805f9b34fb","value":null}]}]}]}"#
+            )
+        );
+    }
+
+    #[test]
+    fn history_line() {
+        let history = Message::history(&[Transition {
+            at: UNIX_EPOCH + Duration::from_secs(1_700_000_000),
+            switch: "lamp".to_string(),
+            old: SwitchState::Off,
+            new: SwitchState::On,
+            source: Source::BleWrite,
+        }]);
+        assert_eq!(
+            line(&history),
+            concat!(
+                r#"{"type":"history","transitions":[{"at":1700000000,"switch":"lamp","#,
+                r#""old":"off","new":"on","source":"BLE write"}]}"#
+            )
+        );
+    }
+
+    #[test]
+    fn stats_line() {
+        let mut stats = StatsSnapshot {
+            reads: 1,
+            writes_accepted: 2,
+            writes_rejected: 3,
+            notifications: 4,
+            subscription_changes: 5,
+            power_changes: 6,
+            events_emitted: 7,
+            dropped_events: 0,
+            ..StatsSnapshot::default()
+        };
+        stats.unhandled.insert("StateUpdate".to_string(), 1);
+        assert_eq!(
+            line(&Message::Stats(stats)),
+            concat!(
+                r#"{"type":"stats","reads":1,"writes_accepted":2,"writes_rejected":3,"#,
+                r#""notifications":4,"subscription_changes":5,"power_changes":6,"#,
+                r#""unhandled":{"StateUpdate":1},"events_emitted":7,"dropped_events":0,"last_drop_at":null}"#
+            )
+        );
+    }
+
+    #[test]
+    fn help_lists_every_command() {
+        let json = serde_json::to_value(Message::help()).unwrap();
+        assert_eq!(json["type"], "help");
+        let commands = json["commands"].as_array().unwrap();
+        assert_eq!(commands.len(), COMMANDS.len());
+        for (entry, info) in commands.iter().zip(COMMANDS) {
+            assert_eq!(entry["name"], info.name);
+            assert_eq!(entry["usage"], info.usage);
+            assert_eq!(entry["summary"], info.summary);
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use tokio::sync::{mpsc, oneshot};
+
+use crate::central::CentralId;
+use crate::error::Error;
+
+/// A central asking to pair. Answer it with `accept` or `reject`; dropping
+/// it rejects the pairing.
+#[derive(Debug)]
+pub struct PairingRequest {
+    pub central: CentralId,
+    /// Passkey to show the user and compare, or `None` for "just works"
+    /// pairing that only needs a yes or no.
+    pub passkey: Option<u32>,
+    responder: oneshot::Sender<bool>,
+}
+
+impl PairingRequest {
+    pub fn accept(self) {
+        let _ = self.responder.send(true);
+    }
+
+    pub fn reject(self) {
+        let _ = self.responder.send(false);
+    }
+}
+
+/// Keeps the pairing agent registered for as long as it is alive.
+pub struct PairingAgent {
+    #[cfg(target_os = "linux")]
+    _session: bluer::Session,
+    #[cfg(target_os = "linux")]
+    _handle: bluer::agent::AgentHandle,
+}
+
+/// Registers a BlueZ agent that forwards every pairing confirmation to
+/// `requests` and waits for the answer.
+///
+/// ble-peripheral-rust has no pairing hooks, so this talks to BlueZ directly
+/// and is only available on Linux. Pairing outcomes are not reported.
+#[cfg(target_os = "linux")]
+pub async fn register_agent(requests: mpsc::Sender<PairingRequest>) -> Result<PairingAgent, Error> {
+    use bluer::agent::{Agent, ReqError, RequestAuthorization, RequestConfirmation};
+
+    let backend = |err: bluer::Error| Error::Backend(err.to_string());
+    let session = bluer::Session::new().await.map_err(backend)?;
+
+    let confirmations = requests.clone();
+    let agent = Agent {
+        request_default: true,
+        request_confirmation: Some(Box::new(move |request: RequestConfirmation| {
+            let requests = confirmations.clone();
+            Box::pin(async move {
+                ask(&requests, request.device.to_string(), Some(request.passkey))
+                    .await
+                    .then_some(())
+                    .ok_or(ReqError::Rejected)
+            })
+        })),
+        request_authorization: Some(Box::new(move |request: RequestAuthorization| {
+            let requests = requests.clone();
+            Box::pin(async move {
+                ask(&requests, request.device.to_string(), None)
+                    .await
+                    .then_some(())
+                    .ok_or(ReqError::Rejected)
+            })
+        })),
+        ..Default::default()
+    };
+    let handle = session.register_agent(agent).await.map_err(backend)?;
+    Ok(PairingAgent {
+        _session: session,
+        _handle: handle,
+    })
+}
+
+/// CoreBluetooth and WinRT handle pairing prompts themselves.
+#[cfg(not(target_os = "linux"))]
+pub async fn register_agent(
+    _requests: mpsc::Sender<PairingRequest>,
+) -> Result<PairingAgent, Error> {
+    Err(Error::UnsupportedOperation(
+        "pairing requests outside of BlueZ",
+    ))
+}
+
+#[cfg(target_os = "linux")]
+async fn ask(
+    requests: &mpsc::Sender<PairingRequest>,
+    central: String,
+    passkey: Option<u32>,
+) -> bool {
+    let (responder, answer) = oneshot::channel();
+    let request = PairingRequest {
+        central: CentralId(central),
+        passkey,
+        responder,
+    };
+    if requests.send(request).await.is_err() {
+        return false;
+    }
+    answer.await.unwrap_or(false)
+}
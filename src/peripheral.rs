@@ -0,0 +1,247 @@
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+
+use uuid::Uuid;
+
+use ble_peripheral_rust::{gatt::service::Service, Peripheral, PeripheralImpl};
+
+use crate::advertising::MAX_ADVERTISEMENT_BYTES;
+use crate::error::Error;
+#[cfg(any(test, feature = "mock"))]
+use crate::mock::MockPeripheral;
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// The platform stack behind a `PeripheralApi`, for branching on what it
+/// supports at runtime.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Backend {
+    CoreBluetooth,
+    BlueZ,
+    WinRT,
+    /// `MockPeripheral`, with the `mock` feature or in tests.
+    #[cfg(any(test, feature = "mock"))]
+    Mock,
+}
+
+impl Backend {
+    /// What `Peripheral` drives on the platform this was built for.
+    pub const NATIVE: Backend = if cfg!(target_os = "linux") {
+        Backend::BlueZ
+    } else if cfg!(target_os = "windows") {
+        Backend::WinRT
+    } else {
+        Backend::CoreBluetooth
+    };
+}
+
+impl Backend {
+    /// What the crate supports on this backend.
+    pub fn capabilities(self) -> Capabilities {
+        let bluez = Capabilities {
+            supports_indicate: true,
+            supports_tx_power_control: false,
+            max_advertisement_bytes: MAX_ADVERTISEMENT_BYTES,
+            supports_peripheral_disconnect: true,
+            supports_rssi: false,
+            supports_broadcaster: true,
+            supports_multiple_advertisements: true,
+            reports_mtu: false,
+        };
+        match self {
+            Backend::BlueZ => bluez,
+            // Disconnecting, broadcasting and extra advertising sets go to
+            // BlueZ directly, which CoreBluetooth and the backend's WinRT
+            // publisher do not allow.
+            Backend::CoreBluetooth | Backend::WinRT => Capabilities {
+                supports_peripheral_disconnect: false,
+                supports_broadcaster: false,
+                supports_multiple_advertisements: false,
+                ..bluez
+            },
+            // The mock stands in for the backend only, so what goes around
+            // it is still up to the platform.
+            #[cfg(any(test, feature = "mock"))]
+            Backend::Mock => Backend::NATIVE.capabilities(),
+        }
+    }
+}
+
+/// What a backend can do, so apps can adapt up front instead of handling
+/// `Error::UnsupportedOperation` at call time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities {
+    /// `Device::indicate_characteristic`.
+    pub supports_indicate: bool,
+    /// `AdvertisingParameters::tx_power_level`.
+    pub supports_tx_power_control: bool,
+    /// Largest payload of one advertising packet.
+    pub max_advertisement_bytes: usize,
+    /// `Device::disconnect`.
+    pub supports_peripheral_disconnect: bool,
+    /// `Device::read_rssi`.
+    pub supports_rssi: bool,
+    /// Non-connectable advertising with manufacturer or service data, the
+    /// appearance and AD flags.
+    pub supports_broadcaster: bool,
+    /// More than one `Device::add_advertisement` at a time.
+    pub supports_multiple_advertisements: bool,
+    /// MTU exchanges reach `Device::current_mtu`. No backend reports them
+    /// yet, so this is false everywhere.
+    pub reports_mtu: bool,
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Backend::CoreBluetooth => "CoreBluetooth",
+            Backend::BlueZ => "BlueZ",
+            Backend::WinRT => "WinRT",
+            #[cfg(any(test, feature = "mock"))]
+            Backend::Mock => "mock",
+        };
+        f.write_str(name)
+    }
+}
+
+/// The backend operations the app relies on, object safe so code can hold
+/// a real or mock backend as `Box<dyn PeripheralApi>` or
+/// `Arc<Mutex<dyn PeripheralApi>>`.
+///
+/// `Peripheral` is the platform implementation; with the `mock` feature or
+/// in tests, `MockPeripheral` implements it too. `Device` drives either.
+pub trait PeripheralApi: Send {
+    fn backend(&self) -> Backend;
+
+    fn capabilities(&self) -> Capabilities {
+        self.backend().capabilities()
+    }
+
+    fn is_powered(&mut self) -> BoxFuture<'_, Result<bool, Error>>;
+
+    fn is_advertising(&mut self) -> BoxFuture<'_, Result<bool, Error>>;
+
+    fn start_advertising<'a>(
+        &'a mut self,
+        name: &'a str,
+        uuids: &'a [Uuid],
+    ) -> BoxFuture<'a, Result<(), Error>>;
+
+    fn stop_advertising(&mut self) -> BoxFuture<'_, Result<(), Error>>;
+
+    fn add_service<'a>(&'a mut self, service: &'a Service) -> BoxFuture<'a, Result<(), Error>>;
+
+    /// Sets the value and notifies subscribers.
+    fn update_characteristic(
+        &mut self,
+        uuid: Uuid,
+        value: Vec<u8>,
+    ) -> BoxFuture<'_, Result<(), Error>>;
+}
+
+/// Implements `PeripheralApi` by forwarding to the type's `PeripheralImpl`.
+macro_rules! forward_to_peripheral_impl {
+    ($peripheral:ty, $backend:expr) => {
+        impl PeripheralApi for $peripheral {
+            fn backend(&self) -> Backend {
+                $backend
+            }
+
+            fn is_powered(&mut self) -> BoxFuture<'_, Result<bool, Error>> {
+                Box::pin(async move { Ok(PeripheralImpl::is_powered(self).await?) })
+            }
+
+            fn is_advertising(&mut self) -> BoxFuture<'_, Result<bool, Error>> {
+                Box::pin(async move { Ok(PeripheralImpl::is_advertising(self).await?) })
+            }
+
+            fn start_advertising<'a>(
+                &'a mut self,
+                name: &'a str,
+                uuids: &'a [Uuid],
+            ) -> BoxFuture<'a, Result<(), Error>> {
+                Box::pin(
+                    async move { Ok(PeripheralImpl::start_advertising(self, name, uuids).await?) },
+                )
+            }
+
+            fn stop_advertising(&mut self) -> BoxFuture<'_, Result<(), Error>> {
+                Box::pin(async move { Ok(PeripheralImpl::stop_advertising(self).await?) })
+            }
+
+            fn add_service<'a>(
+                &'a mut self,
+                service: &'a Service,
+            ) -> BoxFuture<'a, Result<(), Error>> {
+                Box::pin(async move { Ok(PeripheralImpl::add_service(self, service).await?) })
+            }
+
+            fn update_characteristic(
+                &mut self,
+                uuid: Uuid,
+                value: Vec<u8>,
+            ) -> BoxFuture<'_, Result<(), Error>> {
+                Box::pin(async move {
+                    Ok(PeripheralImpl::update_characteristic(self, uuid, value).await?)
+                })
+            }
+        }
+    };
+}
+
+forward_to_peripheral_impl!(Peripheral, Backend::NATIVE);
+#[cfg(any(test, feature = "mock"))]
+forward_to_peripheral_impl!(MockPeripheral, Backend::Mock);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::device::Device;
+
+    /// What a backend that supports nothing would report.
+    const NOTHING: Capabilities = Capabilities {
+        supports_indicate: false,
+        supports_tx_power_control: false,
+        max_advertisement_bytes: 0,
+        supports_peripheral_disconnect: false,
+        supports_rssi: false,
+        supports_broadcaster: false,
+        supports_multiple_advertisements: false,
+        reports_mtu: false,
+    };
+
+    #[test]
+    fn every_backend_reports_what_it_supports() {
+        for backend in [
+            Backend::CoreBluetooth,
+            Backend::BlueZ,
+            Backend::WinRT,
+            Backend::Mock,
+        ] {
+            let capabilities = backend.capabilities();
+            assert_ne!(capabilities, NOTHING, "{}", backend);
+            assert_eq!(
+                capabilities.max_advertisement_bytes, MAX_ADVERTISEMENT_BYTES,
+                "{}",
+                backend
+            );
+            assert!(capabilities.supports_indicate, "{}", backend);
+        }
+        assert!(Backend::BlueZ.capabilities().supports_peripheral_disconnect);
+        assert!(!Backend::CoreBluetooth.capabilities().supports_broadcaster);
+        assert!(
+            !Backend::WinRT
+                .capabilities()
+                .supports_multiple_advertisements
+        );
+    }
+
+    #[test]
+    fn mock_reports_the_native_capabilities() {
+        let (mock, _events) = MockPeripheral::channel(1);
+        let device = Device::mock(mock);
+        assert_eq!(device.capabilities(), Backend::NATIVE.capabilities());
+        assert_eq!(device.capabilities(), Backend::Mock.capabilities());
+    }
+}
@@ -0,0 +1,90 @@
+use std::fs::OpenOptions;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use crate::profile::switch::SwitchState;
+use crate::state::SharedState;
+
+/// How long the state has to stay put before it is written out, so rapid
+/// toggles end up as a single write.
+pub const SAVE_DEBOUNCE: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Serialize, Deserialize)]
+struct PersistedState {
+    power: bool,
+}
+
+/// Reads the state saved by a previous run.
+///
+/// A missing or unreadable file is logged and yields `None`, so the caller
+/// can fall back to the configured default.
+pub fn load(path: &Path) -> Option<SwitchState> {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(err) => {
+            log::warn!(
+                "No saved state in {} ({}), using the default",
+                path.display(),
+                err
+            );
+            return None;
+        }
+    };
+    match serde_json::from_str::<PersistedState>(&contents) {
+        Ok(saved) => Some(SwitchState::from_bool(saved.power)),
+        Err(err) => {
+            log::warn!("Ignoring corrupt state file {}: {}", path.display(), err);
+            None
+        }
+    }
+}
+
+/// Writes `contents` to `path` through a temporary file next to it, so a
+/// crash mid-write never leaves a truncated file behind.
+///
+/// Unless `overwrite` is set, `path` is claimed with `create_new` before
+/// anything is written, so an existing file is never replaced, even one
+/// created while this runs; that fails with `ErrorKind::AlreadyExists`.
+pub fn write_atomically(path: &Path, contents: &[u8], overwrite: bool) -> io::Result<()> {
+    if !overwrite {
+        OpenOptions::new().write(true).create_new(true).open(path)?;
+    }
+    let mut temp_name = path.file_name().unwrap_or_default().to_os_string();
+    temp_name.push(".tmp");
+    let temp = path.with_file_name(temp_name);
+    let result = std::fs::write(&temp, contents).and_then(|()| std::fs::rename(&temp, path));
+    if result.is_err() {
+        let _ = std::fs::remove_file(&temp);
+        if !overwrite {
+            // Only the empty file claimed above is there.
+            let _ = std::fs::remove_file(path);
+        }
+    }
+    result
+}
+
+/// Writes `state` to `path` with `write_atomically`.
+pub fn save(path: &Path, state: SwitchState) -> io::Result<()> {
+    let contents = serde_json::to_string(&PersistedState {
+        power: state.is_on(),
+    })?;
+    write_atomically(path, contents.as_bytes(), true)
+}
+
+/// Saves the state to `path` whenever it changes, debounced by `SAVE_DEBOUNCE`.
+pub fn spawn_saver(path: PathBuf, state: &SharedState) {
+    let mut changes = state.read().unwrap().subscribe();
+    tokio::spawn(async move {
+        while changes.changed().await.is_ok() {
+            tokio::time::sleep(SAVE_DEBOUNCE).await;
+            let current = *changes.borrow_and_update();
+            match save(&path, current) {
+                Ok(()) => log::debug!("Saved state to {}", path.display()),
+                Err(err) => log::error!("Error saving state to {}: {}", path.display(), err),
+            }
+        }
+    });
+}
@@ -0,0 +1,40 @@
+use ble_peripheral_rust::{gatt::characteristic::Characteristic, uuid::ShortUuid};
+use uuid::Uuid;
+
+/// Short UUID of the Characteristic Presentation Format descriptor.
+pub const CPF_UUID: u16 = 0x2904;
+
+/// Size in bytes of a value in a CPF format, from the Assigned Numbers
+/// format table. `None` for strings, `struct` and unknown formats, whose
+/// length is not fixed.
+pub fn format_len(format: u8) -> Option<usize> {
+    let len = match format {
+        // boolean, 2bit, nibble, uint8, sint8
+        0x01..=0x04 | 0x0C => 1,
+        // uint12, uint16, sint12, sint16, SFLOAT
+        0x05 | 0x06 | 0x0D | 0x0E | 0x16 => 2,
+        // uint24, sint24
+        0x07 | 0x0F => 3,
+        // uint32, sint32, float32, FLOAT, duint16
+        0x08 | 0x10 | 0x14 | 0x17 | 0x18 => 4,
+        // uint48, sint48
+        0x09 | 0x11 => 6,
+        // uint64, sint64, float64
+        0x0A | 0x12 | 0x15 => 8,
+        // uint128, sint128
+        0x0B | 0x13 => 16,
+        _ => return None,
+    };
+    Some(len)
+}
+
+/// The value length `characteristic`'s CPF descriptor declares, if it has
+/// one with a fixed-size format.
+pub fn declared_len(characteristic: &Characteristic) -> Option<usize> {
+    let cpf = Uuid::from_short(CPF_UUID);
+    let descriptor = characteristic
+        .descriptors
+        .iter()
+        .find(|descriptor| descriptor.uuid == cpf)?;
+    format_len(*descriptor.value.as_ref()?.first()?)
+}
@@ -0,0 +1,128 @@
+use std::sync::{Arc, Mutex as StdMutex};
+
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use ble_peripheral_rust::gatt::{
+    peripheral_event::{
+        PeripheralRequest, ReadRequestResponse, RequestResponse, WriteRequestResponse,
+    },
+    service::Service,
+};
+
+use crate::builder::{BuildError, CharacteristicBuilder, ServiceBuilder};
+use crate::device::Device;
+use crate::events::LOG_TARGET;
+use crate::handler::CharacteristicHandler;
+use crate::peripheral::BoxFuture;
+use crate::responses::{Failure, ReadRequestResponseExt};
+use crate::state_machine::{StateMachine, TransitionError};
+
+pub const SERVICE_UUID: Uuid = Uuid::from_u128(0x3C4A0001_7B1E_4F4C_9E3B_6D2A1F0C5E90);
+/// Reads and notifies the current state name; writes request a transition.
+pub const CONTROL_UUID: Uuid = Uuid::from_u128(0x3C4A0002_7B1E_4F4C_9E3B_6D2A1F0C5E90);
+
+/// The machine the control characteristic drives, shared by its handler,
+/// `reload` and `save-config`.
+pub type SharedMachine = Arc<StdMutex<StateMachine>>;
+
+/// The control service, starting out in `machine`'s current state.
+pub fn service(machine: &StateMachine) -> Result<Service, BuildError> {
+    let current = machine.current().to_string();
+    ServiceBuilder::new(SERVICE_UUID)
+        .characteristic(
+            CharacteristicBuilder::new(CONTROL_UUID)
+                .readable()
+                .writable()
+                .notify()
+                .value(current.into_bytes())
+                .build()?,
+        )
+        .build()
+}
+
+pub fn on_read(
+    request: &PeripheralRequest,
+    offset: u64,
+    machine: &StdMutex<StateMachine>,
+) -> ReadRequestResponse {
+    let current = machine.lock().unwrap().current().to_string();
+    log::info!(
+        target: LOG_TARGET,
+        "ReadRequest: {:?} Offset: {} -> Responding: {}",
+        request,
+        offset,
+        current
+    );
+    ReadRequestResponse::from_value(current.into_bytes(), offset)
+}
+
+/// Applies a transition request: a state name or a one-byte opcode giving
+/// the state's position in the table. Illegal requests are refused and
+/// nothing is notified.
+pub async fn on_write(
+    request: &PeripheralRequest,
+    value: Vec<u8>,
+    machine: &StdMutex<StateMachine>,
+    device: &Arc<Mutex<Device>>,
+) -> WriteRequestResponse {
+    if request.characteristic != CONTROL_UUID {
+        return Failure::ReadOnly.write_response();
+    }
+    let result = machine.lock().unwrap().request(&value).map(str::to_string);
+    match result {
+        Ok(state) => {
+            log::info!(target: LOG_TARGET, "WriteRequest: state -> {}", state);
+            if let Err(e) = device
+                .lock()
+                .await
+                .update_characteristic(CONTROL_UUID, state.into_bytes())
+                .await
+            {
+                log::error!("Error updating state characteristic: {:?}", e);
+            }
+            WriteRequestResponse {
+                response: RequestResponse::Success,
+            }
+        }
+        Err(err) => {
+            log::warn!(target: LOG_TARGET, "WriteRequest: Rejected: {}", err);
+            match err {
+                TransitionError::UnknownState(_) => Failure::Unparseable,
+                TransitionError::NotAllowed { .. } => Failure::Refused,
+            }
+            .write_response()
+        }
+    }
+}
+
+/// Serves the control characteristic.
+pub struct MachineHandler {
+    machine: SharedMachine,
+    device: Arc<Mutex<Device>>,
+}
+
+impl MachineHandler {
+    pub fn new(machine: SharedMachine, device: Arc<Mutex<Device>>) -> Self {
+        Self { machine, device }
+    }
+}
+
+impl CharacteristicHandler for MachineHandler {
+    fn on_read<'a>(
+        &'a self,
+        request: &'a PeripheralRequest,
+        offset: u64,
+    ) -> BoxFuture<'a, ReadRequestResponse> {
+        Box::pin(async move { on_read(request, offset, &self.machine) })
+    }
+
+    fn on_write<'a>(
+        &'a self,
+        request: &'a PeripheralRequest,
+        _offset: u64,
+        value: Vec<u8>,
+    ) -> BoxFuture<'a, WriteRequestResponse> {
+        Box::pin(on_write(request, value, &self.machine, &self.device))
+    }
+}
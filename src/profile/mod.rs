@@ -0,0 +1,108 @@
+use std::sync::Arc;
+
+use clap::ValueEnum;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use ble_peripheral_rust::{gatt::service::Service, uuid::ShortUuid};
+
+use crate::builder::BuildError;
+use crate::device::Device;
+use crate::handler::{CharacteristicHandler, HandlerRegistry, StoredValueHandler};
+use crate::state_machine::StateMachine;
+use crate::switches::SwitchBank;
+
+pub mod machine;
+pub mod sensor;
+pub mod switch;
+pub mod uart;
+
+/// Built-in GATT layouts, each with its own request handling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum Profile {
+    /// One on/off characteristic.
+    Switch,
+    /// Nordic UART service with RX and TX characteristics.
+    Uart,
+    /// A read/notify temperature plus a writable measurement interval.
+    Sensor,
+    /// A named-state machine driven through a control characteristic.
+    Machine,
+}
+
+impl Profile {
+    /// Services for this profile. `--service`, the switch channels and the
+    /// initial state only apply to `switch`, and `machine` only to
+    /// `machine`.
+    pub fn services(
+        self,
+        service_uuid: Uuid,
+        channels: &[(String, Uuid)],
+        initial: switch::SwitchState,
+        machine: &StateMachine,
+    ) -> Result<Vec<Service>, BuildError> {
+        let service = match self {
+            Profile::Switch => switch::service(service_uuid, channels, initial)?,
+            Profile::Uart => uart::service()?,
+            Profile::Sensor => sensor::service()?,
+            Profile::Machine => machine::service(machine)?,
+        };
+        Ok(vec![service])
+    }
+
+    /// Characteristic that console input is written to.
+    pub fn console_characteristic(self, char_uuid: Uuid) -> Uuid {
+        match self {
+            Profile::Switch => char_uuid,
+            Profile::Uart => uart::TX_UUID,
+            Profile::Sensor => Uuid::from_short(sensor::VALUE_UUID),
+            Profile::Machine => machine::CONTROL_UUID,
+        }
+    }
+
+    /// Starts any background work the profile needs once advertising is up.
+    pub fn spawn_tasks(self, device: Arc<Mutex<Device>>) {
+        if self == Profile::Sensor {
+            sensor::spawn_notifier(device);
+        }
+    }
+
+    /// Routes this profile's characteristics to its handlers. Everything
+    /// else, e.g. characteristics from a config file or `char add`, is
+    /// served from its stored value.
+    pub fn handlers(
+        self,
+        device: Arc<Mutex<Device>>,
+        switches: Arc<SwitchBank>,
+        machine: machine::SharedMachine,
+    ) -> HandlerRegistry {
+        let mut registry = HandlerRegistry::new(Arc::new(StoredValueHandler::new(device.clone())));
+        match self {
+            Profile::Switch => {
+                let handler = switch::SwitchHandler::new(switches, device);
+                let uuids = handler.characteristics();
+                let handler: Arc<dyn CharacteristicHandler> = Arc::new(handler);
+                for uuid in uuids {
+                    registry.register(uuid, handler.clone());
+                }
+            }
+            Profile::Uart => {
+                let handler: Arc<dyn CharacteristicHandler> = Arc::new(uart::UartHandler);
+                registry.register(uart::RX_UUID, handler.clone());
+                registry.register(uart::TX_UUID, handler);
+            }
+            Profile::Sensor => {
+                let handler: Arc<dyn CharacteristicHandler> = Arc::new(sensor::SensorHandler);
+                registry.register(Uuid::from_short(sensor::VALUE_UUID), handler.clone());
+                registry.register(Uuid::from_short(sensor::CONFIG_UUID), handler);
+            }
+            Profile::Machine => {
+                registry.register(
+                    machine::CONTROL_UUID,
+                    Arc::new(machine::MachineHandler::new(machine, device)),
+                );
+            }
+        }
+        registry
+    }
+}
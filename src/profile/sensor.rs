@@ -0,0 +1,134 @@
+use std::sync::{
+    atomic::{AtomicU16, AtomicU32, Ordering},
+    Arc,
+};
+use std::time::Duration;
+
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use ble_peripheral_rust::{
+    gatt::{
+        peripheral_event::{
+            PeripheralRequest, ReadRequestResponse, RequestResponse, WriteRequestResponse,
+        },
+        service::Service,
+    },
+    uuid::ShortUuid,
+};
+
+use crate::builder::{BuildError, CharacteristicBuilder, ServiceBuilder};
+use crate::device::Device;
+use crate::events::LOG_TARGET;
+use crate::handler::CharacteristicHandler;
+use crate::peripheral::BoxFuture;
+use crate::responses::{Failure, ReadRequestResponseExt};
+
+/// Environmental Sensing service.
+pub const SERVICE_UUID: u16 = 0x181A;
+/// Temperature in 0.01 °C, sint16 little endian.
+pub const VALUE_UUID: u16 = 0x2A6E;
+/// Measurement interval in seconds, uint16 little endian.
+pub const CONFIG_UUID: u16 = 0x2A21;
+
+static INTERVAL_SECS: AtomicU16 = AtomicU16::new(1);
+static TICKS: AtomicU32 = AtomicU32::new(0);
+
+pub fn service() -> Result<Service, BuildError> {
+    ServiceBuilder::new(Uuid::from_short(SERVICE_UUID))
+        .characteristic(
+            CharacteristicBuilder::new(Uuid::from_short(VALUE_UUID))
+                .readable()
+                .notify()
+                .build()?,
+        )
+        .characteristic(
+            CharacteristicBuilder::new(Uuid::from_short(CONFIG_UUID))
+                .readable()
+                .writable()
+                .build()?,
+        )
+        .build()
+}
+
+/// Simulated reading that drifts between 20.00 °C and 24.99 °C.
+fn reading() -> Vec<u8> {
+    let ticks = TICKS.load(Ordering::SeqCst);
+    let centi_degrees = 2000 + (ticks % 500) as i16;
+    centi_degrees.to_le_bytes().to_vec()
+}
+
+/// Pushes a new reading to subscribers every measurement interval.
+pub fn spawn_notifier(device: Arc<Mutex<Device>>) {
+    tokio::spawn(async move {
+        loop {
+            let interval = INTERVAL_SECS.load(Ordering::SeqCst).max(1);
+            tokio::time::sleep(Duration::from_secs(interval.into())).await;
+            TICKS.fetch_add(1, Ordering::SeqCst);
+            if let Err(e) = device
+                .lock()
+                .await
+                .update_characteristic(Uuid::from_short(VALUE_UUID), reading())
+                .await
+            {
+                log::error!("Error updating sensor value: {:?}", e);
+            }
+        }
+    });
+}
+
+pub fn on_read(request: &PeripheralRequest, offset: u64) -> ReadRequestResponse {
+    log::info!(target: LOG_TARGET, "ReadRequest: {:?} Offset: {}", request, offset);
+    let value = if request.characteristic == Uuid::from_short(CONFIG_UUID) {
+        INTERVAL_SECS.load(Ordering::SeqCst).to_le_bytes().to_vec()
+    } else {
+        reading()
+    };
+    ReadRequestResponse::from_value(value, offset)
+}
+
+pub fn on_write(request: &PeripheralRequest, value: Vec<u8>) -> WriteRequestResponse {
+    if request.characteristic != Uuid::from_short(CONFIG_UUID) {
+        log::warn!(target: LOG_TARGET, "WriteRequest: {:?} is read-only", request);
+        return Failure::ReadOnly.write_response();
+    }
+
+    let interval = match value.as_slice() {
+        [low, high] => u16::from_le_bytes([*low, *high]),
+        _ => {
+            log::warn!(
+                target: LOG_TARGET,
+                "WriteRequest: interval must be 2 bytes, got {}",
+                value.len()
+            );
+            return Failure::WrongLength.write_response();
+        }
+    };
+    INTERVAL_SECS.store(interval.max(1), Ordering::SeqCst);
+    log::info!("Sensor interval set to {}s", interval.max(1));
+    WriteRequestResponse {
+        response: RequestResponse::Success,
+    }
+}
+
+/// Serves the temperature and the measurement interval.
+pub struct SensorHandler;
+
+impl CharacteristicHandler for SensorHandler {
+    fn on_read<'a>(
+        &'a self,
+        request: &'a PeripheralRequest,
+        offset: u64,
+    ) -> BoxFuture<'a, ReadRequestResponse> {
+        Box::pin(async move { on_read(request, offset) })
+    }
+
+    fn on_write<'a>(
+        &'a self,
+        request: &'a PeripheralRequest,
+        _offset: u64,
+        value: Vec<u8>,
+    ) -> BoxFuture<'a, WriteRequestResponse> {
+        Box::pin(async move { on_write(request, value) })
+    }
+}
@@ -0,0 +1,736 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex as StdMutex};
+
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use ble_peripheral_rust::{
+    gatt::{
+        characteristic::Characteristic,
+        descriptor::Descriptor,
+        peripheral_event::{
+            PeripheralRequest, ReadRequestResponse, RequestResponse, WriteRequestResponse,
+        },
+        service::Service,
+    },
+    uuid::ShortUuid,
+};
+
+use crate::aliases::Aliases;
+use crate::builder::{BuildError, CharacteristicBuilder, ServiceBuilder};
+use crate::central::CentralId;
+use crate::device::Device;
+use crate::events::LOG_TARGET;
+use crate::handler::CharacteristicHandler;
+use crate::peripheral::BoxFuture;
+use crate::responses::{Failure, ReadRequestResponseExt};
+use crate::state::{Source, WriteOrigin, DEFAULT_BRIGHTNESS};
+use crate::switches::{Switch, SwitchBank};
+
+/// Characteristic holding the 0-255 brightness of the primary switch.
+pub const BRIGHTNESS_UUID: u16 = 0x2B05;
+
+/// Read-only characteristic returning `Status` as compact JSON.
+pub const STATUS_UUID: &str = "120A";
+
+/// On/off value as read and written over the air.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum SwitchState {
+    On,
+    Off,
+}
+
+impl SwitchState {
+    pub fn from_bool(on: bool) -> Self {
+        if on {
+            SwitchState::On
+        } else {
+            SwitchState::Off
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_lowercase().as_str() {
+            "on" => Some(SwitchState::On),
+            "off" => Some(SwitchState::Off),
+            _ => None,
+        }
+    }
+
+    pub fn is_on(self) -> bool {
+        self == SwitchState::On
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SwitchState::On => "on",
+            SwitchState::Off => "off",
+        }
+    }
+}
+
+/// Everything about the primary switch in one read. Fields are only ever
+/// added, so clients can rely on the ones they know.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Status {
+    pub power: bool,
+    pub brightness: u8,
+    pub uptime_secs: u64,
+    pub firmware: String,
+    /// What made the last change: `initial`, `stdin`, `BLE write`, `timer`
+    /// or `API`.
+    pub last_source: String,
+    pub secs_since_change: u64,
+    /// Central whose write made the last change, if one did.
+    pub last_central: Option<String>,
+    /// Characteristic that write went to.
+    pub last_characteristic: Option<String>,
+}
+
+impl Status {
+    pub fn of(switches: &SwitchBank) -> Self {
+        let state = switches.primary().state.read().unwrap();
+        Self {
+            power: state.power(),
+            brightness: state.brightness(),
+            uptime_secs: switches.uptime().as_secs(),
+            firmware: env!("CARGO_PKG_VERSION").to_string(),
+            last_source: state.last_source().to_string(),
+            secs_since_change: state.last_changed().elapsed().as_secs(),
+            last_central: state.last_origin().map(|origin| origin.central.to_string()),
+            last_characteristic: state
+                .last_origin()
+                .map(|origin| origin.characteristic.to_string()),
+        }
+    }
+}
+
+/// The status document last read by each central, so the chunks of one
+/// read all come from the same document even when the uptime or the state
+/// changes in between.
+#[derive(Debug, Default)]
+pub struct StatusReads {
+    documents: StdMutex<HashMap<(CentralId, Uuid), Vec<u8>>>,
+}
+
+impl StatusReads {
+    /// A fresh document for a read at offset 0, kept for the central's
+    /// later offsets; those get the kept one, or a fresh one if there is
+    /// none.
+    fn document(
+        &self,
+        request: &PeripheralRequest,
+        offset: u64,
+        switches: &SwitchBank,
+    ) -> serde_json::Result<Vec<u8>> {
+        let key = (CentralId::from_request(request), request.characteristic);
+        let mut documents = self.documents.lock().unwrap();
+        if offset > 0 {
+            if let Some(json) = documents.get(&key) {
+                return Ok(json.clone());
+            }
+        }
+        let json = serde_json::to_vec(&Status::of(switches))?;
+        documents.insert(key, json.clone());
+        Ok(json)
+    }
+}
+
+/// Maps `on`/`off` or one of `aliases` to a state, logging alias matches.
+pub fn resolve(input: &str, aliases: &Aliases) -> Option<SwitchState> {
+    if let Some(state) = SwitchState::parse(input) {
+        return Some(state);
+    }
+    let state = aliases.resolve(input)?;
+    log::info!("Alias '{}' resolved to: {}", input.trim(), state.as_str());
+    Some(state)
+}
+
+/// One on/off characteristic per switch channel, each starting out as `initial`.
+pub fn service(
+    service_uuid: Uuid,
+    channels: &[(String, Uuid)],
+    initial: SwitchState,
+) -> Result<Service, BuildError> {
+    let mut builder = ServiceBuilder::new(service_uuid);
+    for (_, char_uuid) in channels {
+        builder = builder.characteristic(
+            CharacteristicBuilder::new(*char_uuid)
+                .readable()
+                .writable()
+                .notify()
+                .value(initial.as_str().into())
+                .with_descriptor(Descriptor {
+                    uuid: Uuid::from_short(0x2A13_u16),
+                    value: Some(vec![0, 1]),
+                    ..Default::default()
+                })
+                .build()?,
+        );
+    }
+    builder
+        .characteristic(
+            CharacteristicBuilder::new(Uuid::from_short(BRIGHTNESS_UUID))
+                .readable()
+                .writable()
+                .notify()
+                .value(vec![DEFAULT_BRIGHTNESS])
+                .build()?,
+        )
+        .characteristic(
+            CharacteristicBuilder::new(Uuid::from_string(STATUS_UUID))
+                .readable()
+                .build()?,
+        )
+        .characteristic(Characteristic {
+            uuid: Uuid::from_string("1209"),
+            ..Default::default()
+        })
+        .build()
+}
+
+/// The switch a request is for. Characteristics that belong to no switch
+/// fall back to the primary one.
+fn target<'a>(request: &PeripheralRequest, switches: &'a SwitchBank) -> &'a Switch {
+    switches
+        .by_characteristic(request.characteristic)
+        .unwrap_or_else(|| switches.primary())
+}
+
+fn origin(request: &PeripheralRequest) -> WriteOrigin {
+    WriteOrigin {
+        central: CentralId::from_request(request),
+        characteristic: request.characteristic,
+    }
+}
+
+/// Sets `switch` to `new_state` and notifies its characteristic and mirrors.
+/// Nothing is notified if the switch was already in that state. Returns
+/// whether it changed.
+pub async fn set(
+    switch: &Switch,
+    new_state: SwitchState,
+    source: Source,
+    device: &Arc<Mutex<Device>>,
+) -> bool {
+    set_from(switch, new_state, source, None, device).await
+}
+
+/// Like `set`, also recording the central and characteristic of the write
+/// that asked for it.
+pub async fn set_from(
+    switch: &Switch,
+    new_state: SwitchState,
+    source: Source,
+    origin: Option<WriteOrigin>,
+    device: &Arc<Mutex<Device>>,
+) -> bool {
+    let changed = switch
+        .state
+        .write()
+        .unwrap()
+        .set_power_from(new_state.is_on(), source, origin);
+    if !changed {
+        return false;
+    }
+    let updates = std::iter::once((switch.characteristic, new_state.as_str().into()))
+        .chain(
+            switch
+                .mirrors
+                .iter()
+                .map(|mirror| (mirror.characteristic, mirror.encoding.encode(new_state))),
+        )
+        .collect();
+    notify(switch, updates, device).await;
+    true
+}
+
+/// Sends each `(characteristic, value)` update for `switch`, through its
+/// debouncer if it has one. A failed update does not stop the others; the
+/// failures are logged together.
+async fn notify(switch: &Switch, updates: Vec<(Uuid, Vec<u8>)>, device: &Arc<Mutex<Device>>) {
+    if let Some(debounce) = &switch.debounce {
+        for (uuid, value) in updates {
+            debounce.submit(uuid, value, device);
+        }
+        return;
+    }
+    let uuids: Vec<Uuid> = updates.iter().map(|(uuid, _)| *uuid).collect();
+    let results = device.lock().await.update_characteristics(updates).await;
+    let errors: Vec<String> = uuids
+        .iter()
+        .zip(results)
+        .filter_map(|(uuid, result)| result.err().map(|e| format!("{}: {}", uuid, e)))
+        .collect();
+    if !errors.is_empty() {
+        log::error!(
+            "Error updating characteristics of switch '{}': {}",
+            switch.name,
+            errors.join("; ")
+        );
+    }
+}
+
+/// Parses a brightness write: up to three ASCII digits, or else one raw byte.
+///
+/// ASCII takes precedence, so the raw bytes `0x30`-`0x39` read as `"0"`-`"9"`.
+pub fn parse_brightness(value: &[u8]) -> Option<u8> {
+    if (1..=3).contains(&value.len()) && value.iter().all(u8::is_ascii_digit) {
+        return std::str::from_utf8(value).ok()?.parse().ok();
+    }
+    match value {
+        [level] => Some(*level),
+        _ => None,
+    }
+}
+
+/// Why `parse_brightness` refused `value`: too few or too many bytes to be
+/// a level either way, or digits out of range.
+fn brightness_failure(value: &[u8]) -> Failure {
+    if value.is_empty() || value.len() > 3 {
+        Failure::WrongLength
+    } else {
+        Failure::Unparseable
+    }
+}
+
+/// Sets the primary switch's brightness and notifies if it changed.
+pub async fn set_brightness(
+    level: u8,
+    source: Source,
+    origin: Option<WriteOrigin>,
+    switches: &SwitchBank,
+    device: &Arc<Mutex<Device>>,
+) {
+    let changed = switches
+        .primary()
+        .state
+        .write()
+        .unwrap()
+        .set_brightness_from(level, source, origin);
+    if !changed {
+        return;
+    }
+    let uuid = Uuid::from_short(BRIGHTNESS_UUID);
+    if let Some(debounce) = switches.notify_debounce() {
+        debounce.submit(uuid, vec![level], device);
+        return;
+    }
+    if let Err(e) = device
+        .lock()
+        .await
+        .update_characteristic(uuid, vec![level])
+        .await
+    {
+        log::error!("Error updating brightness characteristic: {:?}", e);
+    }
+}
+
+pub fn on_read(
+    request: &PeripheralRequest,
+    offset: u64,
+    switches: &SwitchBank,
+) -> ReadRequestResponse {
+    if request.characteristic == Uuid::from_string(STATUS_UUID) {
+        return status_read(request, offset, switches);
+    }
+    if request.characteristic == Uuid::from_short(BRIGHTNESS_UUID) {
+        let level = switches.primary().state.read().unwrap().brightness();
+        log::info!(
+            target: LOG_TARGET,
+            "ReadRequest: {:?} Offset: {} -> Responding: brightness {}",
+            request,
+            offset,
+            level
+        );
+        return ReadRequestResponse::from_value(vec![level], offset);
+    }
+
+    let response_value = target(request, switches)
+        .state
+        .read()
+        .unwrap()
+        .switch_state()
+        .as_str();
+
+    log::info!(
+        target: LOG_TARGET,
+        "ReadRequest: {:?} Offset: {} -> Responding: {}",
+        request,
+        offset,
+        response_value
+    );
+
+    ReadRequestResponse::from_value(response_value.into(), offset)
+}
+
+/// Serves the JSON status from `offset` on, so small-MTU clients can read it
+/// in chunks. The document is encoded at offset 0 and later chunks are cut
+/// from that one.
+fn status_read(
+    request: &PeripheralRequest,
+    offset: u64,
+    switches: &SwitchBank,
+) -> ReadRequestResponse {
+    let json = match switches.status_reads().document(request, offset, switches) {
+        Ok(json) => json,
+        Err(err) => {
+            log::error!("Error encoding status: {}", err);
+            return Failure::Internal.read_response();
+        }
+    };
+    log::info!(
+        target: LOG_TARGET,
+        "ReadRequest: {:?} Offset: {} -> Responding: status ({} bytes)",
+        request,
+        offset,
+        json.len()
+    );
+    ReadRequestResponse::from_value(json, offset)
+}
+
+pub async fn on_write(
+    request: &PeripheralRequest,
+    value: Vec<u8>,
+    device: &Arc<Mutex<Device>>,
+    switches: &SwitchBank,
+) -> WriteRequestResponse {
+    if request.characteristic == Uuid::from_string(STATUS_UUID) {
+        log::warn!(target: LOG_TARGET, "WriteRequest: {:?} is read-only", request);
+        return Failure::ReadOnly.write_response();
+    }
+    if request.characteristic == Uuid::from_short(BRIGHTNESS_UUID) {
+        let Some(level) = parse_brightness(&value) else {
+            log::warn!(
+                target: LOG_TARGET,
+                "WriteRequest: invalid brightness {:?}",
+                value
+            );
+            return brightness_failure(&value).write_response();
+        };
+        set_brightness(
+            level,
+            Source::BleWrite,
+            Some(origin(request)),
+            switches,
+            device,
+        )
+        .await;
+        return WriteRequestResponse {
+            response: RequestResponse::Success,
+        };
+    }
+
+    let switch = target(request, switches);
+    if let Ok(msg) = String::from_utf8(value) {
+        log::info!(
+            target: LOG_TARGET,
+            "WriteRequest: Received message for '{}' -> {}",
+            switch.name,
+            msg
+        );
+
+        let resolved = resolve(&msg, &switches.aliases());
+        match resolved {
+            Some(new_state) => {
+                set_from(
+                    switch,
+                    new_state,
+                    Source::BleWrite,
+                    Some(origin(request)),
+                    device,
+                )
+                .await
+            }
+            None if switches.strict_writes() => {
+                log::warn!(
+                    target: LOG_TARGET,
+                    "WriteRequest: Rejected value -> {:?}",
+                    msg
+                );
+                return Failure::Unparseable.write_response();
+            }
+            None => {
+                log::warn!(
+                    target: LOG_TARGET,
+                    "WriteRequest: Unrecognized value -> {}",
+                    msg
+                );
+                // Echo the value back so subscribers see what was written.
+                notify(
+                    switch,
+                    vec![(switch.characteristic, msg.into_bytes())],
+                    device,
+                )
+                .await;
+            }
+        }
+    } else {
+        log::warn!(target: LOG_TARGET, "WriteRequest: Received non-UTF8 data");
+        return Failure::NotUtf8.write_response();
+    }
+
+    WriteRequestResponse {
+        response: RequestResponse::Success,
+    }
+}
+
+/// Serves the switch channels, the brightness and the JSON status.
+pub struct SwitchHandler {
+    switches: Arc<SwitchBank>,
+    device: Arc<Mutex<Device>>,
+}
+
+impl SwitchHandler {
+    pub fn new(switches: Arc<SwitchBank>, device: Arc<Mutex<Device>>) -> Self {
+        Self { switches, device }
+    }
+
+    /// The characteristics to register the handler for.
+    pub fn characteristics(&self) -> Vec<Uuid> {
+        self.switches
+            .iter()
+            .map(|switch| switch.characteristic)
+            .chain([
+                Uuid::from_short(BRIGHTNESS_UUID),
+                Uuid::from_string(STATUS_UUID),
+            ])
+            .collect()
+    }
+}
+
+impl CharacteristicHandler for SwitchHandler {
+    fn on_read<'a>(
+        &'a self,
+        request: &'a PeripheralRequest,
+        offset: u64,
+    ) -> BoxFuture<'a, ReadRequestResponse> {
+        Box::pin(async move { on_read(request, offset, &self.switches) })
+    }
+
+    fn on_write<'a>(
+        &'a self,
+        request: &'a PeripheralRequest,
+        _offset: u64,
+        value: Vec<u8>,
+    ) -> BoxFuture<'a, WriteRequestResponse> {
+        Box::pin(on_write(request, value, &self.device, &self.switches))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::history::History;
+    use crate::mock::MockPeripheral;
+
+    const SERVICE: Uuid = Uuid::from_u128(0x1000);
+    const SWITCH: Uuid = Uuid::from_u128(0x1001);
+
+    /// The switch service on the mock, off, with a central subscribed to
+    /// the switch so every update shows up in `sent_values`.
+    async fn switch_device(
+        strict_writes: bool,
+    ) -> (MockPeripheral, Arc<Mutex<Device>>, SwitchBank) {
+        let (mock, _events) = MockPeripheral::channel(1);
+        let channels = [("switch".to_string(), SWITCH)];
+        let mut device = Device::mock(mock.clone());
+        device
+            .add_service(&service(SERVICE, &channels, SwitchState::Off).unwrap())
+            .await
+            .unwrap();
+        device.update_subscription(CentralId("central".to_string()), SWITCH, true);
+        let switches = SwitchBank::new(&channels, SwitchState::Off, &History::new(0))
+            .with_strict_writes(strict_writes);
+        (mock, Arc::new(Mutex::new(device)), switches)
+    }
+
+    fn request() -> PeripheralRequest {
+        PeripheralRequest {
+            client: "central".to_string(),
+            service: SERVICE,
+            characteristic: SWITCH,
+        }
+    }
+
+    fn power(switches: &SwitchBank) -> bool {
+        switches.primary().state.read().unwrap().power()
+    }
+
+    #[test]
+    fn parses_on_and_off() {
+        assert_eq!(SwitchState::parse("on"), Some(SwitchState::On));
+        assert_eq!(SwitchState::parse(" OFF\r\n"), Some(SwitchState::Off));
+        assert_eq!(SwitchState::parse(""), None);
+        assert_eq!(SwitchState::parse("onn"), None);
+        assert_eq!(SwitchState::On.as_str(), "on");
+        assert_eq!(SwitchState::from_bool(false), SwitchState::Off);
+    }
+
+    #[tokio::test]
+    async fn on_and_off_are_accepted_and_notified() {
+        let (mock, device, switches) = switch_device(true).await;
+        let response = on_write(&request(), b"on".to_vec(), &device, &switches).await;
+        assert!(matches!(response.response, RequestResponse::Success));
+        assert!(power(&switches));
+        let response = on_write(&request(), b"off".to_vec(), &device, &switches).await;
+        assert!(matches!(response.response, RequestResponse::Success));
+        assert!(!power(&switches));
+        assert_eq!(mock.sent_values(SWITCH), [b"on".to_vec(), b"off".to_vec()]);
+    }
+
+    #[tokio::test]
+    async fn strict_writes_reject_other_payloads_without_notifying() {
+        let (mock, device, switches) = switch_device(true).await;
+        let payloads = [Vec::new(), b"on".repeat(300), vec![0xC3, 0x28, 0x00, 0xFF]];
+        for payload in payloads {
+            let response = on_write(&request(), payload.clone(), &device, &switches).await;
+            assert!(
+                matches!(response.response, RequestResponse::UnlikelyError),
+                "{:?}",
+                payload
+            );
+        }
+        assert!(!power(&switches));
+        assert!(mock.sent_values(SWITCH).is_empty());
+        let stored = device.lock().await.stored_read(SWITCH, 0).unwrap();
+        assert_eq!(stored.value, b"off");
+    }
+
+    #[tokio::test]
+    async fn permissive_writes_echo_unknown_text() {
+        let (mock, device, switches) = switch_device(false).await;
+        let response = on_write(&request(), b"dim".to_vec(), &device, &switches).await;
+        assert!(matches!(response.response, RequestResponse::Success));
+        assert!(!power(&switches));
+        assert_eq!(mock.sent_values(SWITCH), [b"dim".to_vec()]);
+
+        // Text is echoed, binary junk still is not.
+        let response = on_write(&request(), vec![0xFF, 0xFE], &device, &switches).await;
+        assert!(matches!(response.response, RequestResponse::UnlikelyError));
+        assert_eq!(mock.sent_values(SWITCH).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn read_returns_on_or_off() {
+        let (_, device, switches) = switch_device(false).await;
+        let response = on_read(&request(), 0, &switches);
+        assert!(matches!(response.response, RequestResponse::Success));
+        assert_eq!(response.value, b"off");
+
+        on_write(&request(), b"ON".to_vec(), &device, &switches).await;
+        assert_eq!(on_read(&request(), 0, &switches).value, b"on");
+        assert_eq!(on_read(&request(), 1, &switches).value, b"n");
+    }
+
+    #[tokio::test]
+    async fn writing_the_current_state_is_not_notified() {
+        let (mock, device, switches) = switch_device(false).await;
+        on_write(&request(), b"on".to_vec(), &device, &switches).await;
+        let response = on_write(&request(), b"on".to_vec(), &device, &switches).await;
+        assert!(matches!(response.response, RequestResponse::Success));
+        assert_eq!(mock.sent_values(SWITCH), [b"on".to_vec()]);
+        assert_eq!(
+            switches.primary().state.read().unwrap().last_source(),
+            Source::BleWrite
+        );
+    }
+
+    #[tokio::test]
+    async fn status_json_has_a_stable_shape() {
+        let (_, device, switches) = switch_device(false).await;
+        on_write(&request(), b"on".to_vec(), &device, &switches).await;
+        let status = Status::of(&switches);
+        let json = serde_json::to_value(&status).unwrap();
+        let mut keys: Vec<&str> = json
+            .as_object()
+            .unwrap()
+            .keys()
+            .map(String::as_str)
+            .collect();
+        let mut expected = [
+            "power",
+            "brightness",
+            "uptime_secs",
+            "firmware",
+            "last_source",
+            "secs_since_change",
+            "last_central",
+            "last_characteristic",
+        ];
+        keys.sort();
+        expected.sort();
+        assert_eq!(keys, expected);
+        assert_eq!(json["power"], true);
+        assert_eq!(json["last_source"], "BLE write");
+        assert_eq!(json["last_central"], "central");
+
+        let parsed: Status = serde_json::from_value(json).unwrap();
+        assert_eq!(parsed, status);
+    }
+
+    #[tokio::test]
+    async fn status_reads_in_chunks_and_refuses_writes() {
+        let (_, device, switches) = switch_device(false).await;
+        let status = PeripheralRequest {
+            characteristic: Uuid::from_string(STATUS_UUID),
+            ..request()
+        };
+        let whole = on_read(&status, 0, &switches).value;
+        let mut chunks = Vec::new();
+        while chunks.len() < whole.len() {
+            let chunk = on_read(&status, chunks.len() as u64, &switches);
+            assert!(matches!(chunk.response, RequestResponse::Success));
+            chunks.extend(chunk.value.into_iter().take(20));
+        }
+        assert_eq!(chunks, whole);
+        let parsed: Status = serde_json::from_slice(&whole).unwrap();
+        assert!(!parsed.power);
+
+        let response = on_write(&status, b"{}".to_vec(), &device, &switches).await;
+        assert!(matches!(
+            response.response,
+            RequestResponse::RequestNotSupported
+        ));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn status_chunks_come_from_one_document() {
+        let (_, device, switches) = switch_device(false).await;
+        let status = PeripheralRequest {
+            characteristic: Uuid::from_string(STATUS_UUID),
+            ..request()
+        };
+        let other = PeripheralRequest {
+            client: "other".to_string(),
+            ..status.clone()
+        };
+        let mut chunks = on_read(&status, 0, &switches).value;
+        chunks.truncate(20);
+        // Long enough for both counters to gain a digit, with a change in
+        // between.
+        tokio::time::advance(Duration::from_secs(10)).await;
+        on_write(&request(), b"on".to_vec(), &device, &switches).await;
+        // Another central starting a read leaves this one's document alone.
+        on_read(&other, 0, &switches);
+        loop {
+            let chunk = on_read(&status, chunks.len() as u64, &switches);
+            assert!(matches!(chunk.response, RequestResponse::Success));
+            if chunk.value.is_empty() {
+                break;
+            }
+            chunks.extend(chunk.value.into_iter().take(20));
+        }
+        let parsed: Status = serde_json::from_slice(&chunks).unwrap();
+        assert_eq!(parsed.uptime_secs, 0);
+        assert!(!parsed.power);
+
+        let fresh: Status = serde_json::from_slice(&on_read(&status, 0, &switches).value).unwrap();
+        assert_eq!(fresh.uptime_secs, 10);
+        assert!(fresh.power);
+    }
+}
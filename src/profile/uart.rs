@@ -0,0 +1,81 @@
+use uuid::Uuid;
+
+use ble_peripheral_rust::gatt::{
+    peripheral_event::{
+        PeripheralRequest, ReadRequestResponse, RequestResponse, WriteRequestResponse,
+    },
+    service::Service,
+};
+
+use crate::builder::{BuildError, CharacteristicBuilder, ServiceBuilder};
+use crate::events::LOG_TARGET;
+use crate::handler::CharacteristicHandler;
+use crate::peripheral::BoxFuture;
+use crate::responses::Failure;
+
+/// Nordic UART Service, understood by most BLE terminal apps.
+pub const SERVICE_UUID: Uuid = Uuid::from_u128(0x6E400001_B5A3_F393_E0A9_E50E24DCCA9E);
+/// Central -> peripheral.
+pub const RX_UUID: Uuid = Uuid::from_u128(0x6E400002_B5A3_F393_E0A9_E50E24DCCA9E);
+/// Peripheral -> central, fed from the console.
+pub const TX_UUID: Uuid = Uuid::from_u128(0x6E400003_B5A3_F393_E0A9_E50E24DCCA9E);
+
+pub fn service() -> Result<Service, BuildError> {
+    ServiceBuilder::new(SERVICE_UUID)
+        .characteristic(
+            CharacteristicBuilder::new(RX_UUID)
+                .writable()
+                .write_without_response()
+                .build()?,
+        )
+        .characteristic(CharacteristicBuilder::new(TX_UUID).notify().build()?)
+        .build()
+}
+
+pub fn on_read(request: &PeripheralRequest, offset: u64) -> ReadRequestResponse {
+    log::info!(
+        target: LOG_TARGET,
+        "ReadRequest: {:?} Offset: {} -> UART has no readable value",
+        request,
+        offset
+    );
+    Failure::WriteOnly.read_response()
+}
+
+pub fn on_write(request: &PeripheralRequest, value: Vec<u8>) -> WriteRequestResponse {
+    if request.characteristic != RX_UUID {
+        log::warn!(
+            target: LOG_TARGET,
+            "WriteRequest: {:?} is not the UART RX characteristic",
+            request
+        );
+        return Failure::ReadOnly.write_response();
+    }
+
+    log::info!(target: LOG_TARGET, "RX: {}", String::from_utf8_lossy(&value));
+    WriteRequestResponse {
+        response: RequestResponse::Success,
+    }
+}
+
+/// Serves RX and TX.
+pub struct UartHandler;
+
+impl CharacteristicHandler for UartHandler {
+    fn on_read<'a>(
+        &'a self,
+        request: &'a PeripheralRequest,
+        offset: u64,
+    ) -> BoxFuture<'a, ReadRequestResponse> {
+        Box::pin(async move { on_read(request, offset) })
+    }
+
+    fn on_write<'a>(
+        &'a self,
+        request: &'a PeripheralRequest,
+        _offset: u64,
+        value: Vec<u8>,
+    ) -> BoxFuture<'a, WriteRequestResponse> {
+        Box::pin(async move { on_write(request, value) })
+    }
+}
@@ -0,0 +1,181 @@
+use std::io::BufRead;
+use std::path::PathBuf;
+
+use rustyline::{error::ReadlineError, history::FileHistory, Editor, ExternalPrinter};
+use tokio::sync::{mpsc, oneshot};
+
+use crate::completion::{ConsoleHelper, KnownUuids};
+use crate::logging;
+
+/// Console history file, kept in the home directory.
+pub const HISTORY_FILE: &str = ".ble_peripheral_history";
+
+const PROMPT: &str = "> ";
+
+/// What the user did at the prompt.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Input {
+    Line(String),
+    /// Ctrl-C, which should shut down like `quit`.
+    Interrupted,
+    /// Ctrl-D, end of piped input or a terminal error.
+    Closed,
+}
+
+/// A line-editing prompt with history and tab completion, read on its own
+/// thread.
+///
+/// The prompt is only shown again once the previous line has been handled,
+/// that is on the next call to `next`, so command output is not printed over
+/// it. Log lines are routed above the prompt while it is up.
+pub struct Prompt {
+    receiver: mpsc::Receiver<(Input, oneshot::Sender<()>)>,
+    handled: Option<oneshot::Sender<()>>,
+}
+
+impl Prompt {
+    /// Starts reading, loading and saving history at `history` if given.
+    /// Tab completes characteristic UUIDs from `uuids`.
+    pub fn spawn(history: Option<PathBuf>, uuids: KnownUuids) -> Self {
+        let (sender, receiver) = mpsc::channel(1);
+        std::thread::spawn(move || read_lines(history, uuids, sender));
+        Self {
+            receiver,
+            handled: None,
+        }
+    }
+
+    /// Reads plain lines from stdin with no prompt, line editing or
+    /// history, so stdout carries nothing but command output.
+    pub fn spawn_plain() -> Self {
+        Self::from_reader(std::io::stdin().lock())
+    }
+
+    /// Like `spawn_plain`, reading from `reader` instead of stdin.
+    pub fn from_reader(reader: impl BufRead + Send + 'static) -> Self {
+        let (sender, receiver) = mpsc::channel(1);
+        std::thread::spawn(move || read_plain_lines(reader, sender));
+        Self {
+            receiver,
+            handled: None,
+        }
+    }
+
+    /// Waits for the next input, first letting the prompt come back.
+    pub async fn next(&mut self) -> Input {
+        self.handled.take();
+        match self.receiver.recv().await {
+            Some((input, handled)) => {
+                self.handled = Some(handled);
+                input
+            }
+            None => Input::Closed,
+        }
+    }
+}
+
+/// `~/.ble_peripheral_history`, or `None` if there is no home directory.
+pub fn default_history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE))
+}
+
+fn read_lines(
+    history: Option<PathBuf>,
+    uuids: KnownUuids,
+    sender: mpsc::Sender<(Input, oneshot::Sender<()>)>,
+) {
+    let mut editor = match Editor::<ConsoleHelper, FileHistory>::new() {
+        Ok(editor) => editor,
+        Err(err) => {
+            log::error!("Error opening the console: {}", err);
+            let (handled, _) = oneshot::channel();
+            let _ = sender.blocking_send((Input::Closed, handled));
+            return;
+        }
+    };
+    editor.set_helper(Some(ConsoleHelper::new(uuids)));
+    if let Some(path) = &history {
+        // A missing file just means there is no history yet.
+        let _ = editor.load_history(path);
+    }
+    match editor.create_external_printer() {
+        Ok(mut printer) => logging::set_printer(move |line| {
+            let _ = printer.print(line);
+        }),
+        Err(err) => log::debug!("Log lines will not be kept clear of the prompt: {}", err),
+    }
+
+    loop {
+        let input = match editor.readline(PROMPT) {
+            Ok(line) => {
+                if !line.trim().is_empty() {
+                    let _ = editor.add_history_entry(line.as_str());
+                    if let Some(path) = &history {
+                        if let Err(err) = editor.save_history(path) {
+                            log::warn!("Error saving console history: {}", err);
+                        }
+                    }
+                }
+                Input::Line(line)
+            }
+            Err(ReadlineError::Interrupted) => Input::Interrupted,
+            Err(ReadlineError::Eof) => Input::Closed,
+            Err(err) => {
+                log::error!("Error reading from console: {}", err);
+                Input::Closed
+            }
+        };
+        let done = !matches!(input, Input::Line(_));
+        let (handled, handled_rx) = oneshot::channel();
+        if sender.blocking_send((input, handled)).is_err() || done {
+            break;
+        }
+        // Returns once the line is handled, or the app stopped listening.
+        let _ = handled_rx.blocking_recv();
+    }
+    logging::clear_printer();
+}
+
+fn read_plain_lines(reader: impl BufRead, sender: mpsc::Sender<(Input, oneshot::Sender<()>)>) {
+    let mut lines = reader.lines();
+    loop {
+        let input = match lines.next() {
+            Some(Ok(line)) => Input::Line(line),
+            Some(Err(err)) => {
+                log::error!("Error reading console input: {}", err);
+                Input::Closed
+            }
+            None => Input::Closed,
+        };
+        let done = !matches!(input, Input::Line(_));
+        let (handled, handled_rx) = oneshot::channel();
+        if sender.blocking_send((input, handled)).is_err() || done {
+            break;
+        }
+        let _ = handled_rx.blocking_recv();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[tokio::test]
+    async fn piped_input_closes_at_eof() {
+        let mut prompt = Prompt::from_reader(Cursor::new("status\n\non\n"));
+        assert_eq!(prompt.next().await, Input::Line("status".to_string()));
+        assert_eq!(prompt.next().await, Input::Line(String::new()));
+        assert_eq!(prompt.next().await, Input::Line("on".to_string()));
+        assert_eq!(prompt.next().await, Input::Closed);
+        // The reader is gone, so it stays closed.
+        assert_eq!(prompt.next().await, Input::Closed);
+    }
+
+    #[tokio::test]
+    async fn empty_input_is_closed_at_once() {
+        let mut prompt = Prompt::from_reader(Cursor::new(""));
+        assert_eq!(prompt.next().await, Input::Closed);
+    }
+}
@@ -0,0 +1,178 @@
+use std::collections::HashSet;
+use std::sync::Mutex as StdMutex;
+
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use crate::config::ConfigSource;
+use crate::device::Device;
+use crate::profile::machine::CONTROL_UUID;
+use crate::state_machine::StateMachine;
+use crate::switches::SwitchBank;
+
+/// Re-reads the service file or directory and brings the running GATT table in line with it.
+///
+/// New services are added, characteristics that disappeared are retired,
+/// and the switch aliases and the state machine's table are replaced with
+/// the file's. If the file cannot be loaded the running configuration is
+/// left untouched.
+pub async fn reload_services(
+    device: &Mutex<Device>,
+    switches: &SwitchBank,
+    machine: &StdMutex<StateMachine>,
+    source: &ConfigSource,
+) {
+    let config = match source.load() {
+        Ok(config) => config,
+        Err(err) => {
+            log::error!("Reload failed, keeping current configuration: {}", err);
+            return;
+        }
+    };
+    let services = config.services;
+    switches.set_aliases(config.aliases);
+    // Without a table the default one applies, as at startup.
+    let moved_to = {
+        let mut machine = machine.lock().unwrap();
+        machine
+            .set_table(config.state_machine.unwrap_or_default())
+            .then(|| machine.current().to_string())
+    };
+
+    let mut device = device.lock().await;
+    if let Some(state) = moved_to {
+        log::info!("State machine table replaced, now in {}", state);
+        if device.characteristic(CONTROL_UUID).is_some() {
+            if let Err(err) = device
+                .update_characteristic(CONTROL_UUID, state.into_bytes())
+                .await
+            {
+                log::error!("Error updating state characteristic: {:?}", err);
+            }
+        }
+    }
+    let mut added = 0;
+    let mut retired = 0;
+    for service in &services {
+        let Some(current) = device.service(service.uuid).cloned() else {
+            match device.add_service(service).await {
+                Ok(()) => added += 1,
+                Err(err) => log::error!("Error adding service {:?}: {}", service.uuid, err),
+            }
+            continue;
+        };
+
+        let wanted: HashSet<Uuid> = service.characteristics.iter().map(|c| c.uuid).collect();
+        let existing: HashSet<Uuid> = current.characteristics.iter().map(|c| c.uuid).collect();
+        for uuid in existing.difference(&wanted) {
+            device.retire_characteristic(*uuid);
+            retired += 1;
+        }
+        for uuid in wanted.difference(&existing) {
+            log::warn!(
+                "Characteristic {:?} added to existing service {:?} needs a restart to be registered",
+                uuid,
+                service.uuid
+            );
+        }
+    }
+
+    log::info!(
+        "Reloaded {}: {} service(s) added, {} characteristic(s) retired",
+        source.path().display(),
+        added,
+        retired
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::history::History;
+    use crate::mock::MockPeripheral;
+    use crate::profile::machine;
+    use crate::profile::switch::{self, SwitchState};
+    use crate::state_machine::TransitionTable;
+
+    fn config_file(test: &str, contents: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("bluetooth-reload-{}-{}", std::process::id(), test));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[tokio::test]
+    async fn reload_replaces_the_aliases() {
+        let path = config_file(
+            "aliases",
+            "[aliases]\nan = \"on\"\n\n[[services]]\nuuid = \"180F\"\n",
+        );
+        let source = ConfigSource::File(path.clone());
+        let (mock, _events) = MockPeripheral::channel(1);
+        let device = Mutex::new(Device::mock(mock));
+        let switches = SwitchBank::new(&[], SwitchState::Off, &History::new(0));
+        let machine = StdMutex::new(StateMachine::new(TransitionTable::default()));
+        assert_eq!(switch::resolve("an", &switches.aliases()), None);
+
+        reload_services(&device, &switches, &machine, &source).await;
+        assert_eq!(
+            switch::resolve("an", &switches.aliases()),
+            Some(SwitchState::On)
+        );
+
+        std::fs::write(
+            &path,
+            "[aliases]\naus = \"off\"\n\n[[services]]\nuuid = \"180F\"\n",
+        )
+        .unwrap();
+        reload_services(&device, &switches, &machine, &source).await;
+        assert_eq!(switch::resolve("an", &switches.aliases()), None);
+        assert_eq!(
+            switch::resolve("aus", &switches.aliases()),
+            Some(SwitchState::Off)
+        );
+    }
+
+    #[tokio::test]
+    async fn reload_replaces_the_state_machine_table() {
+        let path = config_file(
+            "machine",
+            concat!(
+                "[state_machine]\n",
+                "states = [\"Off\", \"On\"]\n",
+                "transitions = { Off = [\"On\"], On = [\"Off\"] }\n",
+                "\n[[services]]\nuuid = \"180F\"\n",
+            ),
+        );
+        let source = ConfigSource::File(path.clone());
+        let (mock, _events) = MockPeripheral::channel(1);
+        let machine = StdMutex::new(StateMachine::new(TransitionTable::default()));
+        let service = machine::service(&machine.lock().unwrap()).unwrap();
+        let mut device = Device::mock(mock);
+        device.add_service(&service).await.unwrap();
+        let device = Mutex::new(device);
+        let switches = SwitchBank::new(&[], SwitchState::Off, &History::new(0));
+
+        reload_services(&device, &switches, &machine, &source).await;
+        {
+            let machine = machine.lock().unwrap();
+            assert_eq!(machine.table().states(), ["Off", "On"]);
+            // `Idle` is not in the new table, so the machine starts over.
+            assert_eq!(machine.current(), "Off");
+        }
+        let stored = device.lock().await.stored_read(machine::CONTROL_UUID, 0);
+        assert_eq!(stored.unwrap().value, b"Off");
+        assert!(machine.lock().unwrap().request(b"On").is_ok());
+
+        // Without a table the default one is back.
+        std::fs::write(&path, "[[services]]\nuuid = \"180F\"\n").unwrap();
+        reload_services(&device, &switches, &machine, &source).await;
+        assert_eq!(*machine.lock().unwrap().table(), TransitionTable::default());
+        let stored = device.lock().await.stored_read(machine::CONTROL_UUID, 0);
+        assert_eq!(stored.unwrap().value, b"Idle");
+    }
+}
@@ -0,0 +1,259 @@
+use std::fmt;
+
+use ble_peripheral_rust::gatt::peripheral_event::{
+    ReadRequestResponse, RequestResponse, WriteRequestResponse,
+};
+
+/// Constructors for read responses that the backend type lacks.
+pub trait ReadRequestResponseExt {
+    /// Answers a read at `offset` with the rest of `full`, so handlers can
+    /// always return the whole value and long reads spanning several ATT
+    /// requests come out right. An offset past the end yields
+    /// `RequestResponse::InvalidOffset`; an offset equal to the length
+    /// yields an empty value, as the ATT spec requires.
+    fn from_value(full: Vec<u8>, offset: u64) -> Self;
+}
+
+impl ReadRequestResponseExt for ReadRequestResponse {
+    fn from_value(mut full: Vec<u8>, offset: u64) -> Self {
+        match usize::try_from(offset) {
+            Ok(offset) if offset <= full.len() => {
+                full.drain(..offset);
+                ReadRequestResponse {
+                    value: full,
+                    response: RequestResponse::Success,
+                }
+            }
+            _ => Failure::BadOffset.read_response(),
+        }
+    }
+}
+
+/// ATT errors a handler can answer with.
+///
+/// The backend's `RequestResponse` only carries a handful of codes and
+/// ble-peripheral-rust translates those into each platform's native error,
+/// so the remaining ones are sent as the closest code it has. `att_code`
+/// gives the code the Core spec assigns, which is what gets logged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AttError {
+    InvalidOffset,
+    InvalidAttributeLength,
+    ReadNotPermitted,
+    WriteNotPermitted,
+    InsufficientAuthentication,
+    InsufficientEncryption,
+    UnlikelyError,
+    /// Application-defined error, from `0x80` to `0x9F`.
+    ApplicationError(u8),
+}
+
+impl AttError {
+    pub fn att_code(self) -> u8 {
+        match self {
+            AttError::ReadNotPermitted => 0x02,
+            AttError::WriteNotPermitted => 0x03,
+            AttError::InsufficientAuthentication => 0x05,
+            AttError::InvalidOffset => 0x07,
+            AttError::InvalidAttributeLength => 0x0D,
+            AttError::UnlikelyError => 0x0E,
+            AttError::InsufficientEncryption => 0x0F,
+            AttError::ApplicationError(code) => code,
+        }
+    }
+
+    /// The backend response sent to the central.
+    pub fn response(self) -> RequestResponse {
+        match self {
+            AttError::InvalidOffset => RequestResponse::InvalidOffset,
+            AttError::ReadNotPermitted | AttError::WriteNotPermitted => {
+                RequestResponse::RequestNotSupported
+            }
+            AttError::InvalidAttributeLength
+            | AttError::InsufficientAuthentication
+            | AttError::InsufficientEncryption
+            | AttError::UnlikelyError
+            | AttError::ApplicationError(_) => RequestResponse::UnlikelyError,
+        }
+    }
+
+    pub fn read_response(self) -> ReadRequestResponse {
+        ReadRequestResponse {
+            value: Vec::new(),
+            response: self.response(),
+        }
+    }
+
+    pub fn write_response(self) -> WriteRequestResponse {
+        WriteRequestResponse {
+            response: self.response(),
+        }
+    }
+}
+
+impl fmt::Display for AttError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            AttError::InvalidOffset => "invalid offset",
+            AttError::InvalidAttributeLength => "invalid attribute value length",
+            AttError::ReadNotPermitted => "read not permitted",
+            AttError::WriteNotPermitted => "write not permitted",
+            AttError::InsufficientAuthentication => "insufficient authentication",
+            AttError::InsufficientEncryption => "insufficient encryption",
+            AttError::UnlikelyError => "unlikely error",
+            AttError::ApplicationError(_) => "application error",
+        };
+        write!(f, "{} (0x{:02X})", name, self.att_code())
+    }
+}
+
+/// Why a handler could not serve a read or write, in the handler's terms.
+///
+/// Handlers report what went wrong and `att_error` alone decides which ATT
+/// error the central sees, so the same failure gets the same code from every
+/// profile.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// The offset is past the end of the value, or does not continue a long
+    /// write.
+    BadOffset,
+    /// The value has the wrong number of bytes, or a long write grew too
+    /// long.
+    WrongLength,
+    /// The value is not valid UTF-8 where text is expected.
+    NotUtf8,
+    /// The value is text the characteristic does not understand.
+    Unparseable,
+    /// The value is understood but refused in the current state.
+    Refused,
+    /// The characteristic cannot be written.
+    ReadOnly,
+    /// The characteristic cannot be read.
+    WriteOnly,
+    /// Something failed on this side, e.g. encoding the value.
+    Internal,
+}
+
+impl Failure {
+    /// The ATT error sent for this failure.
+    pub fn att_error(self) -> AttError {
+        match self {
+            Failure::BadOffset => AttError::InvalidOffset,
+            Failure::WrongLength => AttError::InvalidAttributeLength,
+            Failure::ReadOnly => AttError::WriteNotPermitted,
+            Failure::WriteOnly => AttError::ReadNotPermitted,
+            Failure::NotUtf8 | Failure::Unparseable | Failure::Refused | Failure::Internal => {
+                AttError::UnlikelyError
+            }
+        }
+    }
+
+    pub fn read_response(self) -> ReadRequestResponse {
+        self.att_error().read_response()
+    }
+
+    pub fn write_response(self) -> WriteRequestResponse {
+        self.att_error().write_response()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn from_value_at_offset_zero_is_the_whole_value() {
+        let response = ReadRequestResponse::from_value(b"status".to_vec(), 0);
+        assert!(matches!(response.response, RequestResponse::Success));
+        assert_eq!(response.value, b"status");
+    }
+
+    #[test]
+    fn from_value_mid_buffer_is_the_rest() {
+        let response = ReadRequestResponse::from_value(b"status".to_vec(), 2);
+        assert!(matches!(response.response, RequestResponse::Success));
+        assert_eq!(response.value, b"atus");
+    }
+
+    #[test]
+    fn from_value_at_the_end_is_empty() {
+        let response = ReadRequestResponse::from_value(b"status".to_vec(), 6);
+        assert!(matches!(response.response, RequestResponse::Success));
+        assert!(response.value.is_empty());
+    }
+
+    #[test]
+    fn from_value_past_the_end_is_an_invalid_offset() {
+        let response = ReadRequestResponse::from_value(b"status".to_vec(), 7);
+        assert!(matches!(response.response, RequestResponse::InvalidOffset));
+        assert!(response.value.is_empty());
+
+        let response = ReadRequestResponse::from_value(b"status".to_vec(), u64::MAX);
+        assert!(matches!(response.response, RequestResponse::InvalidOffset));
+    }
+
+    #[test]
+    fn failures_map_to_att_codes() {
+        let expected = [
+            (Failure::BadOffset, 0x07),
+            (Failure::WrongLength, 0x0D),
+            (Failure::NotUtf8, 0x0E),
+            (Failure::Unparseable, 0x0E),
+            (Failure::Refused, 0x0E),
+            (Failure::ReadOnly, 0x03),
+            (Failure::WriteOnly, 0x02),
+            (Failure::Internal, 0x0E),
+        ];
+        for (failure, code) in expected {
+            assert_eq!(failure.att_error().att_code(), code, "{:?}", failure);
+        }
+    }
+
+    /// Both responses for `failure` carry `expected`, and the read no value.
+    fn assert_responds(failure: Failure, expected: RequestResponse) {
+        let expected = std::mem::discriminant(&expected);
+        let read = failure.read_response();
+        assert_eq!(
+            std::mem::discriminant(&read.response),
+            expected,
+            "{:?}",
+            failure
+        );
+        assert!(read.value.is_empty(), "{:?}", failure);
+        let write = failure.write_response();
+        assert_eq!(
+            std::mem::discriminant(&write.response),
+            expected,
+            "{:?}",
+            failure
+        );
+    }
+
+    #[test]
+    fn failures_map_to_backend_responses() {
+        assert_responds(Failure::BadOffset, RequestResponse::InvalidOffset);
+        assert_responds(Failure::WrongLength, RequestResponse::UnlikelyError);
+        assert_responds(Failure::NotUtf8, RequestResponse::UnlikelyError);
+        assert_responds(Failure::Unparseable, RequestResponse::UnlikelyError);
+        assert_responds(Failure::Refused, RequestResponse::UnlikelyError);
+        assert_responds(Failure::ReadOnly, RequestResponse::RequestNotSupported);
+        assert_responds(Failure::WriteOnly, RequestResponse::RequestNotSupported);
+        assert_responds(Failure::Internal, RequestResponse::UnlikelyError);
+    }
+
+    #[test]
+    fn att_errors_display_their_code() {
+        assert_eq!(
+            AttError::InvalidAttributeLength.to_string(),
+            "invalid attribute value length (0x0D)"
+        );
+        assert_eq!(
+            AttError::ApplicationError(0x80).to_string(),
+            "application error (0x80)"
+        );
+        assert!(matches!(
+            AttError::ApplicationError(0x80).response(),
+            RequestResponse::UnlikelyError
+        ));
+    }
+}
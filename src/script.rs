@@ -0,0 +1,71 @@
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::aliases::Aliases;
+use crate::console::{self, Command, ParseError};
+
+/// One step of a console script.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Step {
+    /// `sleep <seconds>`, which may be fractional.
+    Sleep(Duration),
+    Command(Command),
+}
+
+/// A file of console commands, one per line, run as if typed at the prompt.
+///
+/// Blank lines and lines starting with `#` are skipped.
+#[derive(Debug, Clone)]
+pub struct Script {
+    path: PathBuf,
+    lines: Vec<(usize, String)>,
+}
+
+impl Script {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let contents = std::fs::read_to_string(path)?;
+        let lines = contents
+            .lines()
+            .enumerate()
+            .map(|(index, line)| (index + 1, line.trim()))
+            .filter(|(_, line)| !line.is_empty() && !line.starts_with('#'))
+            .map(|(number, line)| (number, line.to_string()))
+            .collect();
+        Ok(Self {
+            path: path.to_path_buf(),
+            lines,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The lines to run with their 1-based line numbers.
+    pub fn lines(&self) -> impl Iterator<Item = (usize, &str)> {
+        self.lines
+            .iter()
+            .map(|(number, line)| (*number, line.as_str()))
+    }
+}
+
+/// Parses one script line: `sleep <seconds>` or anything `console::parse`
+/// accepts with `aliases`.
+pub fn parse_step(line: &str, aliases: &Aliases) -> Result<Step, ParseError> {
+    let mut words = line.split_whitespace();
+    if !words
+        .next()
+        .is_some_and(|word| word.eq_ignore_ascii_case("sleep"))
+    {
+        return console::parse(line, aliases).map(Step::Command);
+    }
+    let (Some(secs), None) = (words.next(), words.next()) else {
+        return Err(ParseError::Usage("sleep <seconds>"));
+    };
+    secs.parse::<f64>()
+        .ok()
+        .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+        .map(Step::Sleep)
+        .ok_or_else(|| ParseError::Invalid(format!("'{}' is not a number of seconds", secs)))
+}
@@ -0,0 +1,80 @@
+use std::fmt;
+
+use uuid::Uuid;
+
+use crate::config::BLUETOOTH_BASE_UUID;
+
+/// Why a string is not a UUID `try_from_string` accepts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UuidParseError {
+    /// Four or eight characters that are not all hex digits.
+    InvalidShort { input: String, bits: u8 },
+    /// Neither a short form nor a full UUID.
+    Invalid(String),
+}
+
+impl fmt::Display for UuidParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UuidParseError::InvalidShort { input, bits } => {
+                write!(f, "invalid {}-bit UUID '{}'", bits, input)
+            }
+            UuidParseError::Invalid(input) => write!(f, "invalid UUID '{}'", input),
+        }
+    }
+}
+
+impl std::error::Error for UuidParseError {}
+
+/// Checked counterparts to the backend's `ShortUuid`, whose `from_string`
+/// reads anything as a 16-bit value.
+pub trait ShortUuidExt: Sized {
+    /// Parses a 16-bit (`180F`) or 32-bit (`0000180F`) short form, with or
+    /// without `0x`, or a full UUID with or without hyphens. Short forms
+    /// must be all hex digits, so a typo is an error instead of a
+    /// different UUID.
+    fn try_from_string(input: &str) -> Result<Self, UuidParseError>;
+
+    /// The 16-bit value, if this UUID is one derived from the Bluetooth
+    /// Base UUID.
+    fn to_short(&self) -> Option<u16>;
+}
+
+impl ShortUuidExt for Uuid {
+    fn try_from_string(input: &str) -> Result<Self, UuidParseError> {
+        let trimmed = input.trim();
+        let hex = trimmed
+            .strip_prefix("0x")
+            .or_else(|| trimmed.strip_prefix("0X"))
+            .unwrap_or(trimmed);
+        let bits = match hex.len() {
+            4 => 16,
+            8 => 32,
+            _ => {
+                return Uuid::parse_str(trimmed)
+                    .map_err(|_| UuidParseError::Invalid(input.to_string()))
+            }
+        };
+        // `from_str_radix` alone would take a sign, e.g. `+180`.
+        let short = hex
+            .bytes()
+            .all(|byte| byte.is_ascii_hexdigit())
+            .then(|| u32::from_str_radix(hex, 16).ok())
+            .flatten()
+            .ok_or_else(|| UuidParseError::InvalidShort {
+                input: input.to_string(),
+                bits,
+            })?;
+        Ok(Uuid::from_u128(
+            ((short as u128) << 96) | BLUETOOTH_BASE_UUID,
+        ))
+    }
+
+    fn to_short(&self) -> Option<u16> {
+        let value = self.as_u128();
+        if value & 0xFFFF_FFFF_FFFF_FFFF_FFFF_FFFF != BLUETOOTH_BASE_UUID {
+            return None;
+        }
+        u16::try_from(value >> 96).ok()
+    }
+}
@@ -0,0 +1,179 @@
+use std::io;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::device::Device;
+use crate::persist;
+use crate::switches::{Switch, SwitchBank};
+
+/// Format version written into every snapshot. Bumped when a field is
+/// renamed or removed; new fields may appear without a bump.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// The whole runtime picture, for post-mortems on a misbehaving unit.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    /// Unix time the snapshot was taken, in seconds.
+    pub taken_at: u64,
+    pub uptime_secs: u64,
+    /// What is being advertised, or `None` when advertising is stopped.
+    pub advertising: Option<AdvertisingSnapshot>,
+    pub switches: Vec<SwitchSnapshot>,
+    pub characteristics: Vec<CharacteristicSnapshot>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AdvertisingSnapshot {
+    pub name: String,
+    pub service_uuids: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SwitchSnapshot {
+    pub name: String,
+    pub characteristic: String,
+    pub power: bool,
+    pub brightness: u8,
+    pub last_source: String,
+    pub secs_since_change: u64,
+    /// Central whose write made the last change, if one did.
+    pub last_central: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CharacteristicSnapshot {
+    pub service: String,
+    pub uuid: String,
+    /// Last value set on the characteristic, as lowercase hex.
+    pub value: Option<String>,
+    /// Unix time of the last update sent through the app, in seconds.
+    pub updated_at: Option<u64>,
+    pub subscribers: usize,
+}
+
+impl Snapshot {
+    pub fn capture(device: &Device, switches: &SwitchBank) -> Self {
+        let switches_snapshot = switches.iter().map(SwitchSnapshot::of).collect();
+        let characteristics = device
+            .services()
+            .iter()
+            .flat_map(|service| {
+                service
+                    .characteristics
+                    .iter()
+                    .map(move |characteristic| (service.uuid, characteristic))
+            })
+            .map(|(service, characteristic)| CharacteristicSnapshot {
+                service: service.to_string(),
+                uuid: characteristic.uuid.to_string(),
+                value: characteristic.value.as_deref().map(hex),
+                updated_at: device.updated_at(characteristic.uuid).map(unix_secs),
+                subscribers: device.subscriptions().subscriber_count(characteristic.uuid),
+            })
+            .collect();
+        Self {
+            version: SNAPSHOT_VERSION,
+            taken_at: unix_secs(SystemTime::now()),
+            uptime_secs: switches.uptime().as_secs(),
+            advertising: AdvertisingSnapshot::of(device),
+            switches: switches_snapshot,
+            characteristics,
+        }
+    }
+
+    /// Writes the snapshot as pretty JSON through a temporary file, so a
+    /// reader never sees a half-written snapshot.
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        let contents = serde_json::to_string_pretty(self)?;
+        persist::write_atomically(path, contents.as_bytes(), true)
+    }
+}
+
+impl AdvertisingSnapshot {
+    /// What `device` advertises, or `None` when advertising is stopped.
+    pub fn of(device: &Device) -> Option<Self> {
+        device.advertisement().map(|data| Self {
+            name: data.advertised_name().to_string(),
+            service_uuids: data
+                .all_service_uuids()
+                .iter()
+                .map(ToString::to_string)
+                .collect(),
+        })
+    }
+}
+
+impl SwitchSnapshot {
+    pub fn of(switch: &Switch) -> Self {
+        let state = switch.state.read().unwrap();
+        Self {
+            name: switch.name.clone(),
+            characteristic: switch.characteristic.to_string(),
+            power: state.power(),
+            brightness: state.brightness(),
+            last_source: state.last_source().to_string(),
+            secs_since_change: state.last_changed().elapsed().as_secs(),
+            last_central: state.last_origin().map(|origin| origin.central.to_string()),
+        }
+    }
+}
+
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub(crate) fn unix_secs(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since| since.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use uuid::Uuid;
+
+    use super::*;
+    use crate::central::CentralId;
+    use crate::history::History;
+    use crate::mock::MockPeripheral;
+    use crate::profile::switch::{self, SwitchState};
+
+    #[tokio::test]
+    async fn written_snapshot_reads_back_the_same() {
+        let (mock, _events) = MockPeripheral::channel(1);
+        let switch_uuid = Uuid::from_u128(0x1001);
+        let channels = [("lamp".to_string(), switch_uuid)];
+        let mut device = Device::mock(mock);
+        device
+            .add_service(
+                &switch::service(Uuid::from_u128(0x1000), &channels, SwitchState::On).unwrap(),
+            )
+            .await
+            .unwrap();
+        device.update_subscription(CentralId("central".to_string()), switch_uuid, true);
+        let switches = SwitchBank::new(&channels, SwitchState::On, &History::new(0));
+
+        let snapshot = Snapshot::capture(&device, &switches);
+        assert_eq!(snapshot.version, SNAPSHOT_VERSION);
+        assert!(snapshot.advertising.is_none());
+        assert_eq!(snapshot.switches[0].name, "lamp");
+        assert!(snapshot.switches[0].power);
+        let characteristic = snapshot
+            .characteristics
+            .iter()
+            .find(|characteristic| characteristic.uuid == switch_uuid.to_string())
+            .unwrap();
+        assert_eq!(characteristic.value.as_deref(), Some("6f6e"));
+        assert_eq!(characteristic.subscribers, 1);
+
+        let dir = std::env::temp_dir().join(format!("bluetooth-snapshot-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("snapshot.json");
+        snapshot.write(&path).unwrap();
+        let read: Snapshot =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(read, snapshot);
+    }
+}
@@ -0,0 +1,374 @@
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+use tokio::sync::watch;
+use tokio::time::Instant;
+use uuid::Uuid;
+
+use crate::central::CentralId;
+use crate::history::{History, Transition};
+use crate::profile::switch::SwitchState;
+
+/// Brightness a switch starts with.
+pub const DEFAULT_BRIGHTNESS: u8 = u8::MAX;
+
+/// Where a state change came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    /// The state the app booted in.
+    Initial,
+    /// A command typed on the console.
+    Stdin,
+    /// A central writing the characteristic.
+    BleWrite,
+    /// Library code calling `set_power` directly.
+    Api,
+    /// The auto-off timer expiring.
+    Timer,
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Source::Initial => "initial",
+            Source::Stdin => "stdin",
+            Source::BleWrite => "BLE write",
+            Source::Api => "API",
+            Source::Timer => "timer",
+        };
+        f.write_str(name)
+    }
+}
+
+/// The central and characteristic behind a change made by a BLE write.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WriteOrigin {
+    pub central: CentralId,
+    pub characteristic: Uuid,
+}
+
+type ObserverFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+type Observer = Arc<dyn Fn(SwitchState, SwitchState, Source) -> ObserverFuture + Send + Sync>;
+
+/// The switch state shared by the event loop and the console.
+pub struct DeviceState {
+    power: bool,
+    brightness: u8,
+    last_changed: Instant,
+    last_source: Source,
+    last_origin: Option<WriteOrigin>,
+    changes: watch::Sender<SwitchState>,
+    observers: Vec<Observer>,
+    history: Option<(History, String)>,
+}
+
+impl fmt::Debug for DeviceState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeviceState")
+            .field("power", &self.power)
+            .field("brightness", &self.brightness)
+            .field("last_changed", &self.last_changed)
+            .field("last_source", &self.last_source)
+            .field("last_origin", &self.last_origin)
+            .field("observers", &self.observers.len())
+            .finish()
+    }
+}
+
+/// `DeviceState` as handed to the event loop, profiles and the console.
+pub type SharedState = Arc<RwLock<DeviceState>>;
+
+impl DeviceState {
+    pub fn new(initial: SwitchState) -> Self {
+        Self {
+            power: initial.is_on(),
+            brightness: DEFAULT_BRIGHTNESS,
+            last_changed: Instant::now(),
+            last_source: Source::Initial,
+            last_origin: None,
+            changes: watch::channel(initial).0,
+            observers: Vec::new(),
+            history: None,
+        }
+    }
+
+    pub fn shared(initial: SwitchState) -> SharedState {
+        Arc::new(RwLock::new(Self::new(initial)))
+    }
+
+    pub fn power(&self) -> bool {
+        self.power
+    }
+
+    pub fn switch_state(&self) -> SwitchState {
+        SwitchState::from_bool(self.power)
+    }
+
+    pub fn brightness(&self) -> u8 {
+        self.brightness
+    }
+
+    /// Sets the 0-255 level and returns whether it changed. Observers are
+    /// only told about power changes.
+    pub fn set_brightness(&mut self, level: u8, source: Source) -> bool {
+        self.set_brightness_from(level, source, None)
+    }
+
+    /// Like `set_brightness`, also recording which central wrote it.
+    pub fn set_brightness_from(
+        &mut self,
+        level: u8,
+        source: Source,
+        origin: Option<WriteOrigin>,
+    ) -> bool {
+        if self.brightness == level {
+            return false;
+        }
+        self.brightness = level;
+        self.last_changed = Instant::now();
+        self.last_source = source;
+        self.last_origin = origin;
+        log::info!("Brightness changed to: {} ({})", level, source);
+        true
+    }
+
+    /// When the power or brightness last actually changed, or when the state
+    /// was created.
+    pub fn last_changed(&self) -> Instant {
+        self.last_changed
+    }
+
+    pub fn last_source(&self) -> Source {
+        self.last_source
+    }
+
+    /// Central and characteristic of the last change, if a BLE write made it.
+    pub fn last_origin(&self) -> Option<&WriteOrigin> {
+        self.last_origin.as_ref()
+    }
+
+    /// Records every power change in `history` under the switch's `name`.
+    pub fn record_history(&mut self, history: History, name: impl Into<String>) {
+        self.history = Some((history, name.into()));
+    }
+
+    /// Receiver that is marked changed after every actual state change.
+    pub fn subscribe(&self) -> watch::Receiver<SwitchState> {
+        self.changes.subscribe()
+    }
+
+    /// Registers a callback run after every actual change with the old and
+    /// new state and where the change came from.
+    ///
+    /// Each call runs as its own task, so callbacks never hold the state or
+    /// device locks, may finish out of order, and a panic only ends that
+    /// task. Changes made outside a Tokio runtime are not reported.
+    pub fn on_change<F, Fut>(&mut self, callback: F)
+    where
+        F: Fn(SwitchState, SwitchState, Source) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let observer: Observer = Arc::new(move |old, new, source| -> ObserverFuture {
+            Box::pin(callback(old, new, source))
+        });
+        self.observers.push(observer);
+    }
+
+    fn notify_observers(&self, old: SwitchState, new: SwitchState, source: Source) {
+        if self.observers.is_empty() {
+            return;
+        }
+        let Ok(runtime) = tokio::runtime::Handle::try_current() else {
+            log::warn!("State changed outside a Tokio runtime, observers not run");
+            return;
+        };
+        for observer in &self.observers {
+            let task = runtime.spawn(observer(old, new, source));
+            runtime.spawn(async move {
+                if let Err(err) = task.await {
+                    if err.is_panic() {
+                        log::error!("State observer panicked");
+                    }
+                }
+            });
+        }
+    }
+
+    /// Turns the switch on or off and returns whether that changed anything.
+    /// Setting the current value again leaves the timestamps alone.
+    pub fn set_power(&mut self, on: bool, source: Source) -> bool {
+        self.set_power_from(on, source, None)
+    }
+
+    /// Like `set_power`, also recording which central wrote the change.
+    pub fn set_power_from(
+        &mut self,
+        on: bool,
+        source: Source,
+        origin: Option<WriteOrigin>,
+    ) -> bool {
+        if self.power == on {
+            log::debug!(
+                "STATE already {} ({})",
+                SwitchState::from_bool(on).as_str(),
+                source
+            );
+            return false;
+        }
+        self.power = on;
+        self.last_changed = Instant::now();
+        self.last_source = source;
+        self.last_origin = origin;
+        self.changes.send_replace(SwitchState::from_bool(on));
+        if let Some((history, name)) = &self.history {
+            history.record(Transition {
+                at: SystemTime::now(),
+                switch: name.clone(),
+                old: SwitchState::from_bool(!on),
+                new: SwitchState::from_bool(on),
+                source,
+            });
+        }
+        self.notify_observers(
+            SwitchState::from_bool(!on),
+            SwitchState::from_bool(on),
+            source,
+        );
+        if on {
+            log::info!("STATE changed to: ON ✅ ({})", source);
+        } else {
+            log::info!("STATE changed to: OFF ❌ ({})", source);
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn origin() -> WriteOrigin {
+        WriteOrigin {
+            central: CentralId("central".to_string()),
+            characteristic: Uuid::from_u128(0x1001),
+        }
+    }
+
+    #[test]
+    fn starts_in_the_initial_state() {
+        let state = DeviceState::new(SwitchState::On);
+        assert!(state.power());
+        assert_eq!(state.switch_state(), SwitchState::On);
+        assert_eq!(state.brightness(), DEFAULT_BRIGHTNESS);
+        assert_eq!(state.last_source(), Source::Initial);
+        assert!(state.last_origin().is_none());
+    }
+
+    #[test]
+    fn set_power_reports_and_records_changes() {
+        let mut state = DeviceState::new(SwitchState::Off);
+        let mut changes = state.subscribe();
+        assert!(state.set_power_from(true, Source::BleWrite, Some(origin())));
+        assert_eq!(state.switch_state(), SwitchState::On);
+        assert_eq!(state.last_source(), Source::BleWrite);
+        assert_eq!(state.last_origin(), Some(&origin()));
+        assert!(changes.has_changed().unwrap());
+        assert_eq!(*changes.borrow_and_update(), SwitchState::On);
+
+        assert!(state.set_power(false, Source::Stdin));
+        assert!(!state.power());
+        assert_eq!(state.last_source(), Source::Stdin);
+        assert!(state.last_origin().is_none());
+    }
+
+    #[test]
+    fn setting_the_same_power_changes_nothing() {
+        let mut state = DeviceState::new(SwitchState::On);
+        let changes = state.subscribe();
+        let last_changed = state.last_changed();
+        assert!(!state.set_power(true, Source::Timer));
+        assert_eq!(state.last_source(), Source::Initial);
+        assert_eq!(state.last_changed(), last_changed);
+        assert!(!changes.has_changed().unwrap());
+    }
+
+    #[test]
+    fn only_changes_are_recorded_in_history() {
+        let history = History::new(10);
+        let mut state = DeviceState::new(SwitchState::Off);
+        state.record_history(history.clone(), "lamp");
+        state.set_power(true, Source::Stdin);
+        state.set_power(true, Source::Stdin);
+        state.set_power(false, Source::Timer);
+        let recent = history.recent(10);
+        assert_eq!(recent.len(), 2);
+        assert_eq!(recent[0].switch, "lamp");
+        assert_eq!(
+            (recent[0].old, recent[0].new, recent[0].source),
+            (SwitchState::Off, SwitchState::On, Source::Stdin)
+        );
+        assert_eq!(
+            (recent[1].old, recent[1].new, recent[1].source),
+            (SwitchState::On, SwitchState::Off, Source::Timer)
+        );
+    }
+
+    #[test]
+    fn set_brightness_reports_changes() {
+        let mut state = DeviceState::new(SwitchState::Off);
+        assert!(state.set_brightness(10, Source::Api));
+        assert_eq!(state.brightness(), 10);
+        assert!(!state.set_brightness(10, Source::Api));
+    }
+
+    #[tokio::test]
+    async fn observers_get_the_old_and_new_state_and_the_source() {
+        let (sender, mut changes) = tokio::sync::mpsc::unbounded_channel();
+        let mut state = DeviceState::new(SwitchState::Off);
+        state.on_change(move |old, new, source| {
+            let sender = sender.clone();
+            async move {
+                let _ = sender.send((old, new, source));
+            }
+        });
+
+        state.set_power(true, Source::BleWrite);
+        state.set_power(true, Source::BleWrite);
+        state.set_power(false, Source::Timer);
+
+        assert_eq!(
+            changes.recv().await,
+            Some((SwitchState::Off, SwitchState::On, Source::BleWrite))
+        );
+        assert_eq!(
+            changes.recv().await,
+            Some((SwitchState::On, SwitchState::Off, Source::Timer))
+        );
+        drop(state);
+        assert_eq!(changes.recv().await, None);
+    }
+
+    #[tokio::test]
+    async fn a_panicking_observer_leaves_later_changes_reported() {
+        let (sender, mut changes) = tokio::sync::mpsc::unbounded_channel();
+        let mut state = DeviceState::new(SwitchState::Off);
+        state.on_change(move |_, new, _| {
+            let sender = sender.clone();
+            async move {
+                if new == SwitchState::On {
+                    panic!("observer failed");
+                }
+                let _ = sender.send(new);
+            }
+        });
+
+        state.set_power(true, Source::Api);
+        tokio::task::yield_now().await;
+        state.set_power(false, Source::Api);
+
+        assert_eq!(changes.recv().await, Some(SwitchState::Off));
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+
+/// Named states and the transitions allowed between them.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransitionTable {
+    states: Vec<String>,
+    initial: usize,
+    allowed: BTreeMap<usize, BTreeSet<usize>>,
+}
+
+/// Why a transition request was refused.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TransitionError {
+    /// The name or opcode matches no state.
+    UnknownState(String),
+    /// The table has no edge from the current state to the requested one.
+    NotAllowed { from: String, to: String },
+}
+
+impl fmt::Display for TransitionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TransitionError::UnknownState(name) => write!(f, "unknown state '{}'", name),
+            TransitionError::NotAllowed { from, to } => {
+                write!(f, "transition {} -> {} is not allowed", from, to)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TransitionError {}
+
+impl TransitionTable {
+    /// Builds a table from state names, the initial state and, for each
+    /// state, the states it may move to. Every name must be listed in
+    /// `states`, and the first state is the initial one when `initial` is
+    /// `None`.
+    pub fn new(
+        states: Vec<String>,
+        initial: Option<&str>,
+        transitions: &BTreeMap<String, Vec<String>>,
+    ) -> Result<Self, String> {
+        if states.is_empty() {
+            return Err("at least one state is required".to_string());
+        }
+        let mut seen = BTreeSet::new();
+        for state in &states {
+            if !seen.insert(state.to_ascii_lowercase()) {
+                return Err(format!("state '{}' is listed twice", state));
+            }
+        }
+        let index = |name: &str| {
+            states
+                .iter()
+                .position(|state| state.eq_ignore_ascii_case(name))
+                .ok_or_else(|| format!("'{}' is not one of the listed states", name))
+        };
+        let initial = match initial {
+            Some(name) => index(name)?,
+            None => 0,
+        };
+        let mut allowed: BTreeMap<usize, BTreeSet<usize>> = BTreeMap::new();
+        for (from, targets) in transitions {
+            let from = index(from)?;
+            for to in targets {
+                allowed.entry(from).or_default().insert(index(to)?);
+            }
+        }
+        Ok(Self {
+            states,
+            initial,
+            allowed,
+        })
+    }
+
+    pub fn states(&self) -> &[String] {
+        &self.states
+    }
+
+    pub fn initial(&self) -> &str {
+        &self.states[self.initial]
+    }
+
+    /// The states `from` may move to, in table order.
+    pub fn targets(&self, from: &str) -> Vec<&str> {
+        let Some(from) = self.index(from) else {
+            return Vec::new();
+        };
+        self.allowed
+            .get(&from)
+            .into_iter()
+            .flatten()
+            .map(|&to| self.states[to].as_str())
+            .collect()
+    }
+
+    /// Resolves a written value: a state name, case-insensitively, or a
+    /// single byte giving the state's position in the table.
+    pub fn resolve(&self, value: &[u8]) -> Result<&str, TransitionError> {
+        if let Ok(name) = std::str::from_utf8(value) {
+            if let Some(index) = self.index(name.trim()) {
+                return Ok(&self.states[index]);
+            }
+        }
+        match value {
+            [opcode] if (*opcode as usize) < self.states.len() => {
+                Ok(&self.states[*opcode as usize])
+            }
+            _ => Err(TransitionError::UnknownState(
+                String::from_utf8_lossy(value).into_owned(),
+            )),
+        }
+    }
+
+    pub fn is_allowed(&self, from: &str, to: &str) -> bool {
+        match (self.index(from), self.index(to)) {
+            (Some(from), Some(to)) => self
+                .allowed
+                .get(&from)
+                .is_some_and(|targets| targets.contains(&to)),
+            _ => false,
+        }
+    }
+
+    fn index(&self, name: &str) -> Option<usize> {
+        self.states
+            .iter()
+            .position(|state| state.eq_ignore_ascii_case(name))
+    }
+}
+
+impl Default for TransitionTable {
+    /// `Idle`, `Running`, `Error` and `Maintenance`, where `Running` can fail
+    /// into `Error` and only `Maintenance` leads back out of it.
+    fn default() -> Self {
+        let transitions = BTreeMap::from([
+            (
+                "Idle".to_string(),
+                vec!["Running".to_string(), "Maintenance".to_string()],
+            ),
+            (
+                "Running".to_string(),
+                vec!["Idle".to_string(), "Error".to_string()],
+            ),
+            ("Error".to_string(), vec!["Maintenance".to_string()]),
+            ("Maintenance".to_string(), vec!["Idle".to_string()]),
+        ]);
+        let states = ["Idle", "Running", "Error", "Maintenance"]
+            .map(String::from)
+            .to_vec();
+        Self::new(states, None, &transitions).expect("the default table is valid")
+    }
+}
+
+/// A state machine that only moves along its table's transitions.
+#[derive(Debug, Clone)]
+pub struct StateMachine {
+    table: TransitionTable,
+    current: usize,
+}
+
+impl StateMachine {
+    pub fn new(table: TransitionTable) -> Self {
+        let current = table.initial;
+        Self { table, current }
+    }
+
+    pub fn table(&self) -> &TransitionTable {
+        &self.table
+    }
+
+    pub fn current(&self) -> &str {
+        &self.table.states[self.current]
+    }
+
+    /// Swaps in `table`, staying in the current state if it has one of that
+    /// name and starting over in its initial state otherwise. Returns
+    /// whether anything changed.
+    pub fn set_table(&mut self, table: TransitionTable) -> bool {
+        if table == self.table {
+            return false;
+        }
+        self.current = table.index(self.current()).unwrap_or(table.initial);
+        self.table = table;
+        true
+    }
+
+    /// Moves to the state `value` names, returning the new state's name.
+    /// Refused requests leave the state untouched.
+    pub fn request(&mut self, value: &[u8]) -> Result<&str, TransitionError> {
+        let target = self.table.resolve(value)?;
+        if !self.table.is_allowed(self.current(), target) {
+            return Err(TransitionError::NotAllowed {
+                from: self.current().to_string(),
+                to: target.to_string(),
+            });
+        }
+        self.current = self.table.index(target).unwrap_or(self.current);
+        Ok(self.current())
+    }
+}
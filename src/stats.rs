@@ -0,0 +1,158 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, OnceLock};
+use std::time::SystemTime;
+
+use serde::Serialize;
+
+use crate::event_queue::ChannelStats;
+use crate::snapshot::unix_secs;
+
+/// Counts of what the peripheral has handled since startup or the last
+/// `reset`, shared between the event loop and the console.
+#[derive(Debug, Default)]
+pub struct EventStats {
+    reads: AtomicU64,
+    writes_accepted: AtomicU64,
+    writes_rejected: AtomicU64,
+    notifications: AtomicU64,
+    subscription_changes: AtomicU64,
+    power_changes: AtomicU64,
+    /// Events nothing handles, by variant name.
+    unhandled: Mutex<BTreeMap<String, u64>>,
+    channel: OnceLock<ChannelStats>,
+}
+
+/// The counters of `EventStats` at one point in time.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct StatsSnapshot {
+    pub reads: u64,
+    pub writes_accepted: u64,
+    pub writes_rejected: u64,
+    pub notifications: u64,
+    pub subscription_changes: u64,
+    pub power_changes: u64,
+    pub unhandled: BTreeMap<String, u64>,
+    /// Events the backend sent and, of those, the ones the overflow policy
+    /// discarded, since startup; `reset` leaves them alone.
+    pub events_emitted: u64,
+    pub dropped_events: u64,
+    /// Unix time of the last dropped event, in seconds.
+    pub last_drop_at: Option<u64>,
+}
+
+impl EventStats {
+    pub fn record_read(&self) {
+        self.reads.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts a write request or command, by whether the profile took it.
+    pub fn record_write(&self, accepted: bool) {
+        let counter = if accepted {
+            &self.writes_accepted
+        } else {
+            &self.writes_rejected
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_notification(&self) {
+        self.notifications.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_subscription_change(&self) {
+        self.subscription_changes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn record_power_change(&self) {
+        self.power_changes.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Counts an event nothing handles under its variant name, taken from
+    /// its `Debug` output.
+    pub fn record_unhandled(&self, event: &impl fmt::Debug) {
+        let debug = format!("{:?}", event);
+        let variant = debug
+            .split(|c: char| !c.is_alphanumeric() && c != '_')
+            .next()
+            .unwrap_or_default();
+        *self
+            .unhandled
+            .lock()
+            .unwrap()
+            .entry(variant.to_string())
+            .or_default() += 1;
+    }
+
+    /// Includes the event channel's counters in snapshots. Only the first
+    /// call has an effect.
+    pub fn watch_channel(&self, channel: ChannelStats) {
+        let _ = self.channel.set(channel);
+    }
+
+    pub fn snapshot(&self) -> StatsSnapshot {
+        StatsSnapshot {
+            reads: self.reads.load(Ordering::Relaxed),
+            writes_accepted: self.writes_accepted.load(Ordering::Relaxed),
+            writes_rejected: self.writes_rejected.load(Ordering::Relaxed),
+            notifications: self.notifications.load(Ordering::Relaxed),
+            subscription_changes: self.subscription_changes.load(Ordering::Relaxed),
+            power_changes: self.power_changes.load(Ordering::Relaxed),
+            unhandled: self.unhandled.lock().unwrap().clone(),
+            events_emitted: self.channel.get().map_or(0, ChannelStats::emitted),
+            dropped_events: self.channel.get().map_or(0, ChannelStats::dropped),
+            last_drop_at: self
+                .channel
+                .get()
+                .and_then(ChannelStats::last_drop)
+                .map(unix_secs),
+        }
+    }
+
+    /// Sets every counter back to zero.
+    pub fn reset(&self) {
+        for counter in [
+            &self.reads,
+            &self.writes_accepted,
+            &self.writes_rejected,
+            &self.notifications,
+            &self.subscription_changes,
+            &self.power_changes,
+        ] {
+            counter.store(0, Ordering::Relaxed);
+        }
+        self.unhandled.lock().unwrap().clear();
+    }
+}
+
+impl fmt::Display for StatsSnapshot {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Read requests: {}", self.reads)?;
+        writeln!(
+            f,
+            "Write requests: {} accepted, {} rejected",
+            self.writes_accepted, self.writes_rejected
+        )?;
+        writeln!(f, "Notifications sent: {}", self.notifications)?;
+        writeln!(f, "Subscription changes: {}", self.subscription_changes)?;
+        writeln!(f, "Power state changes: {}", self.power_changes)?;
+        writeln!(
+            f,
+            "Events from the backend: {}, dropped on overflow: {}",
+            self.events_emitted, self.dropped_events
+        )?;
+        if let Some(at) = self.last_drop_at {
+            let ago = unix_secs(SystemTime::now()).saturating_sub(at);
+            writeln!(f, "Last event dropped {}s ago", ago)?;
+        }
+        if self.unhandled.is_empty() {
+            return writeln!(f, "Unhandled events: none");
+        }
+        writeln!(f, "Unhandled events:")?;
+        for (variant, count) in &self.unhandled {
+            writeln!(f, "  {}: {}", variant, count)?;
+        }
+        Ok(())
+    }
+}
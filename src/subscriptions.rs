@@ -0,0 +1,126 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+
+use uuid::Uuid;
+
+use crate::central::CentralId;
+
+/// Which centrals are subscribed to which characteristics, and since when.
+///
+/// Clones share the same table, so a task can keep one to check for
+/// listeners, e.g. before a heartbeat, without locking the `Device` that
+/// updates it from `CharacteristicSubscriptionUpdate` events.
+#[derive(Debug, Clone, Default)]
+pub struct SubscriptionRegistry {
+    by_characteristic: Arc<RwLock<HashMap<Uuid, HashMap<CentralId, SystemTime>>>>,
+}
+
+impl SubscriptionRegistry {
+    /// Records a subscribe or unsubscribe. Subscribing again keeps the
+    /// original time.
+    pub fn update(&self, central: CentralId, characteristic: Uuid, subscribed: bool) {
+        let mut by_characteristic = self.by_characteristic.write().unwrap();
+        if subscribed {
+            by_characteristic
+                .entry(characteristic)
+                .or_default()
+                .entry(central)
+                .or_insert_with(SystemTime::now);
+        } else if let Some(centrals) = by_characteristic.get_mut(&characteristic) {
+            centrals.remove(&central);
+            if centrals.is_empty() {
+                by_characteristic.remove(&characteristic);
+            }
+        }
+    }
+
+    /// Drops every subscription held by `central`.
+    pub fn remove_central(&self, central: &CentralId) {
+        self.by_characteristic
+            .write()
+            .unwrap()
+            .retain(|_, centrals| {
+                centrals.remove(central);
+                !centrals.is_empty()
+            });
+    }
+
+    /// Drops every subscription to `characteristic`.
+    pub fn remove_characteristic(&self, characteristic: Uuid) {
+        self.by_characteristic
+            .write()
+            .unwrap()
+            .remove(&characteristic);
+    }
+
+    /// Whether any central is subscribed to `characteristic`.
+    pub fn is_subscribed(&self, characteristic: Uuid) -> bool {
+        self.subscriber_count(characteristic) > 0
+    }
+
+    pub fn is_central_subscribed(&self, central: &CentralId, characteristic: Uuid) -> bool {
+        self.subscribed_since(central, characteristic).is_some()
+    }
+
+    /// When `central` subscribed to `characteristic`, if it is subscribed.
+    pub fn subscribed_since(
+        &self,
+        central: &CentralId,
+        characteristic: Uuid,
+    ) -> Option<SystemTime> {
+        self.by_characteristic
+            .read()
+            .unwrap()
+            .get(&characteristic)?
+            .get(central)
+            .copied()
+    }
+
+    pub fn subscribers(&self, characteristic: Uuid) -> Vec<CentralId> {
+        self.by_characteristic
+            .read()
+            .unwrap()
+            .get(&characteristic)
+            .map_or_else(Vec::new, |centrals| centrals.keys().cloned().collect())
+    }
+
+    pub fn subscriber_count(&self, characteristic: Uuid) -> usize {
+        self.by_characteristic
+            .read()
+            .unwrap()
+            .get(&characteristic)
+            .map_or(0, HashMap::len)
+    }
+
+    /// Every `(central, characteristic, since)` subscription, oldest first.
+    pub fn entries(&self) -> Vec<(CentralId, Uuid, SystemTime)> {
+        let mut entries: Vec<_> = self
+            .by_characteristic
+            .read()
+            .unwrap()
+            .iter()
+            .flat_map(|(&characteristic, centrals)| {
+                centrals
+                    .iter()
+                    .map(move |(central, &since)| (central.clone(), characteristic, since))
+            })
+            .collect();
+        entries.sort_by(|a, b| (a.2, &a.0, a.1).cmp(&(b.2, &b.0, b.1)));
+        entries
+    }
+
+    /// Number of subscriptions across all characteristics.
+    pub fn len(&self) -> usize {
+        self.by_characteristic
+            .read()
+            .unwrap()
+            .values()
+            .map(HashMap::len)
+            .sum()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.by_characteristic.read().unwrap().is_empty()
+    }
+}
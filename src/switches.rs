@@ -0,0 +1,212 @@
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use tokio::time::Instant;
+use uuid::Uuid;
+
+use crate::aliases::Aliases;
+use crate::debounce::NotifyDebouncer;
+use crate::history::History;
+use crate::profile::switch::{StatusReads, SwitchState};
+use crate::state::{DeviceState, SharedState};
+
+/// Name of the switch used when no `--switch` is given.
+pub const DEFAULT_SWITCH_NAME: &str = "switch";
+
+/// How a mirror characteristic spells the switch state.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    /// ASCII `"on"` or `"off"`, like the switch characteristic itself.
+    Text,
+    /// A single `0x01` or `0x00` byte, for firmware clients.
+    Byte,
+}
+
+impl Encoding {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "text" => Some(Encoding::Text),
+            "byte" => Some(Encoding::Byte),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Text => "text",
+            Encoding::Byte => "byte",
+        }
+    }
+
+    pub fn encode(self, state: SwitchState) -> Vec<u8> {
+        match self {
+            Encoding::Text => state.as_str().into(),
+            Encoding::Byte => vec![state.is_on() as u8],
+        }
+    }
+}
+
+/// An extra characteristic that carries a switch's state in its own
+/// encoding and is updated whenever the switch changes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mirror {
+    pub switch: String,
+    pub characteristic: Uuid,
+    pub encoding: Encoding,
+}
+
+/// One independently controllable channel and the characteristic it lives on.
+#[derive(Debug)]
+pub struct Switch {
+    pub name: String,
+    pub characteristic: Uuid,
+    pub state: SharedState,
+    /// Coalesces this switch's notifications, when `--notify-debounce-ms` is set.
+    pub debounce: Option<Arc<NotifyDebouncer>>,
+    /// Other characteristics updated with every state change.
+    pub mirrors: Vec<Mirror>,
+}
+
+/// Every switch the peripheral exposes. The first one is the primary switch
+/// that plain `on`/`off` on the console controls.
+#[derive(Debug)]
+pub struct SwitchBank {
+    switches: Vec<Switch>,
+    started: Instant,
+    strict_writes: bool,
+    debounce: Option<Arc<NotifyDebouncer>>,
+    status_reads: StatusReads,
+    /// Words written or typed for a state besides `on` and `off`. A reload
+    /// replaces them while the bank is shared.
+    aliases: RwLock<Aliases>,
+}
+
+impl SwitchBank {
+    /// Creates one switch per `(name, characteristic)` pair, all starting in
+    /// `initial` and recording their changes in `history`. An empty list
+    /// yields a single default switch on the nil UUID, so callers should
+    /// always pass at least one channel.
+    pub fn new(channels: &[(String, Uuid)], initial: SwitchState, history: &History) -> Self {
+        let default_channel = [(DEFAULT_SWITCH_NAME.to_string(), Uuid::nil())];
+        let channels = if channels.is_empty() {
+            &default_channel[..]
+        } else {
+            channels
+        };
+        let switches = channels
+            .iter()
+            .map(|(name, characteristic)| {
+                let mut state = DeviceState::new(initial);
+                state.record_history(history.clone(), name.clone());
+                Switch {
+                    name: name.clone(),
+                    characteristic: *characteristic,
+                    state: Arc::new(RwLock::new(state)),
+                    debounce: None,
+                    mirrors: Vec::new(),
+                }
+            })
+            .collect();
+        Self {
+            switches,
+            started: Instant::now(),
+            strict_writes: false,
+            debounce: None,
+            status_reads: StatusReads::default(),
+            aliases: RwLock::default(),
+        }
+    }
+
+    /// Rejects writes that are not a known command instead of echoing them.
+    pub fn with_strict_writes(mut self, strict: bool) -> Self {
+        self.strict_writes = strict;
+        self
+    }
+
+    pub fn strict_writes(&self) -> bool {
+        self.strict_writes
+    }
+
+    /// Accepts `aliases` for the switch states, in writes and on the console.
+    pub fn with_aliases(self, aliases: Aliases) -> Self {
+        self.set_aliases(aliases);
+        self
+    }
+
+    /// Replaces the aliases, e.g. with those of a reloaded config.
+    pub fn set_aliases(&self, aliases: Aliases) {
+        *self.aliases.write().unwrap() = aliases;
+    }
+
+    pub fn aliases(&self) -> Aliases {
+        self.aliases.read().unwrap().clone()
+    }
+
+    /// Coalesces notifications of every switch characteristic, and of the
+    /// brightness, within `window`. `None` notifies on every change.
+    pub fn with_notify_debounce(mut self, window: Option<Duration>) -> Self {
+        self.debounce = window.map(|window| Arc::new(NotifyDebouncer::new(window)));
+        for switch in &mut self.switches {
+            switch.debounce = self.debounce.clone();
+        }
+        self
+    }
+
+    /// Attaches each mirror to the switch it names. A mirror naming an
+    /// unknown switch is an error.
+    pub fn with_mirrors(mut self, mirrors: Vec<Mirror>) -> Result<Self, String> {
+        for mirror in mirrors {
+            let switch = self
+                .switches
+                .iter_mut()
+                .find(|switch| switch.name == mirror.switch)
+                .ok_or_else(|| {
+                    format!(
+                        "mirror {} names unknown switch '{}'",
+                        mirror.characteristic, mirror.switch
+                    )
+                })?;
+            switch.mirrors.push(mirror);
+        }
+        Ok(self)
+    }
+
+    /// Every mirror of every switch.
+    pub fn mirrors(&self) -> impl Iterator<Item = &Mirror> {
+        self.switches
+            .iter()
+            .flat_map(|switch| switch.mirrors.iter())
+    }
+
+    pub fn notify_debounce(&self) -> Option<&NotifyDebouncer> {
+        self.debounce.as_deref()
+    }
+
+    /// Time since the switches were created, i.e. since startup.
+    /// The status documents being read in chunks.
+    pub fn status_reads(&self) -> &StatusReads {
+        &self.status_reads
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    pub fn primary(&self) -> &Switch {
+        &self.switches[0]
+    }
+
+    pub fn by_name(&self, name: &str) -> Option<&Switch> {
+        self.switches.iter().find(|switch| switch.name == name)
+    }
+
+    pub fn by_characteristic(&self, uuid: Uuid) -> Option<&Switch> {
+        self.switches
+            .iter()
+            .find(|switch| switch.characteristic == uuid)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Switch> {
+        self.switches.iter()
+    }
+}
@@ -0,0 +1,155 @@
+use std::collections::HashSet;
+use std::fmt;
+
+use uuid::Uuid;
+
+use ble_peripheral_rust::{
+    gatt::{
+        properties::{AttributePermission, CharacteristicProperty},
+        service::Service,
+    },
+    uuid::ShortUuid,
+};
+
+use crate::builder::CCCD_UUID;
+
+/// Largest attribute value allowed by the ATT protocol.
+pub const DEFAULT_MAX_ATTRIBUTE_LENGTH: usize = 512;
+
+/// Server Characteristic Configuration Descriptor, managed by the stack like the CCCD.
+const SCCD_UUID: u16 = 0x2903;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Issue {
+    pub location: String,
+    pub message: String,
+}
+
+impl fmt::Display for Issue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.location, self.message)
+    }
+}
+
+/// Checks a service tree for problems the backend would reject or misbehave on.
+///
+/// A notify or indicate characteristic without a CCCD is fine:
+/// `Device::add_service` adds one.
+pub fn validate_services(services: &[Service], max_attribute_length: usize) -> Vec<Issue> {
+    let stack_managed = [Uuid::from_short(CCCD_UUID), Uuid::from_short(SCCD_UUID)];
+    let mut issues = Vec::new();
+    let mut issue = |location: String, message: String| issues.push(Issue { location, message });
+    let mut seen_services = HashSet::new();
+
+    for (service_index, service) in services.iter().enumerate() {
+        if !seen_services.insert(service.uuid) {
+            issue(
+                format!("services[{}] ({})", service_index, service.uuid),
+                "duplicate service UUID".to_string(),
+            );
+        }
+        let mut seen = HashSet::new();
+        for (char_index, characteristic) in service.characteristics.iter().enumerate() {
+            let location = format!(
+                "services[{}].characteristics[{}] ({})",
+                service_index, char_index, characteristic.uuid
+            );
+
+            if !seen.insert(characteristic.uuid) {
+                issue(
+                    location.clone(),
+                    format!("duplicate characteristic UUID in service {}", service.uuid),
+                );
+            }
+            if characteristic
+                .properties
+                .contains(&CharacteristicProperty::Notify)
+                && !characteristic.permissions.iter().any(|permission| {
+                    matches!(
+                        permission,
+                        AttributePermission::Readable | AttributePermission::ReadEncryptionRequired
+                    )
+                })
+            {
+                issue(
+                    location.clone(),
+                    "notify property without readable permission".to_string(),
+                );
+            }
+            if let Some(value) = &characteristic.value {
+                if value.len() > max_attribute_length {
+                    issue(
+                        location.clone(),
+                        format!(
+                            "value is {} bytes, longer than the maximum of {}",
+                            value.len(),
+                            max_attribute_length
+                        ),
+                    );
+                }
+            }
+
+            for (desc_index, descriptor) in characteristic.descriptors.iter().enumerate() {
+                let location = format!(
+                    "{}.descriptors[{}] ({})",
+                    location, desc_index, descriptor.uuid
+                );
+                let value = descriptor.value.as_deref().unwrap_or_default();
+                if value.is_empty() && !stack_managed.contains(&descriptor.uuid) {
+                    issue(location.clone(), "descriptor has no value".to_string());
+                }
+                if descriptor.uuid != stack_managed[0]
+                    && descriptor.permissions.iter().any(is_write_permission)
+                {
+                    issue(
+                        location.clone(),
+                        "writable descriptors are not supported by the backend".to_string(),
+                    );
+                }
+                if value.len() > max_attribute_length {
+                    issue(
+                        location,
+                        format!(
+                            "value is {} bytes, longer than the maximum of {}",
+                            value.len(),
+                            max_attribute_length
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    issues
+}
+
+pub(crate) fn is_write_permission(permission: &AttributePermission) -> bool {
+    matches!(
+        permission,
+        AttributePermission::Writeable | AttributePermission::WriteEncryptionRequired
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use ble_peripheral_rust::gatt::characteristic::Characteristic;
+
+    use super::*;
+
+    #[test]
+    fn notify_without_declared_cccd_is_valid() {
+        let service = Service {
+            uuid: Uuid::from_u128(0x1000),
+            primary: true,
+            characteristics: vec![Characteristic {
+                uuid: Uuid::from_u128(0x1001),
+                properties: vec![CharacteristicProperty::Notify],
+                permissions: vec![AttributePermission::Readable],
+                value: None,
+                descriptors: Vec::new(),
+            }],
+        };
+        let issues = validate_services(&[service], DEFAULT_MAX_ATTRIBUTE_LENGTH);
+        assert!(issues.is_empty(), "{:?}", issues);
+    }
+}